@@ -11,11 +11,13 @@ use clap::Command;
 use clap::ValueHint;
 use deno_config::ConfigFlag;
 use deno_core::resolve_url_or_path;
+use deno_core::serde_json;
 use deno_core::url::Url;
 use deno_graph::GraphKind;
 use deno_runtime::permissions::parse_sys_kind;
 use log::debug;
 use log::Level;
+use std::collections::HashMap;
 use std::env;
 use std::net::SocketAddr;
 use std::num::NonZeroU32;
@@ -447,6 +449,10 @@ pub struct Flags {
   /// This is not exposed as an option in the CLI, it is used internally when
   /// the language server is configured with an explicit cache option.
   pub cache_path: Option<PathBuf>,
+  /// Identifiers, or dotted member expressions like `process.env.NODE_ENV`,
+  /// replaced by a JSON value when transpiling. This is not exposed as an
+  /// option in the CLI, it is used by embedders.
+  pub define: HashMap<String, serde_json::Value>,
   pub cached_only: bool,
   pub type_check_mode: TypeCheckMode,
   pub config_flag: ConfigFlag,
@@ -1332,6 +1332,10 @@ impl CliOptions {
     }
   }
 
+  pub fn define(&self) -> &HashMap<String, serde_json::Value> {
+    &self.flags.define
+  }
+
   pub fn enable_op_summary_metrics(&self) -> bool {
     self.flags.enable_op_summary_metrics
       || matches!(
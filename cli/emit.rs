@@ -4,19 +4,34 @@ use crate::cache::EmitCache;
 use crate::cache::FastInsecureHasher;
 use crate::cache::ParsedSourceCache;
 
+use deno_ast::swc::ast as swc_ast;
+use deno_ast::swc::common::SyntaxContext;
+use deno_ast::swc::visit::noop_visit_type;
+use deno_ast::swc::visit::Visit;
+use deno_ast::swc::visit::VisitWith;
+use deno_ast::ParsedSource;
+use deno_ast::SourceRange;
+use deno_ast::SourceRangedForSpanned;
+use deno_ast::TranspiledSource;
 use deno_core::error::AnyError;
+use deno_core::serde_json;
 use deno_core::ModuleCodeString;
 use deno_core::ModuleSpecifier;
 use deno_graph::MediaType;
 use deno_graph::Module;
 use deno_graph::ModuleGraph;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::sync::Arc;
 
 pub struct Emitter {
   emit_cache: EmitCache,
   parsed_source_cache: Arc<ParsedSourceCache>,
   emit_options: deno_ast::EmitOptions,
-  // cached hash of the emit options
+  /// Expressions replaced when transpiling, mapped to the source text of
+  /// their replacement.
+  define: BTreeMap<String, String>,
+  // cached hash of the emit options and defines
   emit_options_hash: u64,
 }
 
@@ -25,16 +40,31 @@ impl Emitter {
     emit_cache: EmitCache,
     parsed_source_cache: Arc<ParsedSourceCache>,
     emit_options: deno_ast::EmitOptions,
+    define: &HashMap<String, serde_json::Value>,
   ) -> Self {
-    let emit_options_hash = FastInsecureHasher::hash(&emit_options);
+    let define = define
+      .iter()
+      .map(|(name, value)| (name.clone(), format!("({value})")))
+      .collect::<BTreeMap<_, _>>();
+    let emit_options_hash = FastInsecureHasher::new()
+      .write_hashable(&emit_options)
+      .write_hashable(&define)
+      .finish();
     Self {
       emit_cache,
       parsed_source_cache,
       emit_options,
+      define,
       emit_options_hash,
     }
   }
 
+  /// Whether expressions are replaced when transpiling, which applies to
+  /// JavaScript modules too.
+  pub fn has_defines(&self) -> bool {
+    !self.define.is_empty()
+  }
+
   pub fn cache_module_emits(
     &self,
     graph: &ModuleGraph,
@@ -90,7 +120,8 @@ impl Emitter {
         source.clone(),
         media_type,
       )?;
-      let transpiled_source = parsed_source.transpile(&self.emit_options)?;
+      let transpiled_source =
+        self.transpile(&parsed_source, &self.emit_options)?;
       debug_assert!(transpiled_source.source_map.is_none());
       self.emit_cache.set_emit_code(
         specifier,
@@ -117,10 +148,48 @@ impl Emitter {
       .get_or_parse_module(specifier, source_arc, media_type)?;
     let mut options = self.emit_options.clone();
     options.inline_source_map = false;
-    let transpiled_source = parsed_source.transpile(&options)?;
+    let transpiled_source = self.transpile(&parsed_source, &options)?;
     Ok(transpiled_source.text)
   }
 
+  /// Transpiles the source, replacing the defined expressions first.
+  fn transpile(
+    &self,
+    parsed_source: &ParsedSource,
+    options: &deno_ast::EmitOptions,
+  ) -> Result<TranspiledSource, AnyError> {
+    if self.define.is_empty() {
+      return parsed_source.transpile(options);
+    }
+    let replacements = collect_define_replacements(parsed_source, &self.define);
+    if replacements.is_empty() {
+      return parsed_source.transpile(options);
+    }
+
+    let text_info = parsed_source.text_info();
+    let text = text_info.text_str();
+    let start = text_info.range().start;
+    let mut replaced = String::with_capacity(text.len());
+    let mut last_end = 0;
+    for (range, replacement) in replacements {
+      let range = range.as_byte_range(start);
+      replaced.push_str(&text[last_end..range.start]);
+      replaced.push_str(&replacement);
+      last_end = range.end;
+    }
+    replaced.push_str(&text[last_end..]);
+
+    let parsed_source = deno_ast::parse_module(deno_ast::ParseParams {
+      specifier: parsed_source.specifier().to_string(),
+      text_info: deno_ast::SourceTextInfo::from_string(replaced),
+      media_type: parsed_source.media_type(),
+      capture_tokens: false,
+      maybe_syntax: None,
+      scope_analysis: false,
+    })?;
+    parsed_source.transpile(options)
+  }
+
   /// A hashing function that takes the source code and uses the global emit
   /// options then generates a string hash which can be stored to
   /// determine if the cached emit is valid or not.
@@ -131,3 +200,121 @@ impl Emitter {
       .finish()
   }
 }
+
+/// Returns the expressions of `parsed_source` to replace for `define`, in
+/// source order. Only references to globals are replaced, not ones to local
+/// bindings that shadow them.
+fn collect_define_replacements(
+  parsed_source: &ParsedSource,
+  define: &BTreeMap<String, String>,
+) -> Vec<(SourceRange, String)> {
+  let parsed_source = if parsed_source.has_scope_analysis() {
+    parsed_source.clone()
+  } else {
+    parsed_source.clone().into_with_scope_analysis()
+  };
+  let mut collector = DefineCollector {
+    define,
+    unresolved_context: parsed_source.unresolved_context(),
+    replacements: Vec::new(),
+  };
+  parsed_source.module().visit_with(&mut collector);
+  collector.replacements
+}
+
+struct DefineCollector<'a> {
+  define: &'a BTreeMap<String, String>,
+  /// Syntax context of references that don't resolve to a local binding.
+  unresolved_context: SyntaxContext,
+  replacements: Vec<(SourceRange, String)>,
+}
+
+impl Visit for DefineCollector<'_> {
+  noop_visit_type!();
+
+  fn visit_expr(&mut self, expr: &swc_ast::Expr) {
+    if let Some(replacement) = define_name(expr, self.unresolved_context)
+      .and_then(|name| self.define.get(&name))
+    {
+      self.replacements.push((expr.range(), replacement.clone()));
+      return;
+    }
+    expr.visit_children_with(self);
+  }
+
+  fn visit_prop(&mut self, prop: &swc_ast::Prop) {
+    if let swc_ast::Prop::Shorthand(ident) = prop {
+      let replacement = if ident.span.ctxt == self.unresolved_context {
+        self.define.get(ident.sym.as_ref())
+      } else {
+        None
+      };
+      if let Some(replacement) = replacement {
+        self
+          .replacements
+          .push((ident.range(), format!("{}: {}", ident.sym, replacement)));
+        return;
+      }
+    }
+    prop.visit_children_with(self);
+  }
+}
+
+/// Returns the dotted name of a global identifier or a chain of static
+/// member accesses on one, like `process.env.NODE_ENV`.
+fn define_name(
+  expr: &swc_ast::Expr,
+  unresolved_context: SyntaxContext,
+) -> Option<String> {
+  match expr {
+    swc_ast::Expr::Ident(ident) if ident.span.ctxt == unresolved_context => {
+      Some(ident.sym.to_string())
+    }
+    swc_ast::Expr::Member(member) => match &member.prop {
+      swc_ast::MemberProp::Ident(prop) => Some(format!(
+        "{}.{}",
+        define_name(&member.obj, unresolved_context)?,
+        prop.sym
+      )),
+      _ => None,
+    },
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn define_replaces_only_globals() {
+    let parsed_source = deno_ast::parse_module(deno_ast::ParseParams {
+      specifier: "file:///main.js".to_string(),
+      text_info: deno_ast::SourceTextInfo::from_string(
+        "console.log(DEBUG, { DEBUG }, process.env.NODE_ENV);
+function f(DEBUG) { return { DEBUG, value: DEBUG }; }
+const process = {};"
+          .to_string(),
+      ),
+      media_type: MediaType::JavaScript,
+      capture_tokens: false,
+      maybe_syntax: None,
+      scope_analysis: false,
+    })
+    .unwrap();
+    let define = BTreeMap::from([
+      ("DEBUG".to_string(), "true".to_string()),
+      (
+        "process.env.NODE_ENV".to_string(),
+        "\"production\"".to_string(),
+      ),
+    ]);
+
+    let replacements = collect_define_replacements(&parsed_source, &define)
+      .into_iter()
+      .map(|(_, replacement)| replacement)
+      .collect::<Vec<_>>();
+    // `process` is declared in the module, so it isn't replaced either
+    assert_eq!(replacements, vec!["true", "DEBUG: true"]);
+  }
+}
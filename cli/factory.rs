@@ -68,6 +68,7 @@ use import_map::ImportMap;
 use log::warn;
 use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
 
 pub struct CliFactoryBuilder {
   watcher_communicator: Option<Arc<WatcherCommunicator>>,
@@ -554,6 +555,7 @@ impl CliFactory {
         self.emit_cache()?.clone(),
         self.parsed_source_cache().clone(),
         emit_options,
+        self.options.define(),
       )))
     })
   }
@@ -732,6 +734,19 @@ impl CliFactory {
 
   pub async fn create_cli_main_worker_factory(
     &self,
+  ) -> Result<CliMainWorkerFactory, AnyError> {
+    self
+      .create_cli_main_worker_factory_with_options(
+        self.create_cli_main_worker_options()?,
+      )
+      .await
+  }
+
+  /// Like [`Self::create_cli_main_worker_factory`], but uses `options`
+  /// instead of the ones derived from the flags.
+  pub async fn create_cli_main_worker_factory_with_options(
+    &self,
+    options: CliMainWorkerOptions,
   ) -> Result<CliMainWorkerFactory, AnyError> {
     let node_resolver = self.node_resolver().await?;
     let npm_resolver = self.npm_resolver().await?;
@@ -771,14 +786,14 @@ impl CliFactory {
       self.maybe_inspector_server().clone(),
       self.maybe_lockfile().clone(),
       self.feature_checker().clone(),
-      self.create_cli_main_worker_options()?,
+      options,
       self.options.node_ipc_fd(),
       self.options.disable_deprecated_api_warning,
       self.options.verbose_deprecated_api_warning,
     ))
   }
 
-  fn create_cli_main_worker_options(
+  pub fn create_cli_main_worker_options(
     &self,
   ) -> Result<CliMainWorkerOptions, AnyError> {
     Ok(CliMainWorkerOptions {
@@ -795,7 +810,9 @@ impl CliFactory {
       hmr: self.options.has_hmr(),
       inspect_brk: self.options.inspect_brk().is_some(),
       inspect_wait: self.options.inspect_wait().is_some(),
+      inspect_wait_timeout: None,
       strace_ops: self.options.strace_ops().clone(),
+      max_ops_per_second: None,
       is_inspecting: self.options.is_inspecting(),
       is_npm_main: self.options.is_npm_main(),
       location: self.options.location_flag().clone(),
@@ -812,6 +829,57 @@ impl CliFactory {
         .clone(),
       unstable: self.options.legacy_unstable_flag(),
       maybe_root_package_json_deps: self.options.maybe_package_json_deps(),
+      permission_prompt_hook: None,
+      preload_modules: vec![],
+      max_module_count: None,
+      tee_output: None,
+      on_npm_package_missing: None,
+      locale_override: None,
+      max_eval_recursion: None,
+      enable_wasm_module_cache: true,
+      on_permission_revoked: None,
+      irreversible_permission_revocation: false,
+      deterministic_collections: false,
+      execution_deadline: None,
+      exit_code_mapper: None,
+      share_remote_modules: false,
+      max_concurrent_fetches: None,
+      lockfile_frozen: false,
+      resolution_referrer: None,
+      wasm_policy: Default::default(),
+      env_provider: None,
+      env_override: None,
+      allow_top_level_await: true,
+      propagate_worker_errors: false,
+      coverage_file_namer: None,
+      collect_event_loop_stats: false,
+      post_bootstrap: None,
+      web_worker_extensions: None,
+      npm_circular_policy: Default::default(),
+      sandbox_temp_dir: false,
+      error_redactor: None,
+      max_output_bytes: None,
+      on_module_load: None,
+      client_cert: None,
+      track_worker_activity: false,
+      virtual_time: false,
+      watch_lifecycle_events: true,
+      error_class_fn_override: None,
+      cache_quota_bytes: None,
+      measure_worker_creation: false,
+      startup_snapshot_override: None,
+      specifier_rewriter: None,
+      max_egress_bytes: None,
+      require_interceptor: None,
+      local_storage_base_dir: None,
+      cache_storage_base_dir: None,
+      npm_resolution_retries: 0,
+      npm_retry_backoff: Duration::from_millis(500),
+      on_unhandled_rejection: None,
+      forbid_node_builtins: false,
+      on_lockfile_write: None,
+      cpu_count_override: None,
+      import_meta_resolve_hook: None,
     })
   }
 }
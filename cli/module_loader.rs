@@ -25,7 +25,12 @@ use crate::util::text_encoding::code_without_source_map;
 use crate::util::text_encoding::source_map_from_code;
 use crate::worker::ModuleLoaderFactory;
 
+use deno_ast::swc::ast as swc_ast;
+use deno_ast::swc::visit::noop_visit_type;
+use deno_ast::swc::visit::Visit;
+use deno_ast::swc::visit::VisitWith;
 use deno_ast::MediaType;
+use deno_ast::SourceRangedForSpanned;
 use deno_core::anyhow::anyhow;
 use deno_core::anyhow::Context;
 use deno_core::error::custom_error;
@@ -56,7 +61,12 @@ use deno_runtime::colors;
 use deno_runtime::deno_fs;
 use deno_runtime::permissions::PermissionsContainer;
 use deno_semver::npm::NpmPackageReqReference;
+use import_map::ImportMap;
+use indexmap::IndexSet;
 use std::borrow::Cow;
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::pin::Pin;
 use std::rc::Rc;
@@ -303,6 +313,13 @@ impl PreparedModuleLoader {
         ..
       })) => {
         let code: ModuleCodeString = match media_type {
+          MediaType::JavaScript | MediaType::Mjs
+            if self.emitter.has_defines() =>
+          {
+            self
+              .emitter
+              .emit_parsed_source(specifier, *media_type, source)?
+          }
           MediaType::JavaScript
           | MediaType::Unknown
           | MediaType::Cjs
@@ -443,6 +460,32 @@ impl ModuleLoaderFactory for CliModuleLoaderFactory {
       shared: self.shared.clone(),
     }))
   }
+
+  fn lock_modules(
+    &self,
+    specifiers: &[ModuleSpecifier],
+    lockfile: &mut Lockfile,
+  ) {
+    let graph = self.shared.graph_container.graph();
+    for specifier in specifiers {
+      if !matches!(specifier.scheme(), "http" | "https") {
+        continue;
+      }
+      let found = graph.resolve(specifier);
+      let source = match graph.get(found) {
+        Some(Module::Esm(module)) if module.media_type.is_declaration() => {
+          continue
+        }
+        Some(Module::Esm(module)) => &module.source,
+        Some(Module::Json(module)) => &module.source,
+        _ => continue,
+      };
+      if found != specifier {
+        lockfile.insert_redirect(specifier.to_string(), found.to_string());
+      }
+      lockfile.check_or_insert_remote(found.as_str(), source);
+    }
+  }
 }
 
 struct CliModuleLoader {
@@ -732,3 +775,1034 @@ impl SourceMapGetter for CliSourceMapGetter {
     }
   }
 }
+
+/// A module whose source is held in memory instead of being fetched.
+#[derive(Debug, Clone)]
+pub struct InMemoryModule {
+  pub module_type: ModuleType,
+  pub code: Arc<str>,
+}
+
+/// Modules and resolutions that are served from memory instead of going
+/// through the regular module loader.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryModules {
+  pub modules: HashMap<ModuleSpecifier, InMemoryModule>,
+  /// Resolved specifiers keyed by the `(specifier, referrer)` pair that was
+  /// resolved.
+  pub resolutions: HashMap<(String, String), ModuleSpecifier>,
+}
+
+/// One feature added to a module loader by a [`LayeredModuleLoader`], e.g.
+/// a limit or a recording. Every method receives the wrapped loader and
+/// defers to it by default, so a layer only implements what it changes.
+pub trait ModuleLoaderLayer {
+  fn resolve(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, AnyError> {
+    inner.resolve(specifier, referrer, kind)
+  }
+
+  fn load(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+    is_dynamic: bool,
+    requested_module_type: RequestedModuleType,
+  ) -> deno_core::ModuleLoadResponse {
+    inner.load(specifier, maybe_referrer, is_dynamic, requested_module_type)
+  }
+
+  fn prepare_load(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &ModuleSpecifier,
+    maybe_referrer: Option<String>,
+    is_dynamic: bool,
+  ) -> Pin<Box<dyn Future<Output = Result<(), AnyError>>>> {
+    inner.prepare_load(specifier, maybe_referrer, is_dynamic)
+  }
+}
+
+/// Wraps a module loader, adding the feature of `layer` to it.
+pub struct LayeredModuleLoader<L: ModuleLoaderLayer> {
+  inner: Rc<dyn ModuleLoader>,
+  layer: L,
+}
+
+impl<L: ModuleLoaderLayer> LayeredModuleLoader<L> {
+  pub fn new(inner: Rc<dyn ModuleLoader>, layer: L) -> Self {
+    Self { inner, layer }
+  }
+}
+
+impl<L: ModuleLoaderLayer> ModuleLoader for LayeredModuleLoader<L> {
+  fn resolve(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, AnyError> {
+    self.layer.resolve(&self.inner, specifier, referrer, kind)
+  }
+
+  fn load(
+    &self,
+    specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+    is_dynamic: bool,
+    requested_module_type: RequestedModuleType,
+  ) -> deno_core::ModuleLoadResponse {
+    self.layer.load(
+      &self.inner,
+      specifier,
+      maybe_referrer,
+      is_dynamic,
+      requested_module_type,
+    )
+  }
+
+  fn prepare_load(
+    &self,
+    specifier: &ModuleSpecifier,
+    maybe_referrer: Option<String>,
+    is_dynamic: bool,
+  ) -> Pin<Box<dyn Future<Output = Result<(), AnyError>>>> {
+    self
+      .layer
+      .prepare_load(&self.inner, specifier, maybe_referrer, is_dynamic)
+  }
+}
+
+/// Passes the source `response` resolves to through `f` once it's loaded.
+fn map_load_response(
+  response: deno_core::ModuleLoadResponse,
+  specifier: &ModuleSpecifier,
+  f: impl FnOnce(&ModuleSpecifier, ModuleSource) -> Result<ModuleSource, AnyError>
+    + 'static,
+) -> deno_core::ModuleLoadResponse {
+  match response {
+    deno_core::ModuleLoadResponse::Sync(result) => {
+      deno_core::ModuleLoadResponse::Sync(
+        result.and_then(|source| f(specifier, source)),
+      )
+    }
+    deno_core::ModuleLoadResponse::Async(future) => {
+      let specifier = specifier.clone();
+      deno_core::ModuleLoadResponse::Async(
+        async move { f(&specifier, future.await?) }.boxed_local(),
+      )
+    }
+  }
+}
+
+/// Prepares the module with the wrapped loader before loading it, for modules
+/// that are imported by a module that the wrapped loader never prepared.
+fn prepare_and_load(
+  inner: Rc<dyn ModuleLoader>,
+  specifier: &ModuleSpecifier,
+  maybe_referrer: Option<&ModuleSpecifier>,
+  is_dynamic: bool,
+  requested_module_type: RequestedModuleType,
+) -> deno_core::ModuleLoadResponse {
+  let specifier = specifier.clone();
+  let maybe_referrer = maybe_referrer.cloned();
+  deno_core::ModuleLoadResponse::Async(
+    async move {
+      inner
+        .prepare_load(
+          &specifier,
+          maybe_referrer.as_ref().map(|r| r.to_string()),
+          is_dynamic,
+        )
+        .await?;
+      match inner.load(
+        &specifier,
+        maybe_referrer.as_ref(),
+        is_dynamic,
+        requested_module_type,
+      ) {
+        deno_core::ModuleLoadResponse::Sync(result) => result,
+        deno_core::ModuleLoadResponse::Async(future) => future.await,
+      }
+    }
+    .boxed_local(),
+  )
+}
+
+/// Serves the modules registered in [`InMemoryModules`] from memory and
+/// defers to the wrapped loader for everything else.
+pub struct InMemoryModuleLayer {
+  modules: Rc<RefCell<InMemoryModules>>,
+}
+
+impl InMemoryModuleLayer {
+  pub fn new(modules: Rc<RefCell<InMemoryModules>>) -> Self {
+    Self { modules }
+  }
+}
+
+impl ModuleLoaderLayer for InMemoryModuleLayer {
+  fn resolve(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, AnyError> {
+    if let Some(resolved) = self
+      .modules
+      .borrow()
+      .resolutions
+      .get(&(specifier.to_string(), referrer.to_string()))
+    {
+      return Ok(resolved.clone());
+    }
+    inner.resolve(specifier, referrer, kind)
+  }
+
+  fn load(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+    is_dynamic: bool,
+    requested_module_type: RequestedModuleType,
+  ) -> deno_core::ModuleLoadResponse {
+    let modules = self.modules.borrow();
+    if let Some(module) = modules.modules.get(specifier) {
+      return deno_core::ModuleLoadResponse::Sync(Ok(ModuleSource::new(
+        module.module_type.clone(),
+        ModuleSourceCode::String(module.code.clone().into()),
+        specifier,
+      )));
+    }
+
+    let is_referrer_in_memory = maybe_referrer
+      .map(|referrer| modules.modules.contains_key(referrer))
+      .unwrap_or(false);
+    if !is_referrer_in_memory {
+      return inner.load(
+        specifier,
+        maybe_referrer,
+        is_dynamic,
+        requested_module_type,
+      );
+    }
+
+    // static imports of an in-memory module were never prepared by the
+    // wrapped loader, so prepare them before loading
+    prepare_and_load(
+      inner.clone(),
+      specifier,
+      maybe_referrer,
+      is_dynamic,
+      requested_module_type,
+    )
+  }
+
+  fn prepare_load(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &ModuleSpecifier,
+    maybe_referrer: Option<String>,
+    is_dynamic: bool,
+  ) -> Pin<Box<dyn Future<Output = Result<(), AnyError>>>> {
+    if self.modules.borrow().modules.contains_key(specifier) {
+      return Box::pin(deno_core::futures::future::ready(Ok(())));
+    }
+    inner.prepare_load(specifier, maybe_referrer, is_dynamic)
+  }
+}
+
+/// Records every resolution and loaded module, so they can later be
+/// replayed from memory with an [`InMemoryModuleLayer`].
+///
+/// Only modules with string sources are recorded and redirects are not
+/// preserved.
+pub struct ModuleRecordingLayer {
+  recorded: Rc<RefCell<InMemoryModules>>,
+}
+
+impl ModuleRecordingLayer {
+  pub fn new(recorded: Rc<RefCell<InMemoryModules>>) -> Self {
+    Self { recorded }
+  }
+}
+
+impl ModuleLoaderLayer for ModuleRecordingLayer {
+  fn resolve(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, AnyError> {
+    let resolved = inner.resolve(specifier, referrer, kind)?;
+    self.recorded.borrow_mut().resolutions.insert(
+      (specifier.to_string(), referrer.to_string()),
+      resolved.clone(),
+    );
+    Ok(resolved)
+  }
+
+  fn load(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+    is_dynamic: bool,
+    requested_module_type: RequestedModuleType,
+  ) -> deno_core::ModuleLoadResponse {
+    let recorded = self.recorded.clone();
+    map_load_response(
+      inner.load(specifier, maybe_referrer, is_dynamic, requested_module_type),
+      specifier,
+      move |specifier, source| {
+        if let ModuleSourceCode::String(code) = &source.code {
+          recorded.borrow_mut().modules.insert(
+            specifier.clone(),
+            InMemoryModule {
+              module_type: source.module_type.clone(),
+              code: code.as_str().into(),
+            },
+          );
+        }
+        Ok(source)
+      },
+    )
+  }
+}
+
+/// Fails any load that would take the number of distinct loaded modules over
+/// `max_module_count`.
+pub struct ModuleCountLimitLayer {
+  max_module_count: usize,
+  loaded: RefCell<HashSet<ModuleSpecifier>>,
+}
+
+impl ModuleCountLimitLayer {
+  pub fn new(max_module_count: usize) -> Self {
+    Self {
+      max_module_count,
+      loaded: Default::default(),
+    }
+  }
+}
+
+impl ModuleLoaderLayer for ModuleCountLimitLayer {
+  fn load(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+    is_dynamic: bool,
+    requested_module_type: RequestedModuleType,
+  ) -> deno_core::ModuleLoadResponse {
+    {
+      let mut loaded = self.loaded.borrow_mut();
+      if !loaded.contains(specifier) {
+        if loaded.len() >= self.max_module_count {
+          return deno_core::ModuleLoadResponse::Sync(Err(generic_error(
+            format!(
+              "Module graph exceeded the maximum module count of {} ({} modules loaded) when loading \"{}\".",
+              self.max_module_count,
+              loaded.len() + 1,
+              specifier,
+            ),
+          )));
+        }
+        loaded.insert(specifier.clone());
+      }
+    }
+    inner.load(specifier, maybe_referrer, is_dynamic, requested_module_type)
+  }
+}
+
+/// Rejects imports that are nested deeper than `max_depth` below the main
+/// module with a `RangeError`. This bounds the recursion V8 does when
+/// instantiating and evaluating the module graph.
+pub struct ModuleDepthLimitLayer {
+  max_depth: usize,
+  depths: RefCell<HashMap<ModuleSpecifier, usize>>,
+}
+
+impl ModuleDepthLimitLayer {
+  pub fn new(max_depth: usize) -> Self {
+    Self {
+      max_depth,
+      depths: Default::default(),
+    }
+  }
+}
+
+impl ModuleLoaderLayer for ModuleDepthLimitLayer {
+  fn resolve(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, AnyError> {
+    let resolved = inner.resolve(specifier, referrer, kind)?;
+    let mut depths = self.depths.borrow_mut();
+    let depth = if matches!(kind, ResolutionKind::MainModule) {
+      0
+    } else {
+      ModuleSpecifier::parse(referrer)
+        .ok()
+        .and_then(|referrer| depths.get(&referrer).copied())
+        .unwrap_or(0)
+        + 1
+    };
+    if depth > self.max_depth {
+      return Err(custom_error(
+        "RangeError",
+        format!(
+          "Maximum import depth of {} exceeded when importing \"{}\" from \"{}\".",
+          self.max_depth, resolved, referrer,
+        ),
+      ));
+    }
+    depths
+      .entry(resolved.clone())
+      .and_modify(|current| *current = (*current).min(depth))
+      .or_insert(depth);
+    Ok(resolved)
+  }
+}
+
+/// Fails to load JavaScript modules that use top-level await.
+pub struct TopLevelAwaitForbiddenLayer;
+
+impl ModuleLoaderLayer for TopLevelAwaitForbiddenLayer {
+  fn load(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+    is_dynamic: bool,
+    requested_module_type: RequestedModuleType,
+  ) -> deno_core::ModuleLoadResponse {
+    map_load_response(
+      inner.load(specifier, maybe_referrer, is_dynamic, requested_module_type),
+      specifier,
+      ensure_no_top_level_await,
+    )
+  }
+}
+
+fn ensure_no_top_level_await(
+  specifier: &ModuleSpecifier,
+  source: ModuleSource,
+) -> Result<ModuleSource, AnyError> {
+  if !matches!(source.module_type, ModuleType::JavaScript) {
+    return Ok(source);
+  }
+  let ModuleSourceCode::String(code) = &source.code else {
+    return Ok(source);
+  };
+  let code = code.as_str().to_string();
+  // syntax errors are left for V8 to report
+  let Ok(parsed) = deno_ast::parse_module(deno_ast::ParseParams {
+    specifier: specifier.to_string(),
+    text_info: deno_ast::SourceTextInfo::from_string(code),
+    media_type: MediaType::JavaScript,
+    capture_tokens: false,
+    maybe_syntax: None,
+    scope_analysis: false,
+  }) else {
+    return Ok(source);
+  };
+  let mut finder = TopLevelAwaitFinder::default();
+  parsed.module().visit_with(&mut finder);
+  if let Some(range) = finder.found {
+    let position = parsed.text_info().line_and_column_display(range.start);
+    return Err(custom_error(
+      "SyntaxError",
+      format!(
+        "Top-level await is not allowed, but is used at {}:{}:{}.",
+        specifier, position.line_number, position.column_number,
+      ),
+    ));
+  }
+  Ok(source)
+}
+
+/// Finds the first `await` that isn't nested in a function.
+#[derive(Default)]
+struct TopLevelAwaitFinder {
+  found: Option<deno_ast::SourceRange>,
+}
+
+impl Visit for TopLevelAwaitFinder {
+  noop_visit_type!();
+
+  fn visit_function(&mut self, _function: &swc_ast::Function) {}
+
+  fn visit_arrow_expr(&mut self, _arrow_expr: &swc_ast::ArrowExpr) {}
+
+  fn visit_constructor(&mut self, _constructor: &swc_ast::Constructor) {}
+
+  fn visit_getter_prop(&mut self, _getter_prop: &swc_ast::GetterProp) {}
+
+  fn visit_setter_prop(&mut self, _setter_prop: &swc_ast::SetterProp) {}
+
+  fn visit_static_block(&mut self, _static_block: &swc_ast::StaticBlock) {}
+
+  fn visit_await_expr(&mut self, await_expr: &swc_ast::AwaitExpr) {
+    if self.found.is_none() {
+      self.found = Some(await_expr.range());
+    }
+  }
+
+  fn visit_for_of_stmt(&mut self, for_of_stmt: &swc_ast::ForOfStmt) {
+    if for_of_stmt.is_await && self.found.is_none() {
+      self.found = Some(for_of_stmt.range());
+    }
+    for_of_stmt.visit_children_with(self);
+  }
+}
+
+/// Remote modules loaded by any of the workers sharing this cache, so other
+/// workers in the process can use them without fetching them again.
+#[derive(Clone, Default)]
+pub struct SharedRemoteModuleCache(
+  Arc<Mutex<HashMap<ModuleSpecifier, InMemoryModule>>>,
+);
+
+impl SharedRemoteModuleCache {
+  fn get(&self, specifier: &ModuleSpecifier) -> Option<InMemoryModule> {
+    self.0.lock().get(specifier).cloned()
+  }
+
+  fn contains(&self, specifier: &ModuleSpecifier) -> bool {
+    self.0.lock().contains_key(specifier)
+  }
+
+  fn insert(&self, specifier: &ModuleSpecifier, source: &ModuleSource) {
+    if !matches!(specifier.scheme(), "http" | "https") {
+      return;
+    }
+    if let ModuleSourceCode::String(code) = &source.code {
+      self.0.lock().insert(
+        specifier.clone(),
+        InMemoryModule {
+          module_type: source.module_type.clone(),
+          code: code.as_str().into(),
+        },
+      );
+    }
+  }
+}
+
+/// Serves remote modules from a [`SharedRemoteModuleCache`] and adds the
+/// remote modules it loads to it. Cached modules are still prepared by the
+/// wrapped loader, so they are checked against the permissions of every
+/// worker loading them.
+pub struct SharedRemoteModuleCacheLayer {
+  cache: SharedRemoteModuleCache,
+}
+
+impl SharedRemoteModuleCacheLayer {
+  pub fn new(cache: SharedRemoteModuleCache) -> Self {
+    Self { cache }
+  }
+}
+
+impl ModuleLoaderLayer for SharedRemoteModuleCacheLayer {
+  fn load(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+    is_dynamic: bool,
+    requested_module_type: RequestedModuleType,
+  ) -> deno_core::ModuleLoadResponse {
+    if let Some(module) = self.cache.get(specifier) {
+      // the wrapped loader still checks the permissions and integrity of the
+      // module for this worker, the cache only saves loading its source
+      let inner = inner.clone();
+      let specifier = specifier.clone();
+      let maybe_referrer = maybe_referrer.map(|r| r.to_string());
+      return deno_core::ModuleLoadResponse::Async(
+        async move {
+          inner
+            .prepare_load(&specifier, maybe_referrer, is_dynamic)
+            .await?;
+          Ok(ModuleSource::new(
+            module.module_type,
+            ModuleSourceCode::String(module.code.into()),
+            &specifier,
+          ))
+        }
+        .boxed_local(),
+      );
+    }
+
+    let is_referrer_cached = maybe_referrer
+      .map(|referrer| self.cache.contains(referrer))
+      .unwrap_or(false);
+    let response = if is_referrer_cached {
+      // imports of a cached module were never prepared by the wrapped loader
+      prepare_and_load(
+        inner.clone(),
+        specifier,
+        maybe_referrer,
+        is_dynamic,
+        requested_module_type,
+      )
+    } else {
+      inner.load(specifier, maybe_referrer, is_dynamic, requested_module_type)
+    };
+    let cache = self.cache.clone();
+    map_load_response(response, specifier, move |specifier, source| {
+      cache.insert(specifier, &source);
+      Ok(source)
+    })
+  }
+}
+
+pub type ModuleLoadCallback =
+  Arc<dyn Fn(&ModuleSpecifier, usize) + Send + Sync>;
+
+/// Calls `on_module_load` with every module that finished loading and the
+/// number of modules loaded so far.
+pub struct ModuleLoadProgressLayer {
+  on_module_load: ModuleLoadCallback,
+  loaded_count: Rc<Cell<usize>>,
+}
+
+impl ModuleLoadProgressLayer {
+  pub fn new(on_module_load: ModuleLoadCallback) -> Self {
+    Self {
+      on_module_load,
+      loaded_count: Default::default(),
+    }
+  }
+}
+
+impl ModuleLoaderLayer for ModuleLoadProgressLayer {
+  fn load(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+    is_dynamic: bool,
+    requested_module_type: RequestedModuleType,
+  ) -> deno_core::ModuleLoadResponse {
+    let on_module_load = self.on_module_load.clone();
+    let loaded_count = self.loaded_count.clone();
+    map_load_response(
+      inner.load(specifier, maybe_referrer, is_dynamic, requested_module_type),
+      specifier,
+      move |specifier, source| {
+        loaded_count.set(loaded_count.get() + 1);
+        on_module_load(specifier, loaded_count.get());
+        Ok(source)
+      },
+    )
+  }
+}
+
+/// The modules loaded by a worker and the modules each of them imports, in
+/// the order they were first seen.
+#[derive(Debug, Default)]
+pub struct LoadedModuleGraph {
+  loaded: IndexSet<ModuleSpecifier>,
+  imports: HashMap<ModuleSpecifier, IndexSet<ModuleSpecifier>>,
+}
+
+impl LoadedModuleGraph {
+  /// Returns the loaded modules in load order.
+  pub fn loaded(&self) -> Vec<ModuleSpecifier> {
+    self.loaded.iter().cloned().collect()
+  }
+
+  /// Returns every loaded module along with its direct imports.
+  pub fn snapshot(&self) -> Vec<(ModuleSpecifier, Vec<ModuleSpecifier>)> {
+    self
+      .loaded
+      .iter()
+      .map(|specifier| {
+        let imports = self
+          .imports
+          .get(specifier)
+          .map(|imports| imports.iter().cloned().collect())
+          .unwrap_or_default();
+        (specifier.clone(), imports)
+      })
+      .collect()
+  }
+}
+
+/// Records the modules that finished loading and the import edges between
+/// them into a [`LoadedModuleGraph`].
+pub struct ModuleGraphRecordingLayer {
+  graph: Rc<RefCell<LoadedModuleGraph>>,
+}
+
+impl ModuleGraphRecordingLayer {
+  pub fn new(graph: Rc<RefCell<LoadedModuleGraph>>) -> Self {
+    Self { graph }
+  }
+}
+
+impl ModuleLoaderLayer for ModuleGraphRecordingLayer {
+  fn resolve(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, AnyError> {
+    let resolved = inner.resolve(specifier, referrer, kind)?;
+    // the main module is resolved against a non url referrer
+    if let Ok(referrer) = ModuleSpecifier::parse(referrer) {
+      self
+        .graph
+        .borrow_mut()
+        .imports
+        .entry(referrer)
+        .or_default()
+        .insert(resolved.clone());
+    }
+    Ok(resolved)
+  }
+
+  fn load(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+    is_dynamic: bool,
+    requested_module_type: RequestedModuleType,
+  ) -> deno_core::ModuleLoadResponse {
+    let graph = self.graph.clone();
+    map_load_response(
+      inner.load(specifier, maybe_referrer, is_dynamic, requested_module_type),
+      specifier,
+      move |specifier, source| {
+        graph.borrow_mut().loaded.insert(specifier.clone());
+        Ok(source)
+      },
+    )
+  }
+}
+
+/// Fails to resolve `node:` built-in modules.
+pub struct NodeBuiltinForbiddenLayer;
+
+impl ModuleLoaderLayer for NodeBuiltinForbiddenLayer {
+  fn resolve(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, AnyError> {
+    let resolved = inner.resolve(specifier, referrer, kind)?;
+    if resolved.scheme() == "node" {
+      return Err(custom_error(
+        "NotSupported",
+        format!(
+          "Node built-in modules are not allowed, but \"{resolved}\" is imported from {referrer}."
+        ),
+      ));
+    }
+    Ok(resolved)
+  }
+}
+
+/// Resolves specifiers with `import_map` before falling back to the wrapped
+/// loader's resolution.
+///
+/// Modules a mapping resolves to are prepared by the wrapped loader when
+/// they are loaded. The wrapped loader may still need to resolve the static
+/// imports of the modules it prepares itself, e.g. to build a module graph,
+/// so bare specifiers only mapped by `import_map` are best used with
+/// dynamic imports or loaders that serve modules from memory.
+pub struct ImportMapOverrideLayer {
+  import_map: Arc<ImportMap>,
+  /// Modules resolved with a mapping of `import_map`.
+  mapped: RefCell<HashSet<ModuleSpecifier>>,
+}
+
+impl ImportMapOverrideLayer {
+  pub fn new(import_map: Arc<ImportMap>) -> Self {
+    Self {
+      import_map,
+      mapped: Default::default(),
+    }
+  }
+}
+
+impl ModuleLoaderLayer for ImportMapOverrideLayer {
+  fn resolve(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, AnyError> {
+    let Ok(referrer_url) = ModuleSpecifier::parse(referrer) else {
+      return inner.resolve(specifier, referrer, kind);
+    };
+    let Ok(mapped) = self.import_map.resolve(specifier, &referrer_url) else {
+      return inner.resolve(specifier, referrer, kind);
+    };
+    let is_mapped = deno_core::resolve_import(specifier, referrer)
+      .map(|unmapped| unmapped != mapped)
+      .unwrap_or(true);
+    if !is_mapped {
+      return inner.resolve(specifier, referrer, kind);
+    }
+    let resolved = inner.resolve(mapped.as_str(), referrer, kind)?;
+    // built-in modules are loaded by the runtime itself
+    if resolved.scheme() != "node" {
+      self.mapped.borrow_mut().insert(resolved.clone());
+    }
+    Ok(resolved)
+  }
+
+  fn load(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+    is_dynamic: bool,
+    requested_module_type: RequestedModuleType,
+  ) -> deno_core::ModuleLoadResponse {
+    if !self.mapped.borrow().contains(specifier) {
+      return inner.load(
+        specifier,
+        maybe_referrer,
+        is_dynamic,
+        requested_module_type,
+      );
+    }
+    prepare_and_load(
+      inner.clone(),
+      specifier,
+      maybe_referrer,
+      is_dynamic,
+      requested_module_type,
+    )
+  }
+}
+
+pub type SpecifierRewriter =
+  Arc<dyn Fn(&ModuleSpecifier) -> Option<ModuleSpecifier> + Send + Sync>;
+
+/// Replaces every resolved specifier for which `rewriter` returns a new one,
+/// e.g. to load remote modules from a mirror.
+///
+/// Rewritten modules are prepared by the wrapped loader when they are
+/// loaded, as it only prepares the specifiers it resolved itself.
+pub struct SpecifierRewritingLayer {
+  rewriter: SpecifierRewriter,
+  /// Modules resolved to a rewritten specifier.
+  rewritten: RefCell<HashSet<ModuleSpecifier>>,
+}
+
+impl SpecifierRewritingLayer {
+  pub fn new(rewriter: SpecifierRewriter) -> Self {
+    Self {
+      rewriter,
+      rewritten: Default::default(),
+    }
+  }
+}
+
+impl ModuleLoaderLayer for SpecifierRewritingLayer {
+  fn resolve(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, AnyError> {
+    let resolved = inner.resolve(specifier, referrer, kind)?;
+    match (self.rewriter)(&resolved) {
+      Some(rewritten) => {
+        log::debug!("Rewrote module specifier {resolved} to {rewritten}.");
+        self.rewritten.borrow_mut().insert(rewritten.clone());
+        Ok(rewritten)
+      }
+      None => Ok(resolved),
+    }
+  }
+
+  fn load(
+    &self,
+    inner: &Rc<dyn ModuleLoader>,
+    specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+    is_dynamic: bool,
+    requested_module_type: RequestedModuleType,
+  ) -> deno_core::ModuleLoadResponse {
+    if !self.rewritten.borrow().contains(specifier) {
+      return inner.load(
+        specifier,
+        maybe_referrer,
+        is_dynamic,
+        requested_module_type,
+      );
+    }
+    prepare_and_load(
+      inner.clone(),
+      specifier,
+      maybe_referrer,
+      is_dynamic,
+      requested_module_type,
+    )
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use deno_runtime::permissions::Permissions;
+
+  /// Serves modules from memory and checks them against `permissions` when
+  /// they are prepared, like the module loader of the CLI does.
+  struct StubLoader {
+    permissions: PermissionsContainer,
+    modules: HashMap<ModuleSpecifier, &'static str>,
+    prepared: RefCell<HashSet<ModuleSpecifier>>,
+  }
+
+  impl StubLoader {
+    fn new(
+      permissions: PermissionsContainer,
+      modules: &[(&str, &'static str)],
+    ) -> Self {
+      Self {
+        permissions,
+        modules: modules
+          .iter()
+          .map(|(specifier, code)| {
+            (ModuleSpecifier::parse(specifier).unwrap(), *code)
+          })
+          .collect(),
+        prepared: Default::default(),
+      }
+    }
+  }
+
+  impl ModuleLoader for StubLoader {
+    fn resolve(
+      &self,
+      specifier: &str,
+      referrer: &str,
+      _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, AnyError> {
+      Ok(deno_core::resolve_import(specifier, referrer)?)
+    }
+
+    fn load(
+      &self,
+      specifier: &ModuleSpecifier,
+      _maybe_referrer: Option<&ModuleSpecifier>,
+      _is_dynamic: bool,
+      _requested_module_type: RequestedModuleType,
+    ) -> deno_core::ModuleLoadResponse {
+      let result = self
+        .modules
+        .get(specifier)
+        .filter(|_| self.prepared.borrow().contains(specifier))
+        .map(|code| {
+          ModuleSource::new(
+            ModuleType::JavaScript,
+            ModuleSourceCode::String(code.to_string().into()),
+            specifier,
+          )
+        })
+        .ok_or_else(|| anyhow!("Loading unprepared module: {specifier}"));
+      deno_core::ModuleLoadResponse::Sync(result)
+    }
+
+    fn prepare_load(
+      &self,
+      specifier: &ModuleSpecifier,
+      _maybe_referrer: Option<String>,
+      _is_dynamic: bool,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AnyError>>>> {
+      let result = self.permissions.check_specifier(specifier);
+      if result.is_ok() {
+        self.prepared.borrow_mut().insert(specifier.clone());
+      }
+      Box::pin(deno_core::futures::future::ready(result))
+    }
+  }
+
+  async fn load_module(
+    loader: &dyn ModuleLoader,
+    specifier: &ModuleSpecifier,
+  ) -> Result<ModuleSource, AnyError> {
+    match loader.load(specifier, None, false, RequestedModuleType::None) {
+      deno_core::ModuleLoadResponse::Sync(result) => result,
+      deno_core::ModuleLoadResponse::Async(future) => future.await,
+    }
+  }
+
+  #[tokio::test]
+  async fn shared_remote_module_cache_checks_permissions() {
+    let modules = [("https://deno.land/x/mod.js", "export default 1;")];
+    let specifier = ModuleSpecifier::parse(modules[0].0).unwrap();
+    let cache = SharedRemoteModuleCache::default();
+
+    let allowed = LayeredModuleLoader::new(
+      Rc::new(StubLoader::new(PermissionsContainer::allow_all(), &modules)),
+      SharedRemoteModuleCacheLayer::new(cache.clone()),
+    );
+    allowed.prepare_load(&specifier, None, false).await.unwrap();
+    load_module(&allowed, &specifier).await.unwrap();
+    assert!(cache.contains(&specifier));
+
+    let denied = LayeredModuleLoader::new(
+      Rc::new(StubLoader::new(
+        PermissionsContainer::new(Permissions::default()),
+        &modules,
+      )),
+      SharedRemoteModuleCacheLayer::new(cache.clone()),
+    );
+    let err = denied
+      .prepare_load(&specifier, None, true)
+      .await
+      .unwrap_err();
+    assert!(err.to_string().contains("Requires net access"), "{err}");
+    // also denied when the module is loaded without being prepared first
+    let err = load_module(&denied, &specifier).await.unwrap_err();
+    assert!(err.to_string().contains("Requires net access"), "{err}");
+  }
+
+  #[tokio::test]
+  async fn specifier_rewriting_prepares_rewritten_imports() {
+    let modules = [
+      ("https://deno.land/x/main.js", "import './dep.js';"),
+      ("https://mirror.example/x/dep.js", "export default 1;"),
+    ];
+    let main = ModuleSpecifier::parse(modules[0].0).unwrap();
+    let loader = LayeredModuleLoader::new(
+      Rc::new(StubLoader::new(PermissionsContainer::allow_all(), &modules)),
+      SpecifierRewritingLayer::new(Arc::new(|specifier| {
+        let path = specifier.path().strip_suffix("dep.js")?;
+        ModuleSpecifier::parse(&format!("https://mirror.example{path}dep.js"))
+          .ok()
+      })),
+    );
+    loader.prepare_load(&main, None, false).await.unwrap();
+    load_module(&loader, &main).await.unwrap();
+    let dep = loader
+      .resolve("./dep.js", main.as_str(), ResolutionKind::Import)
+      .unwrap();
+    assert_eq!(dep.as_str(), modules[1].0);
+    load_module(&loader, &dep).await.unwrap();
+  }
+}
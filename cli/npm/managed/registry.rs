@@ -8,7 +8,6 @@ use std::path::PathBuf;
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use deno_core::anyhow::anyhow;
 use deno_core::anyhow::Context;
 use deno_core::error::custom_error;
 use deno_core::error::AnyError;
@@ -86,9 +85,7 @@ impl NpmRegistryApi for CliNpmRegistryApi {
       Ok(None) => Err(NpmRegistryPackageInfoLoadError::PackageNotExists {
         package_name: name.to_string(),
       }),
-      Err(err) => {
-        Err(NpmRegistryPackageInfoLoadError::LoadError(Arc::new(err)))
-      }
+      Err(err) => Err(NpmRegistryPackageInfoLoadError::LoadError(err)),
     }
   }
 
@@ -134,7 +131,7 @@ impl CliNpmRegistryApiInner {
   pub async fn maybe_package_info(
     self: &Arc<Self>,
     name: &str,
-  ) -> Result<Option<Arc<NpmPackageInfo>>, AnyError> {
+  ) -> Result<Option<Arc<NpmPackageInfo>>, Arc<AnyError>> {
     let (created, future) = {
       let mut mem_cache = self.mem_cache.lock();
       match mem_cache.get(name) {
@@ -190,11 +187,11 @@ impl CliNpmRegistryApiInner {
         Err(err) => {
           // purge the item from the cache so it loads next time
           self.mem_cache.lock().remove(name);
-          Err(anyhow!("{:#}", err))
+          Err(err)
         }
       }
     } else {
-      Ok(future.await.map_err(|err| anyhow!("{:#}", err))?)
+      future.await
     }
   }
 
@@ -0,0 +1,58 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use deno_core::op2;
+use deno_core::OpState;
+use tokio::sync::Notify;
+
+deno_core::extension!(deno_host_abort,
+  ops = [
+    op_host_abort_wait,
+  ],
+  options = {
+    handle: AbortHandle,
+  },
+  state = |state, options| {
+    state.put(options.handle);
+  },
+);
+
+/// Lets the host abort the `globalThis.hostAbortSignal` of a worker.
+#[derive(Clone, Default)]
+pub struct AbortHandle {
+  aborted: Arc<AtomicBool>,
+  notify: Arc<Notify>,
+}
+
+impl AbortHandle {
+  pub fn abort(&self) {
+    self.aborted.store(true, Ordering::SeqCst);
+    self.notify.notify_waiters();
+  }
+
+  pub fn is_aborted(&self) -> bool {
+    self.aborted.load(Ordering::SeqCst)
+  }
+
+  async fn wait_aborted(&self) {
+    loop {
+      // register before checking the flag so a concurrent abort isn't missed
+      let notified = self.notify.notified();
+      if self.is_aborted() {
+        return;
+      }
+      notified.await;
+    }
+  }
+}
+
+#[op2(async)]
+async fn op_host_abort_wait(state: Rc<RefCell<OpState>>) {
+  let handle = state.borrow().borrow::<AbortHandle>().clone();
+  handle.wait_aborted().await;
+}
@@ -1,5 +1,6 @@
 // Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
 
+pub mod abort;
 pub mod bench;
 pub mod jupyter;
 pub mod testing;
@@ -43,6 +43,7 @@ use deno_core::ModuleSpecifier;
 use deno_core::ModuleType;
 use deno_core::RequestedModuleType;
 use deno_core::ResolutionKind;
+use deno_lockfile::Lockfile;
 use deno_runtime::deno_fs;
 use deno_runtime::deno_node::analyze::NodeCodeTranslator;
 use deno_runtime::deno_node::NodeResolver;
@@ -55,6 +56,7 @@ use deno_semver::npm::NpmPackageReqReference;
 use import_map::parse_from_json;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Duration;
 
 mod binary;
 mod file_system;
@@ -274,6 +276,14 @@ impl ModuleLoaderFactory for StandaloneModuleLoaderFactory {
   ) -> Option<Box<dyn deno_core::SourceMapGetter>> {
     None
   }
+
+  fn lock_modules(
+    &self,
+    _specifiers: &[ModuleSpecifier],
+    _lockfile: &mut Lockfile,
+  ) {
+    // embedded modules were verified when the binary was compiled
+  }
 }
 
 struct StandaloneRootCertStoreProvider {
@@ -523,7 +533,9 @@ pub async fn run(
       hmr: false,
       inspect_brk: false,
       inspect_wait: false,
+      inspect_wait_timeout: None,
       strace_ops: None,
+      max_ops_per_second: None,
       is_inspecting: false,
       is_npm_main: main_module.scheme() == "npm",
       skip_op_registration: true,
@@ -539,6 +551,57 @@ pub async fn run(
         .unsafely_ignore_certificate_errors,
       unstable: metadata.unstable_config.legacy_flag_enabled,
       maybe_root_package_json_deps: package_json_deps_provider.deps().cloned(),
+      permission_prompt_hook: None,
+      preload_modules: vec![],
+      max_module_count: None,
+      tee_output: None,
+      on_npm_package_missing: None,
+      locale_override: None,
+      max_eval_recursion: None,
+      enable_wasm_module_cache: true,
+      on_permission_revoked: None,
+      irreversible_permission_revocation: false,
+      deterministic_collections: false,
+      execution_deadline: None,
+      exit_code_mapper: None,
+      share_remote_modules: false,
+      max_concurrent_fetches: None,
+      lockfile_frozen: false,
+      resolution_referrer: None,
+      wasm_policy: Default::default(),
+      env_provider: None,
+      env_override: None,
+      allow_top_level_await: true,
+      propagate_worker_errors: false,
+      coverage_file_namer: None,
+      collect_event_loop_stats: false,
+      post_bootstrap: None,
+      web_worker_extensions: None,
+      npm_circular_policy: Default::default(),
+      sandbox_temp_dir: false,
+      error_redactor: None,
+      max_output_bytes: None,
+      on_module_load: None,
+      client_cert: None,
+      track_worker_activity: false,
+      virtual_time: false,
+      watch_lifecycle_events: true,
+      error_class_fn_override: None,
+      cache_quota_bytes: None,
+      measure_worker_creation: false,
+      startup_snapshot_override: None,
+      specifier_rewriter: None,
+      max_egress_bytes: None,
+      require_interceptor: None,
+      local_storage_base_dir: None,
+      cache_storage_base_dir: None,
+      npm_resolution_retries: 0,
+      npm_retry_backoff: Duration::from_millis(500),
+      on_unhandled_rejection: None,
+      forbid_node_builtins: false,
+      on_lockfile_write: None,
+      cpu_count_override: None,
+      import_meta_resolve_hook: None,
     },
     None,
     metadata.disable_deprecated_api_warning,
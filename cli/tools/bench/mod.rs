@@ -210,11 +210,12 @@ async fn bench_specifier_inner(
       PermissionsContainer::new(permissions),
       vec![ops::bench::deno_bench::init_ops(sender.clone())],
       Default::default(),
+      Default::default(),
     )
     .await?;
 
   // We execute the main module as a side module so that import.meta.main is not set.
-  worker.execute_side_module_possibly_with_npm().await?;
+  worker.execute_side_module_possibly_with_npm(None).await?;
 
   let mut worker = worker.into_main_worker();
 
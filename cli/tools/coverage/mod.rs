@@ -3,11 +3,13 @@
 use crate::args::CoverageFlags;
 use crate::args::FileFlags;
 use crate::args::Flags;
+use crate::cache::CACHE_PERM;
 use crate::cdp;
 use crate::factory::CliFactory;
 use crate::npm::CliNpmResolver;
 use crate::tools::fmt::format_json;
 use crate::tools::test::is_supported_test_path;
+use crate::util::fs::atomic_write_file;
 use crate::util::fs::FileCollector;
 use crate::util::text_encoding::source_map_from_code;
 
@@ -28,10 +30,9 @@ use deno_core::ModuleCodeString;
 use regex::Regex;
 use std::fs;
 use std::fs::File;
-use std::io::BufWriter;
-use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
 use text_lines::TextLines;
 use uuid::Uuid;
 
@@ -41,14 +42,27 @@ mod reporter;
 mod util;
 use merge::ProcessCoverage;
 
+/// Returns the name of the file the coverage of a script is written to.
+pub type CoverageFileNamer =
+  Arc<dyn Fn(&ModuleSpecifier) -> String + Send + Sync>;
+
 pub struct CoverageCollector {
   pub dir: PathBuf,
   session: LocalInspectorSession,
+  maybe_file_namer: Option<CoverageFileNamer>,
 }
 
 impl CoverageCollector {
-  pub fn new(dir: PathBuf, session: LocalInspectorSession) -> Self {
-    Self { dir, session }
+  pub fn new(
+    dir: PathBuf,
+    session: LocalInspectorSession,
+    maybe_file_namer: Option<CoverageFileNamer>,
+  ) -> Self {
+    Self {
+      dir,
+      session,
+      maybe_file_namer,
+    }
   }
 
   async fn enable_debugger(&mut self) -> Result<(), AnyError> {
@@ -124,7 +138,25 @@ impl CoverageCollector {
     Ok(())
   }
 
+  /// Writes the coverage collected so far and keeps collecting.
+  pub async fn flush(&mut self) -> Result<(), AnyError> {
+    self.write_coverage().await
+  }
+
   pub async fn stop_collecting(&mut self) -> Result<(), AnyError> {
+    self.write_coverage().await?;
+
+    self.disable_debugger().await?;
+    self.disable_profiler().await?;
+
+    Ok(())
+  }
+
+  /// Writes the coverage collected since the last write, one file per
+  /// script. Each file is written atomically, so readers never see partial
+  /// data. Files named by the file namer already exist when coverage is
+  /// written again, so the new coverage is merged into them.
+  async fn write_coverage(&mut self) -> Result<(), AnyError> {
     fs::create_dir_all(&self.dir)?;
 
     let script_coverages = self.take_precise_coverage().await?.result;
@@ -136,10 +168,22 @@ impl CoverageCollector {
         continue;
       }
 
-      let filename = format!("{}.json", Uuid::new_v4());
+      let (filename, script_coverage) = match (
+        &self.maybe_file_namer,
+        ModuleSpecifier::parse(&script_coverage.url),
+      ) {
+        (Some(file_namer), Ok(specifier)) => {
+          let filename = file_namer(&specifier);
+          let script_coverage = merge_with_coverage_file(
+            &self.dir.join(&filename),
+            script_coverage,
+          )?;
+          (filename, script_coverage)
+        }
+        _ => (format!("{}.json", Uuid::new_v4()), script_coverage),
+      };
       let filepath = self.dir.join(filename);
 
-      let mut out = BufWriter::new(File::create(&filepath)?);
       let coverage = serde_json::to_string(&script_coverage)?;
       let formatted_coverage =
         format_json(&filepath, &coverage, &Default::default())
@@ -147,17 +191,46 @@ impl CoverageCollector {
           .flatten()
           .unwrap_or(coverage);
 
-      out.write_all(formatted_coverage.as_bytes())?;
-      out.flush()?;
+      atomic_write_file(&filepath, formatted_coverage, CACHE_PERM)?;
     }
 
-    self.disable_debugger().await?;
-    self.disable_profiler().await?;
-
     Ok(())
   }
 }
 
+/// Merges `script_coverage` into the coverage previously written to
+/// `filepath` for the same script, if there is any.
+fn merge_with_coverage_file(
+  filepath: &Path,
+  script_coverage: cdp::ScriptCoverage,
+) -> Result<cdp::ScriptCoverage, AnyError> {
+  let existing = match fs::read_to_string(filepath) {
+    Ok(existing) => existing,
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      return Ok(script_coverage);
+    }
+    Err(err) => return Err(err.into()),
+  };
+  let existing: cdp::ScriptCoverage = serde_json::from_str(&existing)
+    .with_context(|| format!("Failed to parse {}", filepath.display()))?;
+  if existing.url != script_coverage.url {
+    return Ok(script_coverage);
+  }
+  let merged = merge::merge_processes(vec![
+    ProcessCoverage {
+      result: vec![existing],
+    },
+    ProcessCoverage {
+      result: vec![script_coverage],
+    },
+  ]);
+  merged
+    .and_then(|c| c.result.into_iter().next())
+    .ok_or_else(|| {
+      anyhow!("Failed to merge coverage into {}", filepath.display())
+    })
+}
+
 #[derive(Debug, Clone)]
 struct BranchCoverageItem {
   line_index: usize,
@@ -579,3 +652,38 @@ pub async fn cover_files(
 
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn script_coverage(count: i64) -> cdp::ScriptCoverage {
+    cdp::ScriptCoverage {
+      script_id: String::from("0"),
+      url: String::from("file:///lib.js"),
+      functions: vec![cdp::FunctionCoverage {
+        function_name: String::from("lib"),
+        is_block_coverage: true,
+        ranges: vec![cdp::CoverageRange {
+          start_char_offset: 0,
+          end_char_offset: 9,
+          count,
+        }],
+      }],
+    }
+  }
+
+  #[test]
+  fn merges_with_existing_coverage_file() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let filepath = temp_dir.path().join("lib.json");
+    let merged =
+      merge_with_coverage_file(&filepath, script_coverage(1)).unwrap();
+    assert_eq!(merged, script_coverage(1));
+
+    fs::write(&filepath, serde_json::to_string(&merged).unwrap()).unwrap();
+    let merged =
+      merge_with_coverage_file(&filepath, script_coverage(2)).unwrap();
+    assert_eq!(merged.functions[0].ranges[0].count, 3);
+  }
+}
@@ -98,6 +98,7 @@ pub async fn kernel(
         stdout,
         stderr,
       },
+      Default::default(),
     )
     .await?;
   worker.setup_repl().await?;
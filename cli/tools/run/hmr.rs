@@ -75,6 +75,12 @@ impl HmrRunner {
     }
   }
 
+  /// Replaces the emitter used to transpile changed modules, e.g. after the
+  /// transpile configuration changed.
+  pub fn set_emitter(&mut self, emitter: Arc<Emitter>) {
+    self.emitter = emitter;
+  }
+
   // TODO(bartlomieju): this code is duplicated in `cli/tools/coverage/mod.rs`
   pub async fn start(&mut self) -> Result<(), AnyError> {
     self.enable_debugger().await
@@ -462,6 +462,7 @@ async fn test_specifier_inner(
         stdout,
         stderr,
       },
+      Default::default(),
     )
     .await?;
 
@@ -475,7 +476,7 @@ async fn test_specifier_inner(
   }
 
   // We execute the main module as a side module so that import.meta.main is not set.
-  worker.execute_side_module_possibly_with_npm().await?;
+  worker.execute_side_module_possibly_with_npm(None).await?;
 
   let mut worker = worker.into_main_worker();
 
@@ -1,60 +1,144 @@
 // Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
 
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
 
 use deno_ast::ModuleSpecifier;
 use deno_core::anyhow::bail;
 use deno_core::anyhow::Context;
+use deno_core::error::generic_error;
 use deno_core::error::AnyError;
+use deno_core::error::JsError;
+use deno_core::futures::future;
+use deno_core::futures::future::LocalBoxFuture;
 use deno_core::futures::FutureExt;
 use deno_core::located_script_name;
 use deno_core::parking_lot::Mutex;
+use deno_core::serde_json;
+use deno_core::serde_v8;
+use deno_core::unsync::spawn;
+use deno_core::unsync::spawn_blocking;
 use deno_core::url::Url;
 use deno_core::v8;
 use deno_core::CompiledWasmModuleStore;
 use deno_core::Extension;
 use deno_core::FeatureChecker;
+use deno_core::GetErrorClassFn;
+use deno_core::JsRuntime;
 use deno_core::ModuleId;
 use deno_core::ModuleLoader;
+use deno_core::ModuleType;
 use deno_core::PollEventLoopOptions;
+use deno_core::ResourceId;
 use deno_core::SharedArrayBufferStore;
+use deno_core::Snapshot;
 use deno_core::SourceMapGetter;
 use deno_lockfile::Lockfile;
+use deno_npm::registry::NpmRegistryPackageInfoLoadError;
+use deno_npm::resolution::NpmResolutionError;
+use deno_npm::resolution::NpmResolutionSnapshot;
+use deno_npm::NpmPackageId;
 use deno_runtime::colors;
 use deno_runtime::deno_broadcast_channel::InMemoryBroadcastChannel;
+use deno_runtime::deno_fetch;
 use deno_runtime::deno_fs;
+use deno_runtime::deno_io::StdioPipe;
 use deno_runtime::deno_node;
 use deno_runtime::deno_node::NodeResolution;
 use deno_runtime::deno_node::NodeResolutionMode;
 use deno_runtime::deno_node::NodeResolver;
+use deno_runtime::deno_tls;
 use deno_runtime::deno_tls::RootCertStoreProvider;
 use deno_runtime::deno_web::BlobStore;
+use deno_runtime::deno_web::VirtualClock;
 use deno_runtime::fmt_errors::format_js_error;
 use deno_runtime::inspector_server::InspectorServer;
+use deno_runtime::ops::os::EnvProvider;
+use deno_runtime::ops::os::SharedEnvProvider;
+use deno_runtime::ops::permissions::PermissionRevocationOptions;
+use deno_runtime::ops::permissions::PermissionRevokedCallback;
+use deno_runtime::ops::process::SubprocessEnv;
+use deno_runtime::ops::runtime::UnhandledRejectionCallback;
+use deno_runtime::ops::runtime::UnhandledRejectionHandler;
 use deno_runtime::ops::worker_host::CreateWebWorkerCb;
+use deno_runtime::permissions::create_child_permissions_from_options;
+use deno_runtime::permissions::PermissionPrompter;
 use deno_runtime::permissions::PermissionsContainer;
+use deno_runtime::permissions::PermissionsOptions;
+use deno_runtime::permissions::PromptResponse;
+use deno_runtime::permissions::SharedPermissionPrompter;
 use deno_runtime::web_worker::WebWorker;
 use deno_runtime::web_worker::WebWorkerOptions;
+use deno_runtime::web_worker::WebWorkerTerminateHandle;
+use deno_runtime::web_worker::WorkerId;
+use deno_runtime::worker::EventLoopStats;
+use deno_runtime::worker::FormatJsErrorFn;
+use deno_runtime::worker::ImportMetaResolveHook;
 use deno_runtime::worker::MainWorker;
+use deno_runtime::worker::OpCallMetrics;
+use deno_runtime::worker::WorkerActivity;
 use deno_runtime::worker::WorkerOptions;
 use deno_runtime::BootstrapOptions;
 use deno_runtime::WorkerLogLevel;
 use deno_semver::npm::NpmPackageReqReference;
+use deno_semver::package::PackageReq;
 use deno_semver::package::PackageReqReference;
+use import_map::ImportMap;
+use serde::Deserialize;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
 use tokio::select;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
 
 use crate::args::package_json::PackageJsonDeps;
 use crate::args::DenoSubcommand;
 use crate::args::StorageKeyResolver;
 use crate::emit::Emitter;
 use crate::errors;
+use crate::module_loader::ImportMapOverrideLayer;
+use crate::module_loader::InMemoryModule;
+use crate::module_loader::InMemoryModuleLayer;
+use crate::module_loader::InMemoryModules;
+use crate::module_loader::LayeredModuleLoader;
+use crate::module_loader::LoadedModuleGraph;
+use crate::module_loader::ModuleCountLimitLayer;
+use crate::module_loader::ModuleDepthLimitLayer;
+use crate::module_loader::ModuleGraphRecordingLayer;
+use crate::module_loader::ModuleLoadCallback;
+use crate::module_loader::ModuleLoadProgressLayer;
+use crate::module_loader::ModuleRecordingLayer;
+use crate::module_loader::NodeBuiltinForbiddenLayer;
+use crate::module_loader::SharedRemoteModuleCache;
+use crate::module_loader::SharedRemoteModuleCacheLayer;
+use crate::module_loader::SpecifierRewriter;
+use crate::module_loader::SpecifierRewritingLayer;
+use crate::module_loader::TopLevelAwaitForbiddenLayer;
 use crate::npm::CliNpmResolver;
+use crate::npm::ManagedCliNpmResolver;
+use crate::ops;
+use crate::ops::abort::AbortHandle;
 use crate::tools;
 use crate::tools::coverage::CoverageCollector;
+use crate::tools::coverage::CoverageFileNamer;
 use crate::tools::run::hmr::HmrRunner;
+use crate::tools::test::TestFilter;
 use crate::util::checksum;
 use crate::util::file_watcher::WatcherCommunicator;
 use crate::util::file_watcher::WatcherRestartMode;
@@ -74,6 +158,14 @@ pub trait ModuleLoaderFactory: Send + Sync {
   ) -> Rc<dyn ModuleLoader>;
 
   fn create_source_map_getter(&self) -> Option<Box<dyn SourceMapGetter>>;
+
+  /// Records the sources of the given loaded modules, and the redirects that
+  /// led to them, in `lockfile`. Modules that aren't remote are skipped.
+  fn lock_modules(
+    &self,
+    specifiers: &[ModuleSpecifier],
+    lockfile: &mut Lockfile,
+  );
 }
 
 // todo(dsherret): this is temporary and we should remove this
@@ -82,6 +174,232 @@ pub trait HasNodeSpecifierChecker: Send + Sync {
   fn has_node_specifier(&self) -> bool;
 }
 
+/// A permission prompt raised while running a worker.
+pub struct PermissionPromptRequest<'a> {
+  /// Name of the permission, eg. "net" or "read".
+  pub name: &'a str,
+  /// Human readable description of what is being requested, eg.
+  /// `net access to "deno.land"`.
+  pub message: &'a str,
+  pub api_name: Option<&'a str>,
+  pub is_unary: bool,
+}
+
+pub type PermissionPromptHook =
+  Arc<dyn Fn(&PermissionPromptRequest) -> PromptResponse + Send + Sync>;
+
+pub type NpmPackageMissingHook = Arc<
+  dyn Fn(&PackageReq) -> LocalBoxFuture<'static, Result<bool, AnyError>>
+    + Send
+    + Sync,
+>;
+
+/// What to do when the npm packages of an npm main module depend on each
+/// other in a cycle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NpmCircularPolicy {
+  #[default]
+  Allow,
+  Warn,
+  Error,
+}
+
+/// Controls the use of WebAssembly by scripts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WasmPolicy {
+  #[default]
+  Allow,
+  /// Disallow compiling WebAssembly in the isolate, so every way of creating
+  /// a module fails, and don't share compiled modules with other workers.
+  Deny,
+  /// Reject modules larger than the given number of bytes. The size is
+  /// checked by the `WebAssembly` compile and instantiate APIs, so this
+  /// guards against accidental resource use rather than being a security
+  /// boundary, use `Deny` for untrusted code. Compiled modules aren't shared
+  /// with other workers.
+  SizeLimited(u64),
+}
+
+impl WasmPolicy {
+  /// Whether compiled modules may be sent to and received from other
+  /// workers, which would get around the policy.
+  fn allows_module_sharing(&self) -> bool {
+    matches!(self, WasmPolicy::Allow)
+  }
+
+  /// Applies the policy to the isolate of a freshly bootstrapped worker.
+  fn enforce(&self, js_runtime: &mut JsRuntime) -> Result<(), AnyError> {
+    match self {
+      WasmPolicy::Allow => Ok(()),
+      WasmPolicy::Deny => {
+        js_runtime
+          .v8_isolate()
+          .set_allow_wasm_code_generation_callback(deny_wasm_code_generation);
+        Ok(())
+      }
+      WasmPolicy::SizeLimited(max_size) => {
+        js_runtime.execute_script(
+          located_script_name!(),
+          wasm_size_limit_script(*max_size).into(),
+        )?;
+        Ok(())
+      }
+    }
+  }
+}
+
+extern "C" fn deny_wasm_code_generation(
+  _context: v8::Local<v8::Context>,
+  _source: v8::Local<v8::String>,
+) -> bool {
+  false
+}
+
+/// Returns a script that patches the `WebAssembly` namespace to reject
+/// modules larger than `max_size` bytes.
+fn wasm_size_limit_script(max_size: u64) -> String {
+  format!(
+    r#"(() => {{
+      const maxSize = {max_size};
+      const wasm = WebAssembly;
+      const {{ compile, instantiate, Module }} = wasm;
+      function check(bytes) {{
+        const size = bytes?.byteLength;
+        if (typeof size === "number" && size > maxSize) {{
+          throw new wasm.CompileError(
+            `WebAssembly module of ${{size}} bytes exceeds the limit of ${{maxSize}} bytes.`,
+          );
+        }}
+      }}
+      wasm.compile = async (bytes) => {{
+        check(bytes);
+        return await compile(bytes);
+      }};
+      wasm.instantiate = async (source, imports) => {{
+        if (!(source instanceof Module)) {{
+          check(source);
+        }}
+        return await instantiate(source, imports);
+      }};
+      wasm.compileStreaming = async (source) => {{
+        const bytes = await (await source).arrayBuffer();
+        check(bytes);
+        return await compile(bytes);
+      }};
+      wasm.instantiateStreaming = async (source, imports) => {{
+        const bytes = await (await source).arrayBuffer();
+        check(bytes);
+        return await instantiate(bytes, imports);
+      }};
+      const SizeLimitedModule = new Proxy(Module, {{
+        construct(target, args, newTarget) {{
+          check(args[0]);
+          return Reflect.construct(target, args, newTarget);
+        }},
+      }});
+      Object.defineProperty(Module.prototype, "constructor", {{
+        value: SizeLimitedModule,
+      }});
+      wasm.Module = SizeLimitedModule;
+    }})();"#
+  )
+}
+
+pub type ExitCodeMapper = Arc<dyn Fn(i32) -> i32 + Send + Sync>;
+
+pub type WebWorkerExtensionsFactory =
+  Arc<dyn Fn() -> Vec<Extension> + Send + Sync>;
+
+pub type PostBootstrapHook =
+  Arc<dyn Fn(&mut MainWorker) -> Result<(), AnyError> + Send + Sync>;
+
+pub type ErrorRedactor = Arc<dyn Fn(String) -> String + Send + Sync>;
+
+/// Maps an error to the name of the JavaScript error class it is thrown as,
+/// or returns `None` to fall back to the default mapping.
+pub type ErrorClassMapper =
+  Arc<dyn Fn(&AnyError) -> Option<&'static str> + Send + Sync>;
+
+type StaticErrorClassFn =
+  &'static (dyn Fn(&AnyError) -> &'static str + Send + Sync);
+
+/// The installed `error_class_fn_override` mappers, each along with the
+/// function handed to the runtime for it.
+static ERROR_CLASS_MAPPER_SLOTS: Mutex<
+  Vec<(StaticErrorClassFn, Option<ErrorClassMapper>)>,
+> = deno_core::parking_lot::const_mutex(Vec::new());
+
+/// Keeps an `error_class_fn_override` mapper installed while the factory
+/// that uses it is alive.
+///
+/// The runtime requires a `'static` error class function, so one is leaked
+/// per slot. It looks its mapper up by slot, and a slot is reused once its
+/// factory is dropped, so only as many functions are leaked as there were
+/// factories with a mapper alive at once.
+struct ErrorClassMapperSlot(usize);
+
+impl ErrorClassMapperSlot {
+  fn install(mapper: ErrorClassMapper) -> (Self, GetErrorClassFn) {
+    let mut slots = ERROR_CLASS_MAPPER_SLOTS.lock();
+    let index = match slots.iter().position(|(_, mapper)| mapper.is_none()) {
+      Some(index) => index,
+      None => {
+        let index = slots.len();
+        let get_error_class_fn: StaticErrorClassFn =
+          Box::leak(Box::new(move |e: &AnyError| {
+            // don't hold the lock while the mapper runs
+            let mapper = ERROR_CLASS_MAPPER_SLOTS.lock()[index].1.clone();
+            mapper
+              .and_then(|mapper| mapper(e))
+              .unwrap_or_else(|| errors::get_error_class_name(e))
+          }));
+        slots.push((get_error_class_fn, None));
+        index
+      }
+    };
+    slots[index].1 = Some(mapper);
+    let get_error_class_fn: GetErrorClassFn = slots[index].0;
+    (Self(index), get_error_class_fn)
+  }
+}
+
+impl Drop for ErrorClassMapperSlot {
+  fn drop(&mut self) {
+    ERROR_CLASS_MAPPER_SLOTS.lock()[self.0].1 = None;
+  }
+}
+
+/// Answers permission prompts by delegating to an embedder provided hook
+/// instead of asking on the TTY.
+struct HookPermissionPrompter(PermissionPromptHook);
+
+impl PermissionPrompter for HookPermissionPrompter {
+  fn prompt(
+    &mut self,
+    message: &str,
+    name: &str,
+    api_name: Option<&str>,
+    is_unary: bool,
+  ) -> PromptResponse {
+    (self.0)(&PermissionPromptRequest {
+      name,
+      message,
+      api_name,
+      is_unary,
+    })
+  }
+}
+
+pub const DEFAULT_INSPECT_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long `run` waits for the output of a worker to be copied once it
+/// finished, see `CliMainWorker::join_stdio_pipe_threads`.
+const STDIO_PIPE_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The stdio web workers are created with, which is taken once the main
+/// worker finished so they no longer keep its output pipes open.
+type WebWorkerStdio = Arc<Mutex<Option<deno_runtime::deno_io::Stdio>>>;
+
 #[derive(Clone)]
 pub struct CliMainWorkerOptions {
   pub argv: Vec<String>,
@@ -93,7 +411,15 @@ pub struct CliMainWorkerOptions {
   pub hmr: bool,
   pub inspect_brk: bool,
   pub inspect_wait: bool,
+  /// When `inspect_wait` is set, how long `run` keeps the isolate alive after
+  /// the main module finished if no debugger has attached yet. Defaults to
+  /// [`DEFAULT_INSPECT_WAIT_TIMEOUT`].
+  pub inspect_wait_timeout: Option<Duration>,
   pub strace_ops: Option<Vec<String>>,
+  /// Throttles the async ops of the main worker to at most this many per
+  /// second by pausing its event loop, see
+  /// `deno_runtime::worker::WorkerOptions::max_ops_per_second`.
+  pub max_ops_per_second: Option<u32>,
   pub is_inspecting: bool,
   pub is_npm_main: bool,
   pub location: Option<Url>,
@@ -104,6 +430,187 @@ pub struct CliMainWorkerOptions {
   pub unstable: bool,
   pub skip_op_registration: bool,
   pub maybe_root_package_json_deps: Option<PackageJsonDeps>,
+  /// Answers the permission prompts of workers created by this factory, and
+  /// of their web workers, instead of the interactive TTY prompter.
+  pub permission_prompt_hook: Option<PermissionPromptHook>,
+  /// Modules that are loaded and compiled, but not evaluated, before the main
+  /// module runs.
+  pub preload_modules: Vec<ModuleSpecifier>,
+  /// Fails loading once more than this many distinct modules were loaded,
+  /// which guards against dependency explosions.
+  pub max_module_count: Option<usize>,
+  /// When set, everything the worker writes to stdout and stderr is also
+  /// appended to this buffer while still being forwarded to its destination.
+  pub tee_output: Option<Arc<Mutex<Vec<u8>>>>,
+  /// Called when the folder of an npm package can't be found while resolving
+  /// an `npm:` main module. Returning `true` retries the resolution once, so
+  /// the hook can install the package on the fly.
+  pub on_npm_package_missing: Option<NpmPackageMissingHook>,
+  /// Locale reported to the worker instead of the one derived from ICU.
+  pub locale_override: Option<String>,
+  /// Maximum recursion of module evaluation, enforced as the depth of nested
+  /// imports below the main module, since V8 instantiates and evaluates the
+  /// module graph recursively. Deeper imports fail with a catchable
+  /// `RangeError` when they are resolved. Recursion of the code being
+  /// evaluated isn't limited by this.
+  pub max_eval_recursion: Option<usize>,
+  /// Keep a store of compiled WebAssembly modules so they can be transferred
+  /// between workers. Disabling it lowers memory usage for workers that don't
+  /// use WebAssembly, but `WebAssembly.Module`s can then no longer be posted
+  /// to other workers, which have to compile them again instead.
+  pub enable_wasm_module_cache: bool,
+  /// Called with the permission name whenever a script revokes a permission
+  /// via `Deno.permissions.revoke()`.
+  pub on_permission_revoked: Option<PermissionRevokedCallback>,
+  /// Deny whatever the script revokes from then on, so it can't be granted
+  /// again by requesting it or by the prompt of a later check.
+  pub irreversible_permission_revocation: bool,
+  /// Make operations whose order isn't otherwise specified deterministic,
+  /// where feasible. Currently this sorts directory listings by name.
+  pub deterministic_collections: bool,
+  /// Wall clock time after which `run` forcefully terminates the script,
+  /// even if it's stuck in a synchronous loop. The run is abandoned where it
+  /// was: `unload` is not dispatched and coverage is not written.
+  pub execution_deadline: Option<Duration>,
+  /// Transforms the exit code returned by `run`.
+  pub exit_code_mapper: Option<ExitCodeMapper>,
+  /// Let all workers created by the factory share the remote modules any of
+  /// them loaded, instead of fetching them again for every worker.
+  pub share_remote_modules: bool,
+  /// Maximum number of fetch requests a worker can have in flight at once,
+  /// counting a request until its response body is consumed or dropped.
+  /// Further requests are queued until one completes.
+  pub max_concurrent_fetches: Option<usize>,
+  /// Error instead of writing the lockfile when resolving the main module
+  /// would change it.
+  pub lockfile_frozen: bool,
+  /// Referrer used to resolve an npm main module, which determines the
+  /// package.json that governs the resolution. Defaults to the package.json
+  /// in the current working directory.
+  pub resolution_referrer: Option<ModuleSpecifier>,
+  /// Restricts which WebAssembly modules scripts may compile.
+  pub wasm_policy: WasmPolicy,
+  /// Backs `Deno.env` (and `process.env`) with a custom store instead of
+  /// the process environment.
+  pub env_provider: Option<Arc<dyn EnvProvider + Send + Sync>>,
+  /// Backs `Deno.env` of every worker with its own copy of this map instead
+  /// of the process environment. Changes made by a worker are only visible
+  /// to that worker. Takes precedence over `env_provider`.
+  pub env_override: Option<HashMap<String, String>>,
+  /// Permit top-level await in modules. When false, modules using it fail
+  /// to load with a `SyntaxError`.
+  pub allow_top_level_await: bool,
+  /// Fail `run` when a web worker, or any worker it spawns, terminates with
+  /// an uncaught error, even if the error is handled in the parent.
+  pub propagate_worker_errors: bool,
+  /// Names the files coverage is written to in `coverage_dir`, instead of
+  /// using a random name. Collecting coverage for a script again, e.g. by
+  /// flushing the collector, merges it into its file.
+  pub coverage_file_namer: Option<CoverageFileNamer>,
+  /// Count how the event loop of the main worker behaves, for
+  /// [`CliMainWorker::run_with_stats`].
+  pub collect_event_loop_stats: bool,
+  /// Called with every main worker once it is bootstrapped, before any module
+  /// is loaded. An error fails the creation of the worker.
+  pub post_bootstrap: Option<PostBootstrapHook>,
+  /// Creates additional extensions for every web worker, like the custom
+  /// extensions passed to `create_custom_worker` for the main worker.
+  pub web_worker_extensions: Option<WebWorkerExtensionsFactory>,
+  /// How to handle circular dependencies between the npm packages of an npm
+  /// main module.
+  pub npm_circular_policy: NpmCircularPolicy,
+  /// Give every main worker a fresh temporary directory, used by
+  /// `Deno.makeTempDir()`, `Deno.makeTempFile()` and as `$TMPDIR` in
+  /// `Deno.env` and in the environment of subprocesses. It is removed once
+  /// `run` finishes.
+  pub sandbox_temp_dir: bool,
+  /// Applied to every formatted JavaScript error before it is printed or
+  /// returned, e.g. to scrub file paths or secrets. JavaScript errors returned
+  /// from `run` and the `execute_*` methods are replaced with errors carrying
+  /// the redacted message.
+  pub error_redactor: Option<ErrorRedactor>,
+  /// Caps the total number of bytes the main worker and its web workers may
+  /// write to stdout and stderr combined. Output past the limit is dropped,
+  /// and a notice is written in its place.
+  pub max_output_bytes: Option<u64>,
+  /// Called with every module the main worker finished loading and the
+  /// number of modules loaded so far, e.g. to show loading progress.
+  pub on_module_load: Option<ModuleLoadCallback>,
+  /// Paths of a PEM encoded certificate chain and private key, presented as
+  /// the client certificate of TLS connections made by `fetch` for mutual
+  /// TLS. Both are read and validated when a worker is created.
+  pub client_cert: Option<(PathBuf, PathBuf)>,
+  /// Track whether the main worker and its web workers have work left, for
+  /// [`CliMainWorker::run_until_all_idle`].
+  pub track_worker_activity: bool,
+  /// Run the main worker on a virtual clock that only advances with
+  /// [`CliMainWorker::advance_time`], for `Date.now()`, `new Date()`,
+  /// `performance.now()` and timers. Web workers keep using the wall clock.
+  pub virtual_time: bool,
+  /// Dispatch the `load` and `unload` events around every run in watch mode.
+  /// Turning this off makes reloads cheaper, but scripts listening for these
+  /// events will no longer see them.
+  pub watch_lifecycle_events: bool,
+  /// Consulted before the default error class mapping when an op error is
+  /// thrown into JavaScript, e.g. to throw `Deno.errors.TimedOut` for the
+  /// errors of custom ops.
+  pub error_class_fn_override: Option<ErrorClassMapper>,
+  /// Caps the bytes of response bodies the Cache API may store per origin.
+  /// `cache.put` is rejected with a `QuotaExceededError` once the cap would
+  /// be exceeded; nothing is evicted automatically.
+  pub cache_quota_bytes: Option<u64>,
+  /// Measure how long the phases of creating a main worker take, for
+  /// [`CliMainWorker::creation_timings`].
+  pub measure_worker_creation: bool,
+  /// Boot the main worker and its web workers from this snapshot instead of
+  /// the built-in one, e.g. one that already contains the application's
+  /// modules. It must be created by this version of Deno with the extensions
+  /// of the CLI and the custom extensions of the workers.
+  pub startup_snapshot_override: Option<&'static [u8]>,
+  /// Consulted with every specifier the main worker and its web workers
+  /// resolve. When it returns a specifier, that one is loaded instead, e.g.
+  /// to serve `https://deno.land/` modules from an internal mirror.
+  pub specifier_rewriter: Option<SpecifierRewriter>,
+  /// Caps the bytes of request and response bodies a worker may send and
+  /// receive with `fetch`. Once exceeded, starting requests and transferring
+  /// more body bytes fail. The main worker and every web worker have their
+  /// own budget. Only `fetch` is counted: traffic of `Deno.connect`,
+  /// `Deno.connectTls`, WebSockets and `Deno.serve` isn't.
+  pub max_egress_bytes: Option<u64>,
+  /// Consulted with every CommonJS `require` call before it is resolved,
+  /// including the ones of a CommonJS main module. A returned request is
+  /// required instead, e.g. to inject mocks without patching node_modules.
+  pub require_interceptor: Option<deno_node::RequireInterceptor>,
+  /// The directory `localStorage` is persisted in, one subdirectory per
+  /// origin, instead of `origin_data_folder_path`.
+  pub local_storage_base_dir: Option<PathBuf>,
+  /// The directory the Cache API stores its data in, one subdirectory per
+  /// origin, instead of `deno_cache` in the system temp directory.
+  pub cache_storage_base_dir: Option<PathBuf>,
+  /// How many times resolving the npm main module's packages is retried
+  /// after a network or server error from the registry. Other errors, such
+  /// as a package that doesn't exist, fail right away.
+  pub npm_resolution_retries: u32,
+  /// How long to wait before the first npm resolution retry. The delay is
+  /// doubled for every following retry.
+  pub npm_retry_backoff: Duration,
+  /// Called with promise rejections no `unhandledrejection` listener
+  /// prevented, deciding whether they terminate the worker.
+  pub on_unhandled_rejection: Option<UnhandledRejectionCallback>,
+  /// Don't initialize the Node compatibility layer and fail to resolve
+  /// `node:` modules, in the main worker and its web workers. npm packages
+  /// can't be used either, since they depend on it.
+  pub forbid_node_builtins: bool,
+  /// Called with the path of the lockfile after it was written when running
+  /// an npm binary command.
+  pub on_lockfile_write: Option<Arc<dyn Fn(&Path) + Send + Sync>>,
+  /// Reported as `navigator.hardwareConcurrency` instead of the available
+  /// parallelism of the host, e.g. to match a container's CPU quota.
+  pub cpu_count_override: Option<usize>,
+  /// Resolves the `import.meta.resolve()` calls of the main worker and its
+  /// web workers, e.g. to let plugins resolve to URLs that aren't imported.
+  /// Imports are still resolved by the module loader.
+  pub import_meta_resolve_hook: Option<ImportMetaResolveHook>,
 }
 
 struct SharedWorkerState {
@@ -127,17 +634,156 @@ struct SharedWorkerState {
   node_ipc: Option<i64>,
   disable_deprecated_api_warning: bool,
   verbose_deprecated_api_warning: bool,
+  remote_module_cache: SharedRemoteModuleCache,
+  get_error_class_fn: GetErrorClassFn,
+  _error_class_mapper_slot: Option<ErrorClassMapperSlot>,
 }
 
 impl SharedWorkerState {
   // Currently empty
 }
 
+/// The resolved npm dependency graph of a worker factory.
+#[derive(Debug, Clone, Default)]
+pub struct DependencyTree {
+  /// Top level package requirements along with the package each one
+  /// resolved to.
+  pub roots: Vec<(PackageReq, NpmPackageId)>,
+  /// Every resolved package, sorted by id.
+  pub packages: Vec<DependencyTreePackage>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DependencyTreePackage {
+  pub id: NpmPackageId,
+  pub name: String,
+  pub version: String,
+  /// Edges to the packages this package depends on, keyed by the name the
+  /// dependency is imported as.
+  pub dependencies: Vec<(String, NpmPackageId)>,
+}
+
+/// A User Timing entry (`performance.mark` or `performance.measure`)
+/// created by the executed code.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PerformanceEntry {
+  pub name: String,
+  /// Either "mark" or "measure".
+  pub entry_type: String,
+  pub start_time: f64,
+  pub duration: f64,
+}
+
+/// A timer created with `setTimeout` or `setInterval` that hasn't fired or
+/// been cleared yet, as returned by [`CliMainWorker::pending_timers`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimerInfo {
+  pub id: u32,
+  /// Milliseconds until the timer is due, zero if it is already due.
+  pub due_in_ms: f64,
+  /// Whether the timer was created with `setInterval`.
+  pub repeating: bool,
+}
+
+/// A benchmark registered with `Deno.bench`, as returned by
+/// [`CliMainWorker::list_benchmarks`].
+#[derive(Debug, Clone)]
+pub struct BenchInfo {
+  pub name: String,
+  pub origin: String,
+  pub group: Option<String>,
+  pub baseline: bool,
+  pub ignore: bool,
+  pub only: bool,
+}
+
+/// The outcome of [`CliMainWorker::run_with_budget_and_partial`].
+#[derive(Debug, Clone)]
+pub struct PartialResult {
+  /// Whether the main module ran to completion within the budget.
+  pub completed: bool,
+  /// The exit code, if the main module completed.
+  pub exit_code: Option<i32>,
+  /// The value of `globalThis.partialResult` when the run stopped.
+  pub state: Option<serde_json::Value>,
+}
+
+/// The op calls made in a window of time, see
+/// [`CliMainWorker::op_metrics_snapshot`].
+#[derive(Debug, Clone, Default)]
+pub struct OpMetricsSnapshot {
+  /// Every op that was called in the window.
+  pub ops: Vec<OpCallMetrics>,
+}
+
+/// How long the phases of creating a main worker took, see
+/// [`CliMainWorker::creation_timings`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WorkerCreationTimings {
+  /// Resolving the main module, including any npm resolution.
+  pub main_module_resolution: Duration,
+  /// Creating and wrapping the module loader.
+  pub module_loader_setup: Duration,
+  /// Bootstrapping the runtime, including the post-bootstrap setup.
+  pub bootstrap: Duration,
+}
+
+/// A module graph that was pre-loaded and transpiled into memory by
+/// [`CliMainWorkerFactory::prebundle`].
+#[derive(Debug, Clone)]
+pub struct Bundle {
+  pub main_module: ModuleSpecifier,
+  pub modules: InMemoryModules,
+}
+
 pub struct CliMainWorker {
   main_module: ModuleSpecifier,
   is_main_cjs: bool,
   worker: MainWorker,
   shared: Arc<SharedWorkerState>,
+  /// Collector started with `start_coverage`, as opposed to the one set up
+  /// automatically by `run` when `coverage_dir` is set.
+  manual_coverage_collector: Option<CoverageCollector>,
+  web_workers: WebWorkerRegistry,
+  /// Receives the formatted uncaught errors of web workers when
+  /// `propagate_worker_errors` is set.
+  maybe_worker_errors: Option<UnboundedReceiver<String>>,
+  /// Captured when the worker is created, so it can be used to interrupt
+  /// execution from another thread.
+  isolate_handle: v8::IsolateHandle,
+  /// Modules registered with `execute_inline_module`.
+  inline_modules: Rc<RefCell<InMemoryModules>>,
+  /// Set with `update_hmr_emitter`, replacing the emitter of the factory for
+  /// hot module replacement.
+  hmr_emitter: Option<Arc<Emitter>>,
+  /// The clock of the worker when `virtual_time` is set.
+  virtual_clock: Option<VirtualClock>,
+  /// The modules loaded so far and their imports.
+  module_graph: Rc<RefCell<LoadedModuleGraph>>,
+  /// The inspector server the worker registered on, if any.
+  maybe_inspector_server: Option<Arc<InspectorServer>>,
+  /// Set when `measure_worker_creation` is set.
+  creation_timings: Option<WorkerCreationTimings>,
+  /// Set when `max_egress_bytes` is set.
+  egress_budget: Option<deno_fetch::FetchByteBudget>,
+  /// Created when `sandbox_temp_dir` is set, and removed when `run` finishes
+  /// or the worker is dropped.
+  sandbox_temp_dir: Option<tempfile::TempDir>,
+  /// Shares its state with the permissions the worker checks against.
+  permissions: PermissionsContainer,
+  /// The options the worker was bootstrapped with.
+  bootstrap_options: BootstrapOptions,
+  /// Signalled by the threads copying the output of the worker when
+  /// `tee_output` or `max_output_bytes` is set, waited for when `run`
+  /// finishes.
+  stdio_pipe_threads: Vec<oneshot::Receiver<()>>,
+  /// The resources of the worker writing into those pipes, i.e. its stdout
+  /// and stderr.
+  stdio_pipe_rids: Vec<ResourceId>,
+  /// The stdio web workers of this worker are created with.
+  web_worker_stdio: WebWorkerStdio,
 }
 
 impl CliMainWorker {
@@ -145,18 +791,172 @@ impl CliMainWorker {
     self.worker
   }
 
+  pub fn main_worker(&self) -> &MainWorker {
+    &self.worker
+  }
+
+  pub fn main_worker_mut(&mut self) -> &mut MainWorker {
+    &mut self.worker
+  }
+
   pub async fn setup_repl(&mut self) -> Result<(), AnyError> {
     self.worker.run_event_loop(false).await?;
     Ok(())
   }
 
   pub async fn run(&mut self) -> Result<i32, AnyError> {
+    let result = match self.shared.options.execution_deadline {
+      Some(deadline) => match self.run_with_deadline(deadline).await {
+        Ok(Some(exit_code)) => Ok(exit_code),
+        Ok(None) => {
+          Err(generic_error("execution terminated: deadline exceeded"))
+        }
+        Err(err) => Err(err),
+      },
+      None => self.run_without_deadline().await,
+    };
+    self.join_stdio_pipe_threads().await;
+    if let Some(temp_dir) = self.sandbox_temp_dir.take() {
+      let path = temp_dir.path().to_path_buf();
+      // don't let a failed cleanup hide the outcome of the script
+      if let Err(err) = temp_dir.close() {
+        log::warn!(
+          "{} Failed to remove the sandbox temp directory {}: {:#}",
+          colors::yellow("Warning"),
+          path.display(),
+          err
+        );
+      }
+    }
+    result.map_err(|error| self.redact_error(error))
+  }
+
+  /// Waits until the output of the worker was copied to its destination, so
+  /// `tee_output` holds all of it once `run` returns.
+  ///
+  /// The threads copying the output only finish once every write end of
+  /// their pipe is closed, so this closes the stdout and stderr of the worker
+  /// and stops handing them to new web workers first. Web workers and
+  /// subprocesses that are still running keep their ends open, so threads
+  /// that don't finish within [`STDIO_PIPE_DRAIN_TIMEOUT`] are left to finish
+  /// in the background.
+  async fn join_stdio_pipe_threads(&mut self) {
+    if self.stdio_pipe_threads.is_empty() {
+      return;
+    }
+    self.web_worker_stdio.lock().take();
+    {
+      let op_state = self.worker.js_runtime.op_state();
+      let mut op_state = op_state.borrow_mut();
+      for rid in std::mem::take(&mut self.stdio_pipe_rids) {
+        let _ = op_state.resource_table.close(rid);
+      }
+    }
+    let finished =
+      future::join_all(std::mem::take(&mut self.stdio_pipe_threads));
+    let _ = tokio::time::timeout(STDIO_PIPE_DRAIN_TIMEOUT, finished).await;
+  }
+
+  /// Runs the main module like [`Self::run`], and returns the exit code along
+  /// with statistics about how the event loop behaved during the run.
+  ///
+  /// Requires the `collect_event_loop_stats` option.
+  pub async fn run_with_stats(
+    &mut self,
+  ) -> Result<(i32, EventLoopStats), AnyError> {
+    if !self.shared.options.collect_event_loop_stats {
+      bail!("Running with stats requires the collect_event_loop_stats option.");
+    }
+    let exit_code = self.run().await?;
+    let stats = self.worker.event_loop_stats().unwrap_or_default();
+    Ok((exit_code, stats))
+  }
+
+  /// Runs the main module for at most `budget` and returns whether it
+  /// completed in time, along with the value the script stored in
+  /// `globalThis.partialResult` to report its progress so far. A run that
+  /// didn't complete skips its teardown, like with `execution_deadline`.
+  pub async fn run_with_budget_and_partial(
+    &mut self,
+    budget: Duration,
+  ) -> Result<PartialResult, AnyError> {
+    let exit_code = self.run_with_deadline(budget).await?;
+    let value = self.worker.js_runtime.execute_script_static(
+      located_script_name!(),
+      "globalThis.partialResult",
+    )?;
+    let scope = &mut self.worker.js_runtime.handle_scope();
+    let value = v8::Local::new(scope, value);
+    let state = if value.is_undefined() {
+      None
+    } else {
+      Some(serde_v8::from_v8::<serde_json::Value>(scope, value)?)
+    };
+    Ok(PartialResult {
+      completed: exit_code.is_some(),
+      exit_code,
+      state,
+    })
+  }
+
+  /// Runs the main module, returning `None` if it was terminated because it
+  /// didn't finish before `deadline`.
+  ///
+  /// A terminated run is dropped wherever it was, so the teardown at its end
+  /// is skipped: `unload` is not dispatched, coverage is not written and the
+  /// HMR runner is not stopped. The isolate can still be used afterwards.
+  async fn run_with_deadline(
+    &mut self,
+    deadline: Duration,
+  ) -> Result<Option<i32>, AnyError> {
+    // Use a separate thread, so the deadline is also enforced while the
+    // script is stuck in a synchronous loop and the event loop can't run.
+    let deadline_exceeded = Arc::new(AtomicBool::new(false));
+    let (cancel_tx, cancel_rx) = std::sync::mpsc::channel::<()>();
+    let watchdog = std::thread::spawn({
+      let deadline_exceeded = deadline_exceeded.clone();
+      let isolate_handle = self.isolate_handle.clone();
+      move || {
+        if let Err(RecvTimeoutError::Timeout) = cancel_rx.recv_timeout(deadline)
+        {
+          deadline_exceeded.store(true, Ordering::SeqCst);
+          isolate_handle.terminate_execution();
+        }
+      }
+    });
+
+    // the timeout covers scripts that are waiting on the event loop instead
+    let result =
+      tokio::time::timeout(deadline, self.run_without_deadline()).await;
+    // Wait for the watchdog before reading the flag, otherwise it could
+    // terminate the isolate after the termination was cancelled below. It
+    // returns right away once the channel is closed.
+    drop(cancel_tx);
+    let _ = watchdog.join();
+    match result {
+      Ok(result) if !deadline_exceeded.load(Ordering::SeqCst) => {
+        result.map(Some)
+      }
+      _ => {
+        self
+          .worker
+          .js_runtime
+          .v8_isolate()
+          .cancel_terminate_execution();
+        Ok(None)
+      }
+    }
+  }
+
+  async fn run_without_deadline(&mut self) -> Result<i32, AnyError> {
     let mut maybe_coverage_collector =
       self.maybe_setup_coverage_collector().await?;
     let mut maybe_hmr_runner = self.maybe_setup_hmr_runner().await?;
 
     log::debug!("main_module {}", self.main_module);
 
+    self.preload_modules().await?;
+
     if self.is_main_cjs {
       deno_node::load_cjs_module(
         &mut self.worker.js_runtime,
@@ -168,7 +968,7 @@ impl CliMainWorker {
       self.execute_main_module_possibly_with_npm().await?;
     }
 
-    self.worker.dispatch_load_event(located_script_name!())?;
+    self.dispatch_lifecycle_event_if_listened("load")?;
 
     loop {
       if let Some(hmr_runner) = maybe_hmr_runner.as_mut() {
@@ -192,6 +992,17 @@ impl CliMainWorker {
             .change_restart_mode(WatcherRestartMode::Automatic);
           return Err(e);
         }
+      } else if let Some(worker_errors) = self.maybe_worker_errors.as_mut() {
+        let event_loop_future = self
+          .worker
+          .run_event_loop(maybe_coverage_collector.is_none())
+          .boxed_local();
+        select! {
+          result = event_loop_future => result?,
+          Some(message) = worker_errors.recv() => {
+            bail!("Uncaught (in worker) {}", message);
+          }
+        }
       } else {
         self
           .worker
@@ -199,15 +1010,20 @@ impl CliMainWorker {
           .await?;
       }
 
-      if !self
-        .worker
-        .dispatch_beforeunload_event(located_script_name!())?
-      {
+      if !self.dispatch_lifecycle_event_if_listened("beforeunload")? {
         break;
       }
     }
 
-    self.worker.dispatch_unload_event(located_script_name!())?;
+    // with a debugger that attached before, the event loop above already
+    // waited for it to disconnect
+    if self.shared.options.inspect_wait
+      && !self.worker.has_inspector_session_attached()
+    {
+      self.wait_for_inspector_to_attach().await?;
+    }
+
+    self.dispatch_lifecycle_event_if_listened("unload")?;
 
     if let Some(coverage_collector) = maybe_coverage_collector.as_mut() {
       self
@@ -230,7 +1046,37 @@ impl CliMainWorker {
         .await?;
     }
 
-    Ok(self.worker.exit_code())
+    let exit_code = self.worker.exit_code();
+    Ok(match &self.shared.options.exit_code_mapper {
+      Some(exit_code_mapper) => exit_code_mapper(exit_code),
+      None => exit_code,
+    })
+  }
+
+  /// Dispatches the "load", "beforeunload" or "unload" event, skipping the
+  /// dispatch entirely if the script didn't register any listeners for it.
+  ///
+  /// For "beforeunload", returns whether the event was prevented, which is
+  /// never the case without listeners. For the other events, returns whether
+  /// the event was dispatched.
+  pub fn dispatch_lifecycle_event_if_listened(
+    &mut self,
+    event_name: &'static str,
+  ) -> Result<bool, AnyError> {
+    if !self.worker.has_global_event_listeners(event_name) {
+      return Ok(false);
+    }
+    match event_name {
+      "load" => self.worker.dispatch_load_event(located_script_name!())?,
+      "beforeunload" => {
+        return self
+          .worker
+          .dispatch_beforeunload_event(located_script_name!())
+      }
+      "unload" => self.worker.dispatch_unload_event(located_script_name!())?,
+      _ => bail!("Unknown lifecycle event: {event_name}"),
+    }
+    Ok(true)
   }
 
   pub async fn run_for_watcher(self) -> Result<(), AnyError> {
@@ -252,13 +1098,19 @@ impl CliMainWorker {
 
       /// Execute the given main module emitting load and unload events before and after execution
       /// respectively.
+      ///
+      /// When the `watch_lifecycle_events` option is off, neither event is
+      /// dispatched.
       pub async fn execute(&mut self) -> Result<(), AnyError> {
+        let lifecycle_events = self.inner.shared.options.watch_lifecycle_events;
         self.inner.execute_main_module_possibly_with_npm().await?;
-        self
-          .inner
-          .worker
-          .dispatch_load_event(located_script_name!())?;
-        self.pending_unload = true;
+        if lifecycle_events {
+          self
+            .inner
+            .worker
+            .dispatch_load_event(located_script_name!())?;
+          self.pending_unload = true;
+        }
 
         let result = loop {
           match self.inner.worker.run_event_loop(false).await {
@@ -279,10 +1131,12 @@ impl CliMainWorker {
 
         result?;
 
-        self
-          .inner
-          .worker
-          .dispatch_unload_event(located_script_name!())?;
+        if lifecycle_events {
+          self
+            .inner
+            .worker
+            .dispatch_unload_event(located_script_name!())?;
+        }
 
         Ok(())
       }
@@ -303,97 +1157,957 @@ impl CliMainWorker {
     executor.execute().await
   }
 
-  pub async fn execute_main_module_possibly_with_npm(
+  /// Polls the event loop at most `max_turns` times, running the timers,
+  /// microtasks and op completions that are ready each time, without
+  /// waiting for more to become ready. Returns whether work remains, so
+  /// several workers can be driven in turn on one thread.
+  pub async fn run_event_loop_turns(
     &mut self,
-  ) -> Result<(), AnyError> {
-    let id = self.worker.preload_main_module(&self.main_module).await?;
-    self.evaluate_module_possibly_with_npm(id).await
+    max_turns: usize,
+  ) -> Result<bool, AnyError> {
+    for _ in 0..max_turns {
+      let poll_result = std::future::poll_fn(|cx| {
+        Poll::Ready(
+          self
+            .worker
+            .js_runtime
+            .poll_event_loop(cx, PollEventLoopOptions::default()),
+        )
+      })
+      .await;
+      match poll_result {
+        Poll::Ready(result) => return result.map(|()| false),
+        Poll::Pending => tokio::task::yield_now().await,
+      }
+    }
+    Ok(true)
   }
 
-  pub async fn execute_side_module_possibly_with_npm(
+  /// Keeps the event loop alive until a debugger attaches and disconnects
+  /// again, or until `inspect_wait_timeout` elapses without any debugger
+  /// attaching. Only used when no debugger attached during the run.
+  async fn wait_for_inspector_to_attach(&mut self) -> Result<(), AnyError> {
+    let timeout = self
+      .shared
+      .options
+      .inspect_wait_timeout
+      .unwrap_or(DEFAULT_INSPECT_WAIT_TIMEOUT);
+    let mut timeout = tokio::time::sleep(timeout).boxed();
+    let mut attached = false;
+    std::future::poll_fn(|cx| {
+      let poll_result = self.worker.js_runtime.poll_event_loop(
+        cx,
+        PollEventLoopOptions {
+          wait_for_inspector: true,
+          ..Default::default()
+        },
+      );
+      if self
+        .worker
+        .js_runtime
+        .inspector()
+        .borrow()
+        .has_active_sessions()
+      {
+        attached = true;
+      }
+      match poll_result {
+        Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+        // the debugger attached and has since disconnected
+        Poll::Ready(Ok(())) if attached => Poll::Ready(Ok(())),
+        _ if !attached && timeout.poll_unpin(cx).is_ready() => {
+          Poll::Ready(Ok(()))
+        }
+        _ => Poll::Pending,
+      }
+    })
+    .await
+  }
+
+  /// Loads and compiles the modules listed in `preload_modules` without
+  /// evaluating them, so that importing them later is cheap.
+  async fn preload_modules(&mut self) -> Result<(), AnyError> {
+    let mut errors = Vec::new();
+    for specifier in &self.shared.options.preload_modules {
+      if let Err(err) = self.worker.preload_side_module(specifier).await {
+        errors.push(format!("{specifier}: {err:#}"));
+      }
+    }
+    if !errors.is_empty() {
+      bail!("Failed preloading modules:\n  {}", errors.join("\n  "));
+    }
+    Ok(())
+  }
+
+  pub async fn execute_main_module_possibly_with_npm(
     &mut self,
   ) -> Result<(), AnyError> {
-    let id = self.worker.preload_side_module(&self.main_module).await?;
-    self.evaluate_module_possibly_with_npm(id).await
+    let id = self
+      .worker
+      .preload_main_module(&self.main_module)
+      .await
+      .map_err(|error| self.redact_error(error))?;
+    self
+      .evaluate_module_possibly_with_npm(id)
+      .await
+      .map_err(|error| self.redact_error(error))
   }
 
-  async fn evaluate_module_possibly_with_npm(
+  /// Loads and compiles the main module and its dependencies, surfacing
+  /// resolution and syntax errors, without evaluating any of them.
+  pub async fn validate_main_module(&mut self) -> Result<(), AnyError> {
+    self.worker.preload_main_module(&self.main_module).await?;
+    Ok(())
+  }
+
+  /// Returns the function exported as `name` by the evaluated module `id`, so
+  /// it can be invoked with [`Self::call_export`].
+  pub fn get_export_function(
     &mut self,
     id: ModuleId,
-  ) -> Result<(), AnyError> {
-    self.worker.evaluate_module(id).await
+    name: &str,
+  ) -> Result<v8::Global<v8::Function>, AnyError> {
+    let namespace = self.worker.js_runtime.get_module_namespace(id)?;
+    let scope = &mut self.worker.js_runtime.handle_scope();
+    let namespace = v8::Local::new(scope, namespace);
+    let Some(key) = v8::String::new(scope, name) else {
+      bail!("Export name \"{}\" is too long.", name);
+    };
+    let value = namespace.get(scope, key.into());
+    let Some(function) =
+      value.and_then(|value| v8::Local::<v8::Function>::try_from(value).ok())
+    else {
+      bail!("Module does not export a function named \"{}\".", name);
+    };
+    Ok(v8::Global::new(scope, function))
   }
 
-  pub async fn maybe_setup_coverage_collector(
+  /// Calls a function returned by [`Self::get_export_function`] with the
+  /// given arguments, running the event loop until the returned promise, if
+  /// any, settles. Returns the result converted to JSON.
+  pub async fn call_export(
     &mut self,
-  ) -> Result<Option<CoverageCollector>, AnyError> {
-    if let Some(coverage_dir) = &self.shared.options.coverage_dir {
-      let session = self.worker.create_inspector_session().await;
+    function: &v8::Global<v8::Function>,
+    args: &[serde_json::Value],
+  ) -> Result<serde_json::Value, AnyError> {
+    let args = {
+      let scope = &mut self.worker.js_runtime.handle_scope();
+      args
+        .iter()
+        .map(|arg| {
+          let value = serde_v8::to_v8(scope, arg)?;
+          Ok(v8::Global::new(scope, value))
+        })
+        .collect::<Result<Vec<_>, AnyError>>()?
+    };
+    let call = self.worker.js_runtime.call_with_args(function, &args);
+    let result = self
+      .worker
+      .js_runtime
+      .with_event_loop_promise(call, PollEventLoopOptions::default())
+      .await?;
+    let scope = &mut self.worker.js_runtime.handle_scope();
+    let result = v8::Local::new(scope, result);
+    if result.is_undefined() {
+      return Ok(serde_json::Value::Null);
+    }
+    Ok(serde_v8::from_v8::<serde_json::Value>(scope, result)?)
+  }
 
-      let coverage_dir = PathBuf::from(coverage_dir);
-      let mut coverage_collector =
-        tools::coverage::CoverageCollector::new(coverage_dir, session);
-      self
-        .worker
-        .js_runtime
-        .with_event_loop_future(
-          coverage_collector.start_collecting().boxed_local(),
-          PollEventLoopOptions::default(),
-        )
-        .await?;
-      Ok(Some(coverage_collector))
-    } else {
-      Ok(None)
+  /// Evaluates `source` as a script, running the event loop until the
+  /// result settles if it is a promise, and returns the result serialized
+  /// with `JSON.stringify`.
+  pub async fn eval_to_json(
+    &mut self,
+    source: &str,
+  ) -> Result<serde_json::Value, AnyError> {
+    let value = self
+      .worker
+      .js_runtime
+      .execute_script(located_script_name!(), source.to_string().into())?;
+    let resolve = self.worker.js_runtime.resolve(value);
+    let value = self
+      .worker
+      .js_runtime
+      .with_event_loop_promise(resolve, PollEventLoopOptions::default())
+      .await?;
+    let scope = &mut self.worker.js_runtime.handle_scope();
+    let value = v8::Local::new(scope, value);
+    if value.is_undefined() || value.is_function() || value.is_symbol() {
+      let type_of = value.type_of(scope).to_rust_string_lossy(scope);
+      bail!("The expression evaluated to a value of type {type_of}, which can't be serialized to JSON.");
     }
+    let tc_scope = &mut v8::TryCatch::new(scope);
+    let Some(json) = v8::json::stringify(tc_scope, value) else {
+      let message = tc_scope
+        .exception()
+        .map(|exception| exception.to_rust_string_lossy(tc_scope))
+        .unwrap_or_default();
+      bail!(
+        "The result of the expression can't be serialized to JSON: {message}"
+      );
+    };
+    let json = json.to_rust_string_lossy(tc_scope);
+    Ok(serde_json::from_str(&json)?)
   }
 
-  pub async fn maybe_setup_hmr_runner(
+  /// Resolves a value returned by [`Self::execute_script_static`]. If it is
+  /// a promise, the event loop is run until it settles. A rejection is
+  /// returned as an error with the formatted JavaScript error as message.
+  pub async fn resolve_value(
     &mut self,
-  ) -> Result<Option<HmrRunner>, AnyError> {
-    if !self.shared.options.hmr {
-      return Ok(None);
+    global: v8::Global<v8::Value>,
+  ) -> Result<v8::Global<v8::Value>, AnyError> {
+    let resolve = self.worker.js_runtime.resolve(global);
+    let result = self
+      .worker
+      .js_runtime
+      .with_event_loop_promise(resolve, PollEventLoopOptions::default())
+      .await;
+    result.map_err(|error| match error.downcast_ref::<JsError>() {
+      Some(_) => generic_error(self.format_error(&error)),
+      None => error,
+    })
+  }
+
+  /// Formats an error returned by the worker for display, applying the
+  /// `error_redactor` option.
+  pub fn format_error(&self, error: &AnyError) -> String {
+    let message = match error.downcast_ref::<JsError>() {
+      Some(js_error) => format_js_error(js_error),
+      None => format!("{error:?}"),
+    };
+    match &self.shared.options.error_redactor {
+      Some(redactor) => redactor(message),
+      None => message,
     }
+  }
 
-    let watcher_communicator =
-      self.shared.maybe_file_watcher_communicator.clone().unwrap();
-    let emitter = self.shared.emitter.clone().unwrap();
+  /// Replaces a JavaScript error with one carrying its formatted message, so
+  /// the `error_redactor` option also applies to errors that are returned.
+  fn redact_error(&self, error: AnyError) -> AnyError {
+    if self.shared.options.error_redactor.is_none()
+      || error.downcast_ref::<JsError>().is_none()
+    {
+      return error;
+    }
+    generic_error(self.format_error(&error))
+  }
 
-    let session = self.worker.create_inspector_session().await;
-    let mut hmr_runner = HmrRunner::new(emitter, session, watcher_communicator);
+  /// Returns true if the exit code returned by `run` was set by the script,
+  /// with `Deno.exit()` or `process.exitCode`, rather than
+  /// being the default of 0 for running to completion.
+  pub fn exit_was_explicit(&self) -> bool {
+    self.worker.exit_was_explicit()
+  }
 
-    self
-      .worker
-      .js_runtime
-      .with_event_loop_future(
-        hmr_runner.start().boxed_local(),
-        PollEventLoopOptions::default(),
-      )
-      .await?;
+  /// Returns the op calls made since the worker was created or
+  /// [`Self::reset_op_metrics`] was last called. Empty unless the
+  /// `enable_op_summary_metrics` option is set.
+  pub fn op_metrics_snapshot(&self) -> OpMetricsSnapshot {
+    OpMetricsSnapshot {
+      ops: self.worker.op_call_metrics().unwrap_or_default(),
+    }
+  }
 
-    Ok(Some(hmr_runner))
+  /// Starts a new window for [`Self::op_metrics_snapshot`].
+  pub fn reset_op_metrics(&mut self) {
+    self.worker.reset_op_call_metrics();
   }
 
-  pub fn execute_script_static(
-    &mut self,
-    name: &'static str,
-    source_code: &'static str,
-  ) -> Result<v8::Global<v8::Value>, AnyError> {
+  /// Returns the names of the extensions registered in the worker, including
+  /// the custom extensions passed to `create_custom_worker`. Extensions are
+  /// listed even if registering their ops was skipped with
+  /// `skip_op_registration`.
+  pub fn loaded_extension_names(&self) -> Vec<String> {
     self
       .worker
-      .js_runtime
-      .execute_script_static(name, source_code)
+      .extension_names()
+      .iter()
+      .map(|name| name.to_string())
+      .collect()
   }
-}
 
-pub struct CliMainWorkerFactory {
-  shared: Arc<SharedWorkerState>,
-}
+  /// Returns the modules loaded into the worker so far, in load order, each
+  /// with the specifiers it imports directly. Dynamic imports are included
+  /// once they were resolved.
+  pub fn module_graph_snapshot(
+    &self,
+  ) -> Vec<(ModuleSpecifier, Vec<ModuleSpecifier>)> {
+    self.module_graph.borrow().snapshot()
+  }
 
-impl CliMainWorkerFactory {
-  #[allow(clippy::too_many_arguments)]
-  pub fn new(
-    storage_key_resolver: StorageKeyResolver,
-    subcommand: DenoSubcommand,
+  /// Returns true if the main module is parked on a top-level await that
+  /// can't make progress, rather than having completed.
+  pub fn has_pending_top_level_await(&self) -> bool {
+    self.worker.has_pending_top_level_await()
+  }
+
+  /// Returns how long the phases of creating this worker took, when the
+  /// `measure_worker_creation` option is set.
+  pub fn creation_timings(&self) -> Option<WorkerCreationTimings> {
+    self.creation_timings
+  }
+
+  /// Returns how many body bytes the main worker sent and received with
+  /// `fetch` so far. Only counted when the `max_egress_bytes` option is set.
+  pub fn egress_bytes(&self) -> u64 {
+    self
+      .egress_budget
+      .as_ref()
+      .map(|budget| budget.used())
+      .unwrap_or(0)
+  }
+
+  /// Returns the permissions of the main worker. Revoking or granting
+  /// permissions through it takes effect in the running worker.
+  pub fn permissions(&self) -> &PermissionsContainer {
+    &self.permissions
+  }
+
+  /// Returns the bootstrap options the main worker was created with, e.g.
+  /// to check which args and unstable features it got.
+  pub fn bootstrap_snapshot(&self) -> &BootstrapOptions {
+    &self.bootstrap_options
+  }
+
+  /// Forces a full garbage collection of the isolate, e.g. before sampling
+  /// [`heap_statistics`](Self::heap_statistics) to measure retained memory.
+  /// Intended for diagnostics and tests, not for production use.
+  pub fn request_gc(&mut self) {
+    self
+      .worker
+      .js_runtime
+      .v8_isolate()
+      .low_memory_notification();
+  }
+
+  /// Returns the current heap statistics of the isolate.
+  pub fn heap_statistics(&mut self) -> v8::HeapStatistics {
+    let mut stats = v8::HeapStatistics::default();
+    self
+      .worker
+      .js_runtime
+      .v8_isolate()
+      .get_heap_statistics(&mut stats);
+    stats
+  }
+
+  /// Changes whether deprecated API warnings are shown, and whether they are
+  /// shown with a stack trace for every call site, for the rest of the run.
+  pub fn set_deprecated_api_warning(&mut self, disable: bool, verbose: bool) {
+    self.worker.set_deprecated_api_warning(disable, verbose);
+  }
+
+  /// Returns the names of the unstable features the worker was booted with.
+  pub fn enabled_unstable_features(&self) -> Vec<&'static str> {
+    crate::UNSTABLE_GRANULAR_FLAGS
+      .iter()
+      .map(|(feature_name, _, _)| *feature_name)
+      .filter(|feature_name| self.shared.feature_checker.check(feature_name))
+      .collect()
+  }
+
+  /// Executes the main module as a side module.
+  ///
+  /// `wait_for_inspector_session` overrides whether to wait for a debugger
+  /// to attach before evaluating the module. When `None`, the `inspect_wait`
+  /// option is used.
+  pub async fn execute_side_module_possibly_with_npm(
+    &mut self,
+    wait_for_inspector_session: Option<bool>,
+  ) -> Result<(), AnyError> {
+    let main_module = self.main_module.clone();
+    self
+      .execute_side_module_by_specifier_possibly_with_npm(
+        &main_module,
+        wait_for_inspector_session,
+      )
+      .await
+  }
+
+  /// Loads and executes the given module as a side module.
+  ///
+  /// `wait_for_inspector_session` overrides whether to wait for a debugger
+  /// to attach before evaluating the module. When `None`, the `inspect_wait`
+  /// option is used.
+  pub async fn execute_side_module_by_specifier_possibly_with_npm(
+    &mut self,
+    specifier: &ModuleSpecifier,
+    wait_for_inspector_session: Option<bool>,
+  ) -> Result<(), AnyError> {
+    let id = self
+      .worker
+      .preload_side_module(specifier)
+      .await
+      .map_err(|error| self.redact_error(error))?;
+    let Some(wait_for_inspector_session) = wait_for_inspector_session else {
+      return self
+        .evaluate_module_possibly_with_npm(id)
+        .await
+        .map_err(|error| self.redact_error(error));
+    };
+
+    // waiting is only possible when there's an inspector to wait on
+    let wait_for_inspector_session =
+      wait_for_inspector_session && self.maybe_inspector_server.is_some();
+    let previous = self.worker.should_wait_for_inspector_session();
+    self
+      .worker
+      .set_should_wait_for_inspector_session(wait_for_inspector_session);
+    let result = self.evaluate_module_possibly_with_npm(id).await;
+    self.worker.set_should_wait_for_inspector_session(previous);
+    result.map_err(|error| self.redact_error(error))
+  }
+
+  /// Registers the JavaScript module `source` under `specifier` and
+  /// evaluates it as a side module. Its imports are resolved and loaded
+  /// like those of any other module.
+  pub async fn execute_inline_module(
+    &mut self,
+    specifier: &str,
+    source: String,
+  ) -> Result<ModuleId, AnyError> {
+    let specifier = ModuleSpecifier::parse(specifier).with_context(|| {
+      format!("Invalid inline module specifier: {specifier}")
+    })?;
+    self.inline_modules.borrow_mut().modules.insert(
+      specifier.clone(),
+      InMemoryModule {
+        module_type: ModuleType::JavaScript,
+        code: source.into(),
+      },
+    );
+    let id = self
+      .worker
+      .preload_side_module(&specifier)
+      .await
+      .map_err(|error| self.redact_error(error))?;
+    self
+      .evaluate_module_possibly_with_npm(id)
+      .await
+      .map_err(|error| self.redact_error(error))?;
+    Ok(id)
+  }
+
+  /// Evaluates the main module and returns the benchmarks it registered,
+  /// without running them. `filter` uses the same syntax as
+  /// `deno bench --filter`.
+  ///
+  /// The worker must have been created with the `deno_bench` extension.
+  pub async fn list_benchmarks(
+    &mut self,
+    filter: Option<&str>,
+  ) -> Result<Vec<BenchInfo>, AnyError> {
+    if !self
+      .worker
+      .js_runtime
+      .op_state()
+      .borrow()
+      .has::<ops::bench::BenchContainer>()
+    {
+      bail!("Listing benchmarks requires a worker with the bench extension.");
+    }
+    self.execute_side_module_possibly_with_npm(None).await?;
+    self
+      .worker
+      .run_up_to_duration(Duration::from_millis(0))
+      .await?;
+
+    let filter = TestFilter::from_flag(&filter.map(|f| f.to_string()));
+    let state_rc = self.worker.js_runtime.op_state();
+    let state = state_rc.borrow();
+    let benchmarks = state
+      .borrow::<ops::bench::BenchContainer>()
+      .0
+      .iter()
+      .map(|(desc, _)| desc)
+      .filter(|desc| filter.includes(&desc.name))
+      .map(|desc| BenchInfo {
+        name: desc.name.clone(),
+        origin: desc.origin.clone(),
+        group: desc.group.clone(),
+        baseline: desc.baseline,
+        ignore: desc.ignore,
+        only: desc.only,
+      })
+      .collect();
+    Ok(benchmarks)
+  }
+
+  async fn evaluate_module_possibly_with_npm(
+    &mut self,
+    id: ModuleId,
+  ) -> Result<(), AnyError> {
+    self.worker.evaluate_module(id).await
+  }
+
+  pub async fn maybe_setup_coverage_collector(
+    &mut self,
+  ) -> Result<Option<CoverageCollector>, AnyError> {
+    if let Some(coverage_dir) = &self.shared.options.coverage_dir {
+      if self.manual_coverage_collector.is_some() {
+        bail!("Coverage is already being collected with `start_coverage`, which cannot be combined with `coverage_dir`.");
+      }
+      let session = self.worker.create_inspector_session().await;
+
+      let coverage_dir = PathBuf::from(coverage_dir);
+      let mut coverage_collector = tools::coverage::CoverageCollector::new(
+        coverage_dir,
+        session,
+        self.shared.options.coverage_file_namer.clone(),
+      );
+      self
+        .worker
+        .js_runtime
+        .with_event_loop_future(
+          coverage_collector.start_collecting().boxed_local(),
+          PollEventLoopOptions::default(),
+        )
+        .await?;
+      Ok(Some(coverage_collector))
+    } else {
+      Ok(None)
+    }
+  }
+
+  /// Starts collecting coverage into `coverage_dir` until `stop_coverage`
+  /// is called. This can be used to only measure coverage for a part of the
+  /// execution and can't be combined with the `coverage_dir` option.
+  pub async fn start_coverage(
+    &mut self,
+    coverage_dir: PathBuf,
+  ) -> Result<(), AnyError> {
+    if self.shared.options.coverage_dir.is_some() {
+      bail!("Coverage is collected automatically because `coverage_dir` is set, so it can't be started manually.");
+    }
+    if self.manual_coverage_collector.is_some() {
+      bail!("Coverage collection was already started.");
+    }
+
+    let session = self.worker.create_inspector_session().await;
+    let mut coverage_collector = tools::coverage::CoverageCollector::new(
+      coverage_dir,
+      session,
+      self.shared.options.coverage_file_namer.clone(),
+    );
+    self
+      .worker
+      .js_runtime
+      .with_event_loop_future(
+        coverage_collector.start_collecting().boxed_local(),
+        PollEventLoopOptions::default(),
+      )
+      .await?;
+    self.manual_coverage_collector = Some(coverage_collector);
+    Ok(())
+  }
+
+  /// Writes the coverage collected since `start_coverage` or the previous
+  /// flush to disk, without stopping the collection.
+  pub async fn flush_coverage(&mut self) -> Result<(), AnyError> {
+    let Some(coverage_collector) = self.manual_coverage_collector.as_mut()
+    else {
+      bail!("Coverage collection was not started.");
+    };
+    self
+      .worker
+      .js_runtime
+      .with_event_loop_future(
+        coverage_collector.flush().boxed_local(),
+        PollEventLoopOptions::default(),
+      )
+      .await
+  }
+
+  /// Stops the coverage collection started with `start_coverage` and writes
+  /// the collected coverage to disk.
+  pub async fn stop_coverage(&mut self) -> Result<(), AnyError> {
+    let Some(mut coverage_collector) = self.manual_coverage_collector.take()
+    else {
+      bail!("Coverage collection was not started.");
+    };
+    self
+      .worker
+      .js_runtime
+      .with_event_loop_future(
+        coverage_collector.stop_collecting().boxed_local(),
+        PollEventLoopOptions::default(),
+      )
+      .await
+  }
+
+  /// Writes a lockfile to `path` containing the remote modules loaded into
+  /// the worker so far and the npm packages that were resolved, so later runs
+  /// can be verified against it.
+  pub fn write_lockfile(&self, path: &Path) -> Result<(), AnyError> {
+    let mut lockfile = Lockfile::new(path.to_path_buf(), true)?;
+    let loaded = self.module_graph.borrow().loaded();
+    self
+      .shared
+      .module_loader_factory
+      .lock_modules(&loaded, &mut lockfile);
+    if let Some(npm_resolver) = self.shared.npm_resolver.as_managed() {
+      npm_resolver.lock(&mut lockfile)?;
+    }
+    lockfile.write().with_context(|| {
+      format!("Failed writing lockfile to {}.", path.display())
+    })
+  }
+
+  /// Runs the event loop until the main worker and every web worker it
+  /// spawned, directly or not, are idle: none of them has pending work other
+  /// than waiting for messages, and no message is in flight between them.
+  ///
+  /// Requires the `track_worker_activity` option.
+  pub async fn run_until_all_idle(&mut self) -> Result<(), AnyError> {
+    if !self.shared.options.track_worker_activity {
+      bail!("Waiting for all workers to be idle requires the track_worker_activity option.");
+    }
+    self.worker.run_until_all_idle().await
+  }
+
+  /// Moves the virtual clock of the worker forward by `ms` milliseconds. The
+  /// timers that became due fire the next time the event loop is run.
+  ///
+  /// Requires the `virtual_time` option.
+  pub fn advance_time(&mut self, ms: u64) -> Result<(), AnyError> {
+    let Some(clock) = &self.virtual_clock else {
+      bail!("Advancing the time requires the virtual_time option.");
+    };
+    clock.advance(Duration::from_millis(ms));
+    Ok(())
+  }
+
+  /// Returns the ids of the web workers spawned by this worker that are still
+  /// running.
+  pub fn web_worker_ids(&self) -> Vec<WorkerId> {
+    let mut web_workers = self.web_workers.lock();
+    web_workers.retain(|_, handle| !handle.is_terminated());
+    web_workers.keys().copied().collect()
+  }
+
+  /// Signals the web worker with the given id, spawned by this worker, to
+  /// stop. The `unload` event is dispatched in the worker if it gets to poll
+  /// its event loop before the isolate is forcefully terminated. Returns
+  /// `false` if there is no such running worker.
+  pub fn terminate_web_worker(&self, id: WorkerId) -> bool {
+    let mut web_workers = self.web_workers.lock();
+    web_workers.retain(|_, handle| !handle.is_terminated());
+    match web_workers.remove(&id) {
+      Some(handle) => {
+        handle.terminate_with_unload();
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Replaces the emitter used to transpile changed modules for hot module
+  /// replacement, e.g. after the transpile configuration changed. Applies to
+  /// HMR runners set up from now on, like the one of the next `run`. Use
+  /// [`HmrRunner::set_emitter`] to update a runner that is already set up.
+  pub fn update_hmr_emitter(&mut self, emitter: Arc<Emitter>) {
+    self.hmr_emitter = Some(emitter);
+  }
+
+  pub async fn maybe_setup_hmr_runner(
+    &mut self,
+  ) -> Result<Option<HmrRunner>, AnyError> {
+    if !self.shared.options.hmr {
+      return Ok(None);
+    }
+
+    let watcher_communicator =
+      self.shared.maybe_file_watcher_communicator.clone().unwrap();
+    let emitter = self
+      .hmr_emitter
+      .clone()
+      .unwrap_or_else(|| self.shared.emitter.clone().unwrap());
+
+    let session = self.worker.create_inspector_session().await;
+    let mut hmr_runner = HmrRunner::new(emitter, session, watcher_communicator);
+
+    self
+      .worker
+      .js_runtime
+      .with_event_loop_future(
+        hmr_runner.start().boxed_local(),
+        PollEventLoopOptions::default(),
+      )
+      .await?;
+
+    Ok(Some(hmr_runner))
+  }
+
+  /// Dispatches a cancelable event with the given name on the global scope.
+  /// Returns a boolean indicating if the event's default action was
+  /// prevented.
+  ///
+  /// Does not poll event loop, and thus not await any of the event handlers.
+  pub fn dispatch_event(&mut self, name: &str) -> Result<bool, AnyError> {
+    let name = deno_core::serde_json::to_string(name)?;
+    let value = self.worker.execute_script(
+      located_script_name!(),
+      // NOTE: not using `globalThis` here, because user might delete it.
+      // Instead we're using global `dispatchEvent` function which will use a
+      // saved reference to global scope.
+      format!("dispatchEvent(new Event({name}, {{ cancelable: true }}));")
+        .into(),
+    )?;
+    let local_value = value.open(&mut self.worker.js_runtime.handle_scope());
+    Ok(local_value.is_false())
+  }
+
+  /// Returns the marks and measures recorded on the runtime's performance
+  /// timeline.
+  pub fn performance_entries(
+    &mut self,
+  ) -> Result<Vec<PerformanceEntry>, AnyError> {
+    let value = self.worker.js_runtime.execute_script_static(
+      located_script_name!(),
+      r#"performance.getEntries()
+        .filter((e) => e.entryType === "mark" || e.entryType === "measure")
+        .map((e) => ({
+          name: e.name,
+          entryType: e.entryType,
+          startTime: e.startTime,
+          duration: e.duration,
+        }))"#,
+    )?;
+    let scope = &mut self.worker.js_runtime.handle_scope();
+    let value = v8::Local::new(scope, value);
+    Ok(serde_v8::from_v8::<Vec<PerformanceEntry>>(scope, value)?)
+  }
+
+  /// Returns the timers that are still pending, e.g. to find out why the
+  /// event loop doesn't finish.
+  pub fn pending_timers(&mut self) -> Result<Vec<TimerInfo>, AnyError> {
+    let value = self.worker.js_runtime.execute_script_static(
+      located_script_name!(),
+      "Deno[Deno.internal].pendingTimers()",
+    )?;
+    let scope = &mut self.worker.js_runtime.handle_scope();
+    let value = v8::Local::new(scope, value);
+    Ok(serde_v8::from_v8::<Vec<TimerInfo>>(scope, value)?)
+  }
+
+  pub fn execute_script_static(
+    &mut self,
+    name: &'static str,
+    source_code: &'static str,
+  ) -> Result<v8::Global<v8::Value>, AnyError> {
+    self
+      .worker
+      .js_runtime
+      .execute_script_static(name, source_code)
+  }
+}
+
+pub struct CliMainWorkerFactory {
+  shared: Arc<SharedWorkerState>,
+}
+
+/// Builds a [`CliMainWorkerFactory`] without having to pass every argument
+/// of [`CliMainWorkerFactory::new`] positionally.
+#[derive(Default)]
+pub struct CliMainWorkerFactoryBuilder {
+  storage_key_resolver: Option<StorageKeyResolver>,
+  subcommand: Option<DenoSubcommand>,
+  npm_resolver: Option<Arc<dyn CliNpmResolver>>,
+  node_resolver: Option<Arc<NodeResolver>>,
+  blob_store: Option<Arc<BlobStore>>,
+  module_loader_factory: Option<Box<dyn ModuleLoaderFactory>>,
+  root_cert_store_provider: Option<Arc<dyn RootCertStoreProvider>>,
+  fs: Option<Arc<dyn deno_fs::FileSystem>>,
+  emitter: Option<Arc<Emitter>>,
+  maybe_file_watcher_communicator: Option<Arc<WatcherCommunicator>>,
+  maybe_inspector_server: Option<Arc<InspectorServer>>,
+  maybe_lockfile: Option<Arc<Mutex<Lockfile>>>,
+  feature_checker: Option<Arc<FeatureChecker>>,
+  options: Option<CliMainWorkerOptions>,
+  node_ipc: Option<i64>,
+  disable_deprecated_api_warning: bool,
+  verbose_deprecated_api_warning: bool,
+}
+
+impl CliMainWorkerFactoryBuilder {
+  pub fn storage_key_resolver(
+    mut self,
+    storage_key_resolver: StorageKeyResolver,
+  ) -> Self {
+    self.storage_key_resolver = Some(storage_key_resolver);
+    self
+  }
+
+  pub fn subcommand(mut self, subcommand: DenoSubcommand) -> Self {
+    self.subcommand = Some(subcommand);
+    self
+  }
+
+  pub fn npm_resolver(mut self, npm_resolver: Arc<dyn CliNpmResolver>) -> Self {
+    self.npm_resolver = Some(npm_resolver);
+    self
+  }
+
+  pub fn node_resolver(mut self, node_resolver: Arc<NodeResolver>) -> Self {
+    self.node_resolver = Some(node_resolver);
+    self
+  }
+
+  pub fn blob_store(mut self, blob_store: Arc<BlobStore>) -> Self {
+    self.blob_store = Some(blob_store);
+    self
+  }
+
+  pub fn module_loader_factory(
+    mut self,
+    module_loader_factory: Box<dyn ModuleLoaderFactory>,
+  ) -> Self {
+    self.module_loader_factory = Some(module_loader_factory);
+    self
+  }
+
+  pub fn root_cert_store_provider(
+    mut self,
+    root_cert_store_provider: Arc<dyn RootCertStoreProvider>,
+  ) -> Self {
+    self.root_cert_store_provider = Some(root_cert_store_provider);
+    self
+  }
+
+  pub fn fs(mut self, fs: Arc<dyn deno_fs::FileSystem>) -> Self {
+    self.fs = Some(fs);
+    self
+  }
+
+  pub fn emitter(mut self, emitter: Arc<Emitter>) -> Self {
+    self.emitter = Some(emitter);
+    self
+  }
+
+  pub fn file_watcher_communicator(
+    mut self,
+    file_watcher_communicator: Arc<WatcherCommunicator>,
+  ) -> Self {
+    self.maybe_file_watcher_communicator = Some(file_watcher_communicator);
+    self
+  }
+
+  pub fn inspector_server(
+    mut self,
+    inspector_server: Arc<InspectorServer>,
+  ) -> Self {
+    self.maybe_inspector_server = Some(inspector_server);
+    self
+  }
+
+  pub fn lockfile(mut self, lockfile: Arc<Mutex<Lockfile>>) -> Self {
+    self.maybe_lockfile = Some(lockfile);
+    self
+  }
+
+  pub fn feature_checker(
+    mut self,
+    feature_checker: Arc<FeatureChecker>,
+  ) -> Self {
+    self.feature_checker = Some(feature_checker);
+    self
+  }
+
+  pub fn options(mut self, options: CliMainWorkerOptions) -> Self {
+    self.options = Some(options);
+    self
+  }
+
+  pub fn node_ipc(mut self, node_ipc: i64) -> Self {
+    self.node_ipc = Some(node_ipc);
+    self
+  }
+
+  pub fn disable_deprecated_api_warning(
+    mut self,
+    disable_deprecated_api_warning: bool,
+  ) -> Self {
+    self.disable_deprecated_api_warning = disable_deprecated_api_warning;
+    self
+  }
+
+  pub fn verbose_deprecated_api_warning(
+    mut self,
+    verbose_deprecated_api_warning: bool,
+  ) -> Self {
+    self.verbose_deprecated_api_warning = verbose_deprecated_api_warning;
+    self
+  }
+
+  /// Creates the factory, erroring with the names of all required fields
+  /// that were not set.
+  pub fn build(self) -> Result<CliMainWorkerFactory, AnyError> {
+    let mut missing = Vec::new();
+    if self.storage_key_resolver.is_none() {
+      missing.push("storage_key_resolver");
+    }
+    if self.subcommand.is_none() {
+      missing.push("subcommand");
+    }
+    if self.npm_resolver.is_none() {
+      missing.push("npm_resolver");
+    }
+    if self.node_resolver.is_none() {
+      missing.push("node_resolver");
+    }
+    if self.blob_store.is_none() {
+      missing.push("blob_store");
+    }
+    if self.module_loader_factory.is_none() {
+      missing.push("module_loader_factory");
+    }
+    if self.root_cert_store_provider.is_none() {
+      missing.push("root_cert_store_provider");
+    }
+    if self.fs.is_none() {
+      missing.push("fs");
+    }
+    if self.feature_checker.is_none() {
+      missing.push("feature_checker");
+    }
+    if self.options.is_none() {
+      missing.push("options");
+    }
+    if !missing.is_empty() {
+      bail!(
+        "Missing required fields for CliMainWorkerFactory: {}",
+        missing.join(", ")
+      );
+    }
+    Ok(CliMainWorkerFactory::new(
+      self.storage_key_resolver.unwrap(),
+      self.subcommand.unwrap(),
+      self.npm_resolver.unwrap(),
+      self.node_resolver.unwrap(),
+      self.blob_store.unwrap(),
+      self.module_loader_factory.unwrap(),
+      self.root_cert_store_provider.unwrap(),
+      self.fs.unwrap(),
+      self.emitter,
+      self.maybe_file_watcher_communicator,
+      self.maybe_inspector_server,
+      self.maybe_lockfile,
+      self.feature_checker.unwrap(),
+      self.options.unwrap(),
+      self.node_ipc,
+      self.disable_deprecated_api_warning,
+      self.verbose_deprecated_api_warning,
+    ))
+  }
+}
+
+impl CliMainWorkerFactory {
+  #[allow(clippy::too_many_arguments)]
+  pub fn new(
+    storage_key_resolver: StorageKeyResolver,
+    subcommand: DenoSubcommand,
     npm_resolver: Arc<dyn CliNpmResolver>,
     node_resolver: Arc<NodeResolver>,
     blob_store: Arc<BlobStore>,
@@ -410,6 +2124,15 @@ impl CliMainWorkerFactory {
     disable_deprecated_api_warning: bool,
     verbose_deprecated_api_warning: bool,
   ) -> Self {
+    let (error_class_mapper_slot, get_error_class_fn) =
+      match options.error_class_fn_override.clone() {
+        Some(mapper) => {
+          let (slot, get_error_class_fn) =
+            ErrorClassMapperSlot::install(mapper);
+          (Some(slot), get_error_class_fn)
+        }
+        None => (None, &errors::get_error_class_name as GetErrorClassFn),
+      };
     Self {
       shared: Arc::new(SharedWorkerState {
         options,
@@ -432,10 +2155,149 @@ impl CliMainWorkerFactory {
         node_ipc,
         disable_deprecated_api_warning,
         verbose_deprecated_api_warning,
+        remote_module_cache: Default::default(),
+        get_error_class_fn,
+        _error_class_mapper_slot: error_class_mapper_slot,
       }),
     }
   }
 
+  pub fn builder() -> CliMainWorkerFactoryBuilder {
+    CliMainWorkerFactoryBuilder::default()
+  }
+
+  async fn resolve_npm_package_folder(
+    &self,
+    req: &PackageReq,
+    referrer: &ModuleSpecifier,
+  ) -> Result<PathBuf, AnyError> {
+    let npm_resolver = &self.shared.npm_resolver;
+    let err = match npm_resolver
+      .resolve_pkg_folder_from_deno_module_req(req, referrer)
+    {
+      Ok(package_folder) => return Ok(package_folder),
+      Err(err) => err,
+    };
+    if let Some(hook) = &self.shared.options.on_npm_package_missing {
+      if hook(req).await? {
+        return npm_resolver
+          .resolve_pkg_folder_from_deno_module_req(req, referrer)
+          .with_context(|| {
+            format!("Could not find npm package '{req}' after installing it.")
+          });
+      }
+    }
+    Err(err.context(format!(
+      "Could not find npm package '{req}'. Try installing it first by running `deno cache npm:{req}`."
+    )))
+  }
+
+  /// Returns the file that running the npm specifier `req`, like
+  /// `npm:pkg/bin`, would execute, without creating a worker. Unlike worker
+  /// creation, this doesn't install the package if it's missing.
+  pub fn resolve_npm_binary(
+    &self,
+    req: &NpmPackageReqReference,
+    permissions: &PermissionsContainer,
+  ) -> Result<ModuleSpecifier, AnyError> {
+    let referrer = self.npm_main_module_referrer(&*self.shared.fs)?;
+    let package_folder = self
+      .shared
+      .npm_resolver
+      .resolve_pkg_folder_from_deno_module_req(req.req(), &referrer)
+      .with_context(|| {
+        format!(
+          "Could not find npm package '{0}'. Try installing it first by running `deno cache npm:{0}`.",
+          req.req()
+        )
+      })?;
+    let node_resolution = self.resolve_binary_entrypoint(
+      &package_folder,
+      req.sub_path(),
+      permissions,
+    )?;
+    Ok(node_resolution.into_url())
+  }
+
+  /// Returns the referrer used to resolve npm main modules, which determines
+  /// the package.json that governs the resolution.
+  fn npm_main_module_referrer(
+    &self,
+    fs: &dyn deno_fs::FileSystem,
+  ) -> Result<ModuleSpecifier, AnyError> {
+    match &self.shared.options.resolution_referrer {
+      Some(referrer) => Ok(referrer.clone()),
+      // use a fake referrer that can be used to discover the package.json if necessary
+      None => Ok(
+        ModuleSpecifier::from_directory_path(fs.cwd()?)
+          .unwrap()
+          .join("package.json")?,
+      ),
+    }
+  }
+
+  /// Returns the channel backing `BroadcastChannel` in every worker created by
+  /// this factory, so the host can subscribe to it and exchange messages
+  /// with them. Message data is serialized with the V8 value serializer.
+  pub fn broadcast_channel(&self) -> InMemoryBroadcastChannel {
+    self.shared.broadcast_channel.clone()
+  }
+
+  /// Resolves and caches the given npm packages up front, so workers created
+  /// later for them don't have to. Does nothing when the node_modules
+  /// directory is managed by another package manager.
+  pub async fn preload_npm_packages(
+    &self,
+    reqs: &[PackageReq],
+  ) -> Result<(), AnyError> {
+    if let Some(npm_resolver) = self.shared.npm_resolver.as_managed() {
+      npm_resolver.add_package_reqs(reqs).await?;
+    }
+    Ok(())
+  }
+
+  /// Returns the npm dependency graph resolved so far, without doing any
+  /// additional resolution.
+  pub fn npm_dependency_tree(&self) -> Result<DependencyTree, AnyError> {
+    let Some(npm_resolver) = self.shared.npm_resolver.as_managed() else {
+      bail!("The npm dependency tree is not available when using a node_modules directory managed by another package manager.");
+    };
+    let snapshot = npm_resolver.snapshot();
+
+    let mut roots = snapshot
+      .package_reqs()
+      .keys()
+      .filter_map(|req| {
+        let pkg = snapshot.resolve_pkg_from_pkg_req(req).ok()?;
+        Some((req.clone(), pkg.id.clone()))
+      })
+      .collect::<Vec<_>>();
+    roots.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let mut packages =
+      snapshot.all_packages_for_every_system().collect::<Vec<_>>();
+    packages.sort_by(|a, b| a.id.cmp(&b.id));
+    let packages = packages
+      .into_iter()
+      .map(|pkg| {
+        let mut dependencies = pkg
+          .dependencies
+          .iter()
+          .map(|(name, id)| (name.clone(), id.clone()))
+          .collect::<Vec<_>>();
+        dependencies.sort_by(|a, b| a.0.cmp(&b.0));
+        DependencyTreePackage {
+          id: pkg.id.clone(),
+          name: pkg.id.nv.name.to_string(),
+          version: pkg.id.nv.version.to_string(),
+          dependencies,
+        }
+      })
+      .collect();
+
+    Ok(DependencyTree { roots, packages })
+  }
+
   pub async fn create_main_worker(
     &self,
     main_module: ModuleSpecifier,
@@ -447,118 +2309,534 @@ impl CliMainWorkerFactory {
         permissions,
         vec![],
         Default::default(),
+        Default::default(),
       )
       .await
   }
 
+  /// Creates a worker with the permissions requested by `child_request`,
+  /// which must be a subset of the `parent` permissions. Requesting anything
+  /// the parent doesn't grant fails, and the parent's denials are inherited.
+  pub async fn create_child_worker(
+    &self,
+    main_module: ModuleSpecifier,
+    parent: &PermissionsContainer,
+    child_request: PermissionsOptions,
+  ) -> Result<CliMainWorker, AnyError> {
+    let permissions = create_child_permissions_from_options(
+      &mut parent.0.lock(),
+      &child_request,
+    )
+    .context("Failed to derive the permissions of the child worker")?;
+    self
+      .create_main_worker(main_module, PermissionsContainer::new(permissions))
+      .await
+  }
+
+  /// Creates a worker with custom extensions and stdio, and with `overrides`
+  /// taking precedence over the factory's options for this worker only.
   pub async fn create_custom_worker(
     &self,
     main_module: ModuleSpecifier,
     permissions: PermissionsContainer,
     custom_extensions: Vec<Extension>,
     stdio: deno_runtime::deno_io::Stdio,
+    overrides: CustomWorkerOverrides,
+  ) -> Result<CliMainWorker, AnyError> {
+    self
+      .create_custom_worker_with_module_loader(
+        main_module,
+        permissions,
+        custom_extensions,
+        stdio,
+        overrides,
+        |module_loader| module_loader,
+      )
+      .await
+  }
+
+  /// Creates a worker whose stdin reads from `input` instead of the process'
+  /// stdin. The worker sees EOF once `input` is exhausted.
+  pub async fn create_custom_worker_with_input(
+    &self,
+    main_module: ModuleSpecifier,
+    permissions: PermissionsContainer,
+    input: impl AsyncRead + Unpin + 'static,
+  ) -> Result<CliMainWorker, AnyError> {
+    let (reader, writer) = os_pipe::pipe()?;
+    spawn_input_forwarder(input, writer);
+    self
+      .create_custom_worker(
+        main_module,
+        permissions,
+        vec![],
+        deno_runtime::deno_io::Stdio {
+          stdin: StdioPipe::File(pipe_reader_to_file(reader)),
+          ..Default::default()
+        },
+        Default::default(),
+      )
+      .await
+  }
+
+  /// Creates a worker exposing a `hostAbortSignal` global, which is aborted
+  /// once the returned [`AbortHandle`] is triggered. Unlike terminating the
+  /// isolate, this gives the script a chance to clean up.
+  pub async fn create_custom_worker_with_abort(
+    &self,
+    main_module: ModuleSpecifier,
+    permissions: PermissionsContainer,
+  ) -> Result<(CliMainWorker, AbortHandle), AnyError> {
+    let handle = AbortHandle::default();
+    let mut worker = self
+      .create_custom_worker(
+        main_module,
+        permissions,
+        vec![ops::abort::deno_host_abort::init_ops(handle.clone())],
+        Default::default(),
+        Default::default(),
+      )
+      .await?;
+    worker.execute_script_static(
+      located_script_name!(),
+      r#"(() => {
+        const core = Deno[Deno.internal].core;
+        const controller = new AbortController();
+        const promise = core.ops.op_host_abort_wait();
+        core.unrefOpPromise(promise);
+        promise.then(() =>
+          controller.abort(
+            new DOMException("The host aborted the script.", "AbortError"),
+          )
+        );
+        Object.defineProperty(globalThis, "hostAbortSignal", {
+          value: controller.signal,
+          enumerable: false,
+          configurable: true,
+        });
+      })();"#,
+    )?;
+    Ok((worker, handle))
+  }
+
+  /// Pre-loads and transpiles the module graph of `main_module` into an
+  /// in-memory [`Bundle`] that can be executed later with
+  /// [`create_custom_worker_from_bundle`](Self::create_custom_worker_from_bundle)
+  /// without transpiling again.
+  ///
+  /// The main module is not evaluated. Only modules with string sources are
+  /// included and redirects are not preserved.
+  pub async fn prebundle(
+    &self,
+    main_module: ModuleSpecifier,
+    permissions: PermissionsContainer,
+  ) -> Result<Bundle, AnyError> {
+    let recorded = Rc::new(RefCell::new(InMemoryModules::default()));
+    let mut worker = self
+      .create_custom_worker_with_module_loader(
+        main_module,
+        permissions,
+        vec![],
+        Default::default(),
+        Default::default(),
+        |module_loader| {
+          Rc::new(LayeredModuleLoader::new(
+            module_loader,
+            ModuleRecordingLayer::new(recorded.clone()),
+          ))
+        },
+      )
+      .await?;
+    worker
+      .worker
+      .preload_main_module(&worker.main_module)
+      .await?;
+    let main_module = worker.main_module.clone();
+    drop(worker);
+    let modules = Rc::try_unwrap(recorded)
+      .map(|recorded| recorded.into_inner())
+      .unwrap_or_else(|recorded| recorded.borrow().clone());
+    Ok(Bundle {
+      main_module,
+      modules,
+    })
+  }
+
+  /// Creates a worker that executes a [`Bundle`] created with
+  /// [`prebundle`](Self::prebundle), serving its modules from memory.
+  pub async fn create_custom_worker_from_bundle(
+    &self,
+    bundle: Bundle,
+    permissions: PermissionsContainer,
+    custom_extensions: Vec<Extension>,
+    stdio: deno_runtime::deno_io::Stdio,
+  ) -> Result<CliMainWorker, AnyError> {
+    let modules = Rc::new(RefCell::new(bundle.modules));
+    self
+      .create_custom_worker_with_module_loader(
+        bundle.main_module,
+        permissions,
+        custom_extensions,
+        stdio,
+        Default::default(),
+        |module_loader| {
+          Rc::new(LayeredModuleLoader::new(
+            module_loader,
+            InMemoryModuleLayer::new(modules),
+          ))
+        },
+      )
+      .await
+  }
+
+  /// Creates a worker whose main module is the JavaScript `source`, served
+  /// from memory under `specifier` instead of being read from disk. Its
+  /// imports are resolved relative to `specifier` and loaded as usual.
+  pub async fn create_main_worker_from_source(
+    &self,
+    specifier: ModuleSpecifier,
+    source: String,
+    permissions: PermissionsContainer,
   ) -> Result<CliMainWorker, AnyError> {
+    let mut modules = InMemoryModules::default();
+    modules.modules.insert(
+      specifier.clone(),
+      InMemoryModule {
+        module_type: ModuleType::JavaScript,
+        code: source.into(),
+      },
+    );
+    self
+      .create_custom_worker_from_bundle(
+        Bundle {
+          main_module: specifier,
+          modules,
+        },
+        permissions,
+        vec![],
+        Default::default(),
+      )
+      .await
+  }
+
+  /// Creates a worker that boots from `snapshot` instead of the built-in
+  /// startup snapshot.
+  ///
+  /// The snapshot must be built with
+  /// `deno_runtime::snapshot::create_runtime_snapshot` by this build of the
+  /// runtime, as V8 aborts the process on snapshots it can't deserialize.
+  /// Only the JavaScript heap of the snapshotting runtime is restored, its
+  /// resources and op state don't carry over.
+  pub async fn create_from_runtime_snapshot(
+    &self,
+    main_module: ModuleSpecifier,
+    permissions: PermissionsContainer,
+    snapshot: Vec<u8>,
+  ) -> Result<CliMainWorker, AnyError> {
+    self
+      .create_custom_worker_with_module_loader(
+        main_module,
+        permissions,
+        vec![],
+        Default::default(),
+        CustomWorkerOverrides {
+          startup_snapshot: Some(snapshot),
+          ..Default::default()
+        },
+        |module_loader| module_loader,
+      )
+      .await
+  }
+
+  async fn create_custom_worker_with_module_loader<F>(
+    &self,
+    main_module: ModuleSpecifier,
+    permissions: PermissionsContainer,
+    custom_extensions: Vec<Extension>,
+    stdio: deno_runtime::deno_io::Stdio,
+    overrides: CustomWorkerOverrides,
+    wrap_module_loader: F,
+  ) -> Result<CliMainWorker, AnyError>
+  where
+    F: FnOnce(Rc<dyn ModuleLoader>) -> Rc<dyn ModuleLoader>,
+  {
     let shared = &self.shared;
-    let (main_module, is_main_cjs) = if let Ok(package_ref) =
-      NpmPackageReqReference::from_specifier(&main_module)
-    {
-      let package_ref = if package_ref.req().version_req.version_text() == "*" {
-        // When using the wildcard version, select the same version used in the
-        // package.json deps in order to prevent adding new dependency version
-        shared
-          .options
-          .maybe_root_package_json_deps
-          .as_ref()
-          .and_then(|deps| {
-            deps
-              .values()
-              .filter_map(|v| v.as_ref().ok())
-              .find(|dep| dep.name == package_ref.req().name)
-              .map(|dep| {
-                NpmPackageReqReference::new(PackageReqReference {
-                  req: dep.clone(),
-                  sub_path: package_ref.sub_path().map(|s| s.to_string()),
+    validate_startup_snapshot_override(&shared.options)?;
+    if let Some(snapshot) = &overrides.startup_snapshot {
+      if snapshot.is_empty() {
+        bail!("The startup snapshot of the worker is empty.");
+      }
+      if crate::js::deno_isolate_init().is_none() {
+        bail!("A startup snapshot can't be used when the runtime is built without a snapshot.");
+      }
+    }
+    let resolution_start = Instant::now();
+    if let Some(hook) = &shared.options.permission_prompt_hook {
+      permissions
+        .0
+        .lock()
+        .set_prompter(SharedPermissionPrompter::new(Box::new(
+          HookPermissionPrompter(hook.clone()),
+        )));
+    }
+    if shared.options.irreversible_permission_revocation {
+      permissions.0.lock().set_deny_revoked(true);
+    }
+    let fs = overrides.fs.clone().unwrap_or_else(|| shared.fs.clone());
+    if let Some(locale) = &shared.options.locale_override {
+      if !is_well_formed_language_tag(locale) {
+        bail!("Invalid locale override '{locale}'. Expected a BCP 47 language tag like 'en-US'.");
+      }
+    }
+    let main_module = resolve_directory_main_module(fs.as_ref(), main_module)?;
+    let (main_module, is_main_cjs) =
+      if is_local_non_npm_module(&main_module, shared.options.is_npm_main) {
+        // fast path for local scripts, which don't need any npm resolution
+        log::trace!("Resolved main module {main_module} as a local module.");
+        (main_module, false)
+      } else if let Ok(package_ref) =
+        NpmPackageReqReference::from_specifier(&main_module)
+      {
+        if shared.options.forbid_node_builtins {
+          bail!("Node built-ins are forbidden, cannot run {main_module}.");
+        }
+        let original_req = package_ref.req().clone();
+        let package_ref = if package_ref.req().version_req.version_text() == "*"
+        {
+          // When using the wildcard version, select the same version used in the
+          // package.json deps in order to prevent adding new dependency version
+          shared
+            .options
+            .maybe_root_package_json_deps
+            .as_ref()
+            .and_then(|deps| {
+              deps
+                .values()
+                .filter_map(|v| v.as_ref().ok())
+                .find(|dep| dep.name == package_ref.req().name)
+                .map(|dep| {
+                  NpmPackageReqReference::new(PackageReqReference {
+                    req: dep.clone(),
+                    sub_path: package_ref.sub_path().map(|s| s.to_string()),
+                  })
                 })
-              })
-          })
-          .unwrap_or(package_ref)
-      } else {
-        package_ref
-      };
-      if let Some(npm_resolver) = shared.npm_resolver.as_managed() {
-        npm_resolver
-          .add_package_reqs(&[package_ref.req().clone()])
+            })
+            .unwrap_or(package_ref)
+        } else {
+          package_ref
+        };
+        if package_ref.req() != &original_req {
+          log::trace!(
+            "Substituted wildcard {} with {} from package.json.",
+            original_req,
+            package_ref.req()
+          );
+        }
+        if let Some(npm_resolver) = shared.npm_resolver.as_managed() {
+          add_package_reqs_with_retries(
+            npm_resolver,
+            &[package_ref.req().clone()],
+            shared.options.npm_resolution_retries,
+            shared.options.npm_retry_backoff,
+          )
           .await?;
-      }
+          check_npm_circular_dependencies(
+            npm_resolver,
+            package_ref.req(),
+            shared.options.npm_circular_policy,
+          )?;
+        }
 
-      // use a fake referrer that can be used to discover the package.json if necessary
-      let referrer =
-        ModuleSpecifier::from_directory_path(self.shared.fs.cwd()?)
-          .unwrap()
-          .join("package.json")?;
-      let package_folder = shared
-        .npm_resolver
-        .resolve_pkg_folder_from_deno_module_req(
-          package_ref.req(),
-          &referrer,
+        let referrer = self.npm_main_module_referrer(&*fs)?;
+        let package_folder = self
+          .resolve_npm_package_folder(package_ref.req(), &referrer)
+          .await?;
+        let node_resolution = self.resolve_binary_entrypoint(
+          &package_folder,
+          package_ref.sub_path(),
+          &permissions,
         )?;
-      let node_resolution = self.resolve_binary_entrypoint(
-        &package_folder,
-        package_ref.sub_path(),
-        &permissions,
-      )?;
-      let is_main_cjs = matches!(node_resolution, NodeResolution::CommonJs(_));
+        log::trace!(
+          "Resolved npm main module {} to {}.",
+          package_ref,
+          describe_node_resolution(&node_resolution),
+        );
+        let is_main_cjs =
+          matches!(node_resolution, NodeResolution::CommonJs(_));
 
-      if let Some(lockfile) = &shared.maybe_lockfile {
-        // For npm binary commands, ensure that the lockfile gets updated
-        // so that we can re-use the npm resolution the next time it runs
-        // for better performance
-        lockfile
-          .lock()
-          .write()
-          .context("Failed writing lockfile.")?;
-      }
+        if let Some(lockfile) = &shared.maybe_lockfile {
+          if shared.options.lockfile_frozen {
+            ensure_lockfile_up_to_date(&lockfile.lock())?;
+          } else {
+            // For npm binary commands, ensure that the lockfile gets updated
+            // so that we can re-use the npm resolution the next time it runs
+            // for better performance
+            let lockfile = lockfile.lock();
+            lockfile.write().context("Failed writing lockfile.")?;
+            if let Some(on_lockfile_write) = &shared.options.on_lockfile_write {
+              on_lockfile_write(&lockfile.filename);
+            }
+          }
+        }
 
-      (node_resolution.into_url(), is_main_cjs)
-    } else if shared.options.is_npm_main
-      || shared.node_resolver.in_npm_package(&main_module)
-    {
-      let node_resolution =
-        shared.node_resolver.url_to_node_resolution(main_module)?;
-      let is_main_cjs = matches!(node_resolution, NodeResolution::CommonJs(_));
-      (node_resolution.into_url(), is_main_cjs)
-    } else {
-      (main_module, false)
+        (node_resolution.into_url(), is_main_cjs)
+      } else if shared.options.is_npm_main
+        || shared.node_resolver.in_npm_package(&main_module)
+      {
+        let input = main_module.to_string();
+        let node_resolution =
+          shared.node_resolver.url_to_node_resolution(main_module)?;
+        log::trace!(
+          "Resolved main module {} inside an npm package to {}.",
+          input,
+          describe_node_resolution(&node_resolution),
+        );
+        let is_main_cjs =
+          matches!(node_resolution, NodeResolution::CommonJs(_));
+        (node_resolution.into_url(), is_main_cjs)
+      } else {
+        log::trace!("Resolved main module {main_module} as a regular module.");
+        (main_module, false)
+      };
+    let main_module_resolution = resolution_start.elapsed();
+
+    let mut stdio_pipe_threads = Vec::new();
+    let stdio = match &shared.options.tee_output {
+      Some(captured) => tee_stdio(stdio, captured, &mut stdio_pipe_threads)?,
+      None => stdio,
+    };
+    let stdio = match shared.options.max_output_bytes {
+      Some(max_output_bytes) => {
+        meter_stdio(stdio, max_output_bytes, &mut stdio_pipe_threads)?
+      }
+      None => stdio,
     };
 
-    let module_loader = shared
-      .module_loader_factory
-      .create_for_main(PermissionsContainer::allow_all(), permissions.clone());
+    let module_loader_start = Instant::now();
+    let mut module_loader =
+      wrap_module_loader(shared.module_loader_factory.create_for_main(
+        PermissionsContainer::allow_all(),
+        permissions.clone(),
+      ));
+    if let Some(import_map) = &overrides.import_map {
+      module_loader = Rc::new(LayeredModuleLoader::new(
+        module_loader,
+        ImportMapOverrideLayer::new(import_map.clone()),
+      ));
+    }
+    if let Some(rewriter) = &shared.options.specifier_rewriter {
+      module_loader = Rc::new(LayeredModuleLoader::new(
+        module_loader,
+        SpecifierRewritingLayer::new(rewriter.clone()),
+      ));
+    }
+    let inline_modules = Rc::new(RefCell::new(InMemoryModules::default()));
+    module_loader = Rc::new(LayeredModuleLoader::new(
+      module_loader,
+      InMemoryModuleLayer::new(inline_modules.clone()),
+    ));
+    if shared.options.share_remote_modules {
+      module_loader = Rc::new(LayeredModuleLoader::new(
+        module_loader,
+        SharedRemoteModuleCacheLayer::new(shared.remote_module_cache.clone()),
+      ));
+    }
+    if !shared.options.allow_top_level_await {
+      module_loader = Rc::new(LayeredModuleLoader::new(
+        module_loader,
+        TopLevelAwaitForbiddenLayer,
+      ));
+    }
+    if shared.options.forbid_node_builtins {
+      module_loader = Rc::new(LayeredModuleLoader::new(
+        module_loader,
+        NodeBuiltinForbiddenLayer,
+      ));
+    }
+    if let Some(max_module_count) = shared.options.max_module_count {
+      module_loader = Rc::new(LayeredModuleLoader::new(
+        module_loader,
+        ModuleCountLimitLayer::new(max_module_count),
+      ));
+    }
+    if let Some(max_depth) = shared.options.max_eval_recursion {
+      module_loader = Rc::new(LayeredModuleLoader::new(
+        module_loader,
+        ModuleDepthLimitLayer::new(max_depth),
+      ));
+    }
+    if let Some(on_module_load) = &shared.options.on_module_load {
+      module_loader = Rc::new(LayeredModuleLoader::new(
+        module_loader,
+        ModuleLoadProgressLayer::new(on_module_load.clone()),
+      ));
+    }
+    let module_graph = Rc::new(RefCell::new(LoadedModuleGraph::default()));
+    module_loader = Rc::new(LayeredModuleLoader::new(
+      module_loader,
+      ModuleGraphRecordingLayer::new(module_graph.clone()),
+    ));
+    let module_loader_setup = module_loader_start.elapsed();
     let maybe_source_map_getter =
       shared.module_loader_factory.create_source_map_getter();
-    let maybe_inspector_server = shared.maybe_inspector_server.clone();
+    let maybe_inspector_server = overrides
+      .inspector_server
+      .clone()
+      .or_else(|| shared.maybe_inspector_server.clone());
 
-    let create_web_worker_cb =
-      create_web_worker_callback(shared.clone(), stdio.clone());
+    let web_workers = WebWorkerRegistry::default();
+    let (maybe_worker_error_tx, maybe_worker_errors) =
+      if shared.options.propagate_worker_errors {
+        let (tx, rx) = unbounded_channel();
+        (Some(tx), Some(rx))
+      } else {
+        (None, None)
+      };
+    let client_cert_chain_and_key = match &shared.options.client_cert {
+      Some((cert_path, key_path)) => {
+        Some(load_client_cert(cert_path, key_path)?)
+      }
+      None => None,
+    };
+    let worker_activity = shared
+      .options
+      .track_worker_activity
+      .then(|| Arc::new(WorkerActivity::default()));
+    let web_worker_stdio: WebWorkerStdio =
+      Arc::new(Mutex::new(Some(stdio.clone())));
+    let create_web_worker_cb = create_web_worker_callback(
+      shared.clone(),
+      web_worker_stdio.clone(),
+      Some(web_workers.clone()),
+      maybe_worker_error_tx.clone(),
+      client_cert_chain_and_key.clone(),
+      fs.clone(),
+      worker_activity.clone(),
+      maybe_inspector_server.clone(),
+      overrides.import_map.clone(),
+    );
 
     let maybe_storage_key = shared
       .storage_key_resolver
       .resolve_storage_key(&main_module);
-    let origin_storage_dir = maybe_storage_key.as_ref().map(|key| {
-      shared
-        .options
-        .origin_data_folder_path
-        .as_ref()
-        .unwrap() // must be set if storage key resolver returns a value
-        .join(checksum::gen(&[key.as_bytes()]))
-    });
-    let cache_storage_dir = maybe_storage_key.map(|key| {
-      // TODO(@satyarohith): storage quota management
-      // Note: we currently use temp_dir() to avoid managing storage size.
-      std::env::temp_dir()
-        .join("deno_cache")
-        .join(checksum::gen(&[key.as_bytes()]))
-    });
+    let origin_storage_dir = match &maybe_storage_key {
+      Some(key) => {
+        let Some(local_storage_base_dir) = shared
+          .options
+          .local_storage_base_dir
+          .as_ref()
+          .or(shared.options.origin_data_folder_path.as_ref())
+        else {
+          bail!(
+            "The local_storage_base_dir or origin_data_folder_path option must be set when the storage key resolver resolves a storage key for the main module, so origin storage can be persisted."
+          );
+        };
+        Some(local_storage_base_dir.join(checksum::gen(&[key.as_bytes()])))
+      }
+      None => None,
+    };
+    let cache_storage_dir =
+      maybe_storage_key.map(|key| cache_storage_dir(&shared.options, &key));
 
     // TODO(bartlomieju): this is cruft, update FeatureChecker to spit out
     // list of enabled features.
@@ -574,13 +2852,19 @@ impl CliMainWorkerFactory {
     let options = WorkerOptions {
       bootstrap: BootstrapOptions {
         args: shared.options.argv.clone(),
-        cpu_count: std::thread::available_parallelism()
-          .map(|p| p.get())
-          .unwrap_or(1),
+        cpu_count: shared.options.cpu_count_override.unwrap_or_else(|| {
+          std::thread::available_parallelism()
+            .map(|p| p.get())
+            .unwrap_or(1)
+        }),
         log_level: shared.options.log_level,
         enable_op_summary_metrics: shared.options.enable_op_summary_metrics,
         enable_testing_features: shared.options.enable_testing_features,
-        locale: deno_core::v8::icu::get_language_tag(),
+        locale: shared
+          .options
+          .locale_override
+          .clone()
+          .unwrap_or_else(deno_core::v8::icu::get_language_tag),
         location: shared.options.location.clone(),
         no_color: !colors::use_color(),
         is_tty: colors::is_tty(),
@@ -596,45 +2880,64 @@ impl CliMainWorkerFactory {
         node_ipc_fd: shared.node_ipc,
         disable_deprecated_api_warning: shared.disable_deprecated_api_warning,
         verbose_deprecated_api_warning: shared.verbose_deprecated_api_warning,
+        skip_node_bootstrap: shared.options.forbid_node_builtins,
+      },
+      extensions: custom_extensions
+        .into_iter()
+        .chain(worker_state_extension(&shared.options))
+        .collect(),
+      startup_snapshot: match overrides.startup_snapshot {
+        Some(snapshot) => Some(Snapshot::Boxed(snapshot.into_boxed_slice())),
+        None => startup_snapshot(&shared.options),
       },
-      extensions: custom_extensions,
-      startup_snapshot: crate::js::deno_isolate_init(),
       create_params: None,
       unsafely_ignore_certificate_errors: shared
         .options
         .unsafely_ignore_certificate_errors
         .clone(),
       root_cert_store_provider: Some(shared.root_cert_store_provider.clone()),
-      seed: shared.options.seed,
+      client_cert_chain_and_key: client_cert_chain_and_key.clone(),
+      worker_activity: worker_activity.clone(),
+      seed: overrides.seed.or(shared.options.seed),
       source_map_getter: maybe_source_map_getter,
-      format_js_error_fn: Some(Arc::new(format_js_error)),
+      format_js_error_fn: Some(worker_format_js_error_fn(
+        maybe_worker_error_tx,
+        shared.options.error_redactor.clone(),
+      )),
       create_web_worker_cb,
-      maybe_inspector_server,
+      maybe_inspector_server: maybe_inspector_server.clone(),
       should_break_on_first_statement: shared.options.inspect_brk,
       should_wait_for_inspector_session: shared.options.inspect_wait,
       strace_ops: shared.options.strace_ops.clone(),
+      max_ops_per_second: shared.options.max_ops_per_second,
+      collect_event_loop_stats: shared.options.collect_event_loop_stats,
       module_loader,
-      fs: shared.fs.clone(),
+      fs: fs.clone(),
       npm_resolver: Some(shared.npm_resolver.clone().into_npm_resolver()),
-      get_error_class_fn: Some(&errors::get_error_class_name),
+      get_error_class_fn: Some(shared.get_error_class_fn),
       cache_storage_dir,
+      cache_quota_bytes: shared.options.cache_quota_bytes,
+      import_meta_resolve_hook: shared.options.import_meta_resolve_hook.clone(),
       origin_storage_dir,
       blob_store: shared.blob_store.clone(),
       broadcast_channel: shared.broadcast_channel.clone(),
       shared_array_buffer_store: Some(shared.shared_array_buffer_store.clone()),
-      compiled_wasm_module_store: Some(
-        shared.compiled_wasm_module_store.clone(),
-      ),
+      compiled_wasm_module_store: (shared.options.enable_wasm_module_cache
+        && shared.options.wasm_policy.allows_module_sharing())
+      .then(|| shared.compiled_wasm_module_store.clone()),
       stdio,
       feature_checker,
       skip_op_registration: shared.options.skip_op_registration,
     };
 
+    let bootstrap_options = options.bootstrap.clone();
+    let bootstrap_start = Instant::now();
     let mut worker = MainWorker::bootstrap_from_options(
       main_module.clone(),
-      permissions,
+      permissions.clone(),
       options,
     );
+    shared.options.wasm_policy.enforce(&mut worker.js_runtime)?;
 
     if self.shared.subcommand.needs_test() {
       macro_rules! test_file {
@@ -653,11 +2956,102 @@ impl CliMainWorkerFactory {
       );
     }
 
+    let virtual_clock = if shared.options.virtual_time {
+      let clock = VirtualClock::default();
+      worker.js_runtime.op_state().borrow_mut().put(clock.clone());
+      worker.execute_script(
+        located_script_name!(),
+        deno_core::FastString::StaticAscii(VIRTUAL_DATE_SCRIPT),
+      )?;
+      Some(clock)
+    } else {
+      None
+    };
+
+    let sandbox_temp_dir = if shared.options.sandbox_temp_dir {
+      let temp_dir = tempfile::Builder::new()
+        .prefix("deno_sandbox_")
+        .tempdir()
+        .context("Failed to create the sandbox temp directory")?;
+      let path = temp_dir.path().to_path_buf();
+      let op_state = worker.js_runtime.op_state();
+      let mut op_state = op_state.borrow_mut();
+      let env_provider = SandboxTempDirEnvProvider {
+        temp_dir: path.to_string_lossy().into_owned(),
+        inner: op_state
+          .try_take::<SharedEnvProvider>()
+          .map(|provider| provider.0),
+      };
+      let mut subprocess_env =
+        op_state.try_take::<SubprocessEnv>().unwrap_or_default();
+      for key in TEMP_DIR_ENV_VARS {
+        subprocess_env
+          .0
+          .insert(key.to_string(), env_provider.temp_dir.clone());
+      }
+      op_state.put(deno_fs::TempDirOverride(path));
+      op_state.put(SharedEnvProvider(Arc::new(env_provider)));
+      op_state.put(subprocess_env);
+      Some(temp_dir)
+    } else {
+      None
+    };
+
+    if let Some(post_bootstrap) = &shared.options.post_bootstrap {
+      post_bootstrap(&mut worker)
+        .context("Failed to run the post-bootstrap hook of the worker")?;
+    }
+    let egress_budget = worker
+      .js_runtime
+      .op_state()
+      .borrow()
+      .try_borrow::<deno_fetch::FetchByteBudget>()
+      .cloned();
+    let creation_timings =
+      shared
+        .options
+        .measure_worker_creation
+        .then(|| WorkerCreationTimings {
+          main_module_resolution,
+          module_loader_setup,
+          bootstrap: bootstrap_start.elapsed(),
+        });
+
+    let stdio_pipe_rids = if stdio_pipe_threads.is_empty() {
+      vec![]
+    } else {
+      let op_state = worker.js_runtime.op_state();
+      let op_state = op_state.borrow();
+      op_state
+        .resource_table
+        .names()
+        .filter(|(_, name)| name == "stdout" || name == "stderr")
+        .map(|(rid, _)| rid)
+        .collect()
+    };
+    let isolate_handle = worker.js_runtime.v8_isolate().thread_safe_handle();
     Ok(CliMainWorker {
       main_module,
       is_main_cjs,
       worker,
       shared: shared.clone(),
+      manual_coverage_collector: None,
+      web_workers,
+      maybe_worker_errors,
+      isolate_handle,
+      inline_modules,
+      hmr_emitter: None,
+      virtual_clock,
+      module_graph,
+      maybe_inspector_server,
+      creation_timings,
+      egress_budget,
+      sandbox_temp_dir,
+      permissions,
+      bootstrap_options,
+      stdio_pipe_threads,
+      stdio_pipe_rids,
+      web_worker_stdio,
     })
   }
 
@@ -691,80 +3085,866 @@ impl CliMainWorkerFactory {
     }
   }
 
-  /// resolve the binary entrypoint using regular node resolution
-  fn resolve_binary_entrypoint_fallback(
-    &self,
-    package_folder: &Path,
-    sub_path: Option<&str>,
-    permissions: &PermissionsContainer,
-  ) -> Result<Option<NodeResolution>, AnyError> {
-    // only fallback if the user specified a sub path
-    if sub_path.is_none() {
-      // it's confusing to users if the package doesn't have any binary
-      // entrypoint and we just execute the main script which will likely
-      // have blank output, so do not resolve the entrypoint in this case
-      return Ok(None);
-    }
+  /// resolve the binary entrypoint using regular node resolution
+  fn resolve_binary_entrypoint_fallback(
+    &self,
+    package_folder: &Path,
+    sub_path: Option<&str>,
+    permissions: &PermissionsContainer,
+  ) -> Result<Option<NodeResolution>, AnyError> {
+    // only fallback if the user specified a sub path
+    if sub_path.is_none() {
+      // it's confusing to users if the package doesn't have any binary
+      // entrypoint and we just execute the main script which will likely
+      // have blank output, so do not resolve the entrypoint in this case
+      return Ok(None);
+    }
+
+    // use a fake referrer since a real one doesn't exist
+    let referrer =
+      ModuleSpecifier::from_directory_path(package_folder).unwrap();
+    let Some(resolution) = self
+      .shared
+      .node_resolver
+      .resolve_package_subpath_from_deno_module(
+        package_folder,
+        sub_path,
+        &referrer,
+        NodeResolutionMode::Execution,
+        permissions,
+      )?
+    else {
+      return Ok(None);
+    };
+    match &resolution {
+      NodeResolution::BuiltIn(_) => Ok(None),
+      NodeResolution::CommonJs(specifier) | NodeResolution::Esm(specifier) => {
+        if specifier
+          .to_file_path()
+          .map(|p| p.exists())
+          .unwrap_or(false)
+        {
+          Ok(Some(resolution))
+        } else {
+          bail!("Cannot find module '{}'", specifier)
+        }
+      }
+    }
+  }
+}
+
+/// Copies everything read from `input` into the pipe, closing it once
+/// `input` is exhausted.
+fn spawn_input_forwarder(
+  mut input: impl AsyncRead + Unpin + 'static,
+  mut writer: os_pipe::PipeWriter,
+) {
+  // writing to the pipe may block until the worker reads from it, so do
+  // that on a separate thread
+  let (sender, receiver) = std::sync::mpsc::channel::<Vec<u8>>();
+  spawn_blocking(move || {
+    for chunk in receiver {
+      if writer.write_all(&chunk).is_err() {
+        break;
+      }
+    }
+  });
+  spawn(async move {
+    let mut buffer = [0; 8 * 1024];
+    loop {
+      let size = match input.read(&mut buffer).await {
+        Ok(0) | Err(_) => break,
+        Ok(size) => size,
+      };
+      if sender.send(buffer[..size].to_vec()).is_err() {
+        break;
+      }
+    }
+  });
+}
+
+/// Routes stdout and stderr of `stdio` through pipes whose output is copied
+/// into `captured` before being forwarded to the original destination. The
+/// threads doing the copying signal a receiver added to `threads` once done.
+fn tee_stdio(
+  stdio: deno_runtime::deno_io::Stdio,
+  captured: &Arc<Mutex<Vec<u8>>>,
+  threads: &mut Vec<oneshot::Receiver<()>>,
+) -> Result<deno_runtime::deno_io::Stdio, AnyError> {
+  Ok(deno_runtime::deno_io::Stdio {
+    stdin: stdio.stdin,
+    stdout: tee_stdio_pipe(
+      stdio.stdout,
+      Box::new(std::io::stdout()),
+      captured.clone(),
+      threads,
+    )?,
+    stderr: tee_stdio_pipe(
+      stdio.stderr,
+      Box::new(std::io::stderr()),
+      captured.clone(),
+      threads,
+    )?,
+  })
+}
+
+fn tee_stdio_pipe(
+  pipe: StdioPipe,
+  inherited: Box<dyn Write + Send>,
+  captured: Arc<Mutex<Vec<u8>>>,
+  threads: &mut Vec<oneshot::Receiver<()>>,
+) -> Result<StdioPipe, AnyError> {
+  let (mut reader, writer) = os_pipe::pipe()?;
+  let mut target = match pipe {
+    StdioPipe::Inherit => inherited,
+    StdioPipe::File(file) => Box::new(file),
+  };
+  let (finished_tx, finished_rx) = oneshot::channel();
+  threads.push(finished_rx);
+  // use a dedicated thread so the worker never waits on the capture
+  std::thread::spawn(move || {
+    let mut buffer = [0; 8 * 1024];
+    loop {
+      let size = match reader.read(&mut buffer) {
+        Ok(0) | Err(_) => break,
+        Ok(size) => size,
+      };
+      captured.lock().extend_from_slice(&buffer[..size]);
+      if target.write_all(&buffer[..size]).is_ok() {
+        let _ = target.flush();
+      }
+    }
+    let _ = finished_tx.send(());
+  });
+  Ok(StdioPipe::File(pipe_writer_to_file(writer)))
+}
+
+/// How much of the `max_output_bytes` budget shared by stdout and stderr was
+/// used.
+#[derive(Default)]
+struct OutputBudget {
+  written: u64,
+  truncated: bool,
+}
+
+/// Routes stdout and stderr of `stdio` through pipes that forward at most
+/// `max_bytes` in total to the original destination. The threads doing the
+/// forwarding signal a receiver added to `threads` once done.
+fn meter_stdio(
+  stdio: deno_runtime::deno_io::Stdio,
+  max_bytes: u64,
+  threads: &mut Vec<oneshot::Receiver<()>>,
+) -> Result<deno_runtime::deno_io::Stdio, AnyError> {
+  let budget = Arc::new(Mutex::new(OutputBudget::default()));
+  Ok(deno_runtime::deno_io::Stdio {
+    stdin: stdio.stdin,
+    stdout: meter_stdio_pipe(
+      stdio.stdout,
+      Box::new(std::io::stdout()),
+      max_bytes,
+      budget.clone(),
+      threads,
+    )?,
+    stderr: meter_stdio_pipe(
+      stdio.stderr,
+      Box::new(std::io::stderr()),
+      max_bytes,
+      budget,
+      threads,
+    )?,
+  })
+}
+
+fn meter_stdio_pipe(
+  pipe: StdioPipe,
+  inherited: Box<dyn Write + Send>,
+  max_bytes: u64,
+  budget: Arc<Mutex<OutputBudget>>,
+  threads: &mut Vec<oneshot::Receiver<()>>,
+) -> Result<StdioPipe, AnyError> {
+  let (mut reader, writer) = os_pipe::pipe()?;
+  let mut target = match pipe {
+    StdioPipe::Inherit => inherited,
+    StdioPipe::File(file) => Box::new(file),
+  };
+  let (finished_tx, finished_rx) = oneshot::channel();
+  threads.push(finished_rx);
+  // keep draining the pipe after the limit is hit, so the worker never
+  // blocks on a full pipe
+  std::thread::spawn(move || {
+    let mut buffer = [0; 8 * 1024];
+    loop {
+      let size = match reader.read(&mut buffer) {
+        Ok(0) | Err(_) => break,
+        Ok(size) => size,
+      };
+      let (allowed, write_notice) = {
+        let mut budget = budget.lock();
+        let remaining = max_bytes - budget.written;
+        let allowed = (size as u64).min(remaining) as usize;
+        budget.written += allowed as u64;
+        let write_notice = allowed < size && !budget.truncated;
+        if write_notice {
+          budget.truncated = true;
+        }
+        (allowed, write_notice)
+      };
+      if allowed > 0 {
+        let _ = target.write_all(&buffer[..allowed]);
+      }
+      if write_notice {
+        let _ = write!(
+          target,
+          "\n[output truncated: exceeded the limit of {max_bytes} bytes]\n"
+        );
+      }
+      if allowed > 0 || write_notice {
+        let _ = target.flush();
+      }
+    }
+    let _ = finished_tx.send(());
+  });
+  Ok(StdioPipe::File(pipe_writer_to_file(writer)))
+}
+
+#[cfg(windows)]
+fn pipe_writer_to_file(writer: os_pipe::PipeWriter) -> std::fs::File {
+  use std::os::windows::prelude::FromRawHandle;
+  use std::os::windows::prelude::IntoRawHandle;
+  // SAFETY: Requires consuming ownership of the provided handle
+  unsafe { std::fs::File::from_raw_handle(writer.into_raw_handle()) }
+}
+
+#[cfg(unix)]
+fn pipe_writer_to_file(writer: os_pipe::PipeWriter) -> std::fs::File {
+  use std::os::unix::io::FromRawFd;
+  use std::os::unix::io::IntoRawFd;
+  // SAFETY: Requires consuming ownership of the provided handle
+  unsafe { std::fs::File::from_raw_fd(writer.into_raw_fd()) }
+}
+
+#[cfg(windows)]
+fn pipe_reader_to_file(reader: os_pipe::PipeReader) -> std::fs::File {
+  use std::os::windows::prelude::FromRawHandle;
+  use std::os::windows::prelude::IntoRawHandle;
+  // SAFETY: Requires consuming ownership of the provided handle
+  unsafe { std::fs::File::from_raw_handle(reader.into_raw_handle()) }
+}
+
+#[cfg(unix)]
+fn pipe_reader_to_file(reader: os_pipe::PipeReader) -> std::fs::File {
+  use std::os::unix::io::FromRawFd;
+  use std::os::unix::io::IntoRawFd;
+  // SAFETY: Requires consuming ownership of the provided handle
+  unsafe { std::fs::File::from_raw_fd(reader.into_raw_fd()) }
+}
+
+/// Checks that `tag` is syntactically a BCP 47 language tag: a 2-8 letter
+/// language subtag followed by 1-8 character alphanumeric subtags.
+fn is_well_formed_language_tag(tag: &str) -> bool {
+  let mut subtags = tag.split('-');
+  let Some(language) = subtags.next() else {
+    return false;
+  };
+  (2..=8).contains(&language.len())
+    && language.chars().all(|c| c.is_ascii_alphabetic())
+    && subtags.all(|subtag| {
+      (1..=8).contains(&subtag.len())
+        && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+/// Puts the embedder options that ops look up in the op state into the
+/// worker's op state, if any of them were set.
+fn worker_state_extension(options: &CliMainWorkerOptions) -> Option<Extension> {
+  if options.on_permission_revoked.is_none()
+    && !options.deterministic_collections
+    && options.max_concurrent_fetches.is_none()
+    && options.env_provider.is_none()
+    && options.env_override.is_none()
+    && options.max_egress_bytes.is_none()
+    && options.require_interceptor.is_none()
+    && options.on_unhandled_rejection.is_none()
+  {
+    return None;
+  }
+  let revocation_options = PermissionRevocationOptions {
+    on_revoked: options.on_permission_revoked.clone(),
+  };
+  let deterministic_collections = options.deterministic_collections;
+  let max_concurrent_fetches = options.max_concurrent_fetches;
+  let max_egress_bytes = options.max_egress_bytes;
+  let require_interceptor = options.require_interceptor.clone();
+  let unhandled_rejection_handler = options
+    .on_unhandled_rejection
+    .clone()
+    .map(UnhandledRejectionHandler);
+  let env_provider = match &options.env_override {
+    Some(vars) => Some(SharedEnvProvider(Arc::new(ScopedEnvProvider {
+      vars: Mutex::new(vars.clone()),
+    }))),
+    None => options.env_provider.clone().map(SharedEnvProvider),
+  };
+  Some(Extension {
+    name: "deno_cli_worker_state",
+    op_state_fn: Some(Box::new(move |state| {
+      state.put(revocation_options);
+      if deterministic_collections {
+        state.put(deno_fs::SortedReadDir);
+      }
+      if let Some(max_concurrent_fetches) = max_concurrent_fetches {
+        state.put(deno_fetch::FetchConcurrencyLimit::new(
+          max_concurrent_fetches,
+        ));
+      }
+      if let Some(env_provider) = env_provider {
+        state.put(env_provider);
+      }
+      if let Some(max_egress_bytes) = max_egress_bytes {
+        state.put(deno_fetch::FetchByteBudget::new(max_egress_bytes));
+      }
+      if let Some(require_interceptor) = require_interceptor {
+        state.put(require_interceptor);
+      }
+      if let Some(handler) = unhandled_rejection_handler {
+        state.put(handler);
+      }
+    })),
+    ..Default::default()
+  })
+}
+
+/// Makes `Date` follow `performance.now()`, which is driven by the virtual
+/// clock when the `virtual_time` option is set.
+const VIRTUAL_DATE_SCRIPT: &str = r#"(() => {
+  const RealDate = globalThis.Date;
+  const epoch = RealDate.now() - globalThis.performance.now();
+  const now = () => Math.floor(epoch + globalThis.performance.now());
+  function Date(...args) {
+    if (new.target === undefined) {
+      return new RealDate(now()).toString();
+    }
+    return Reflect.construct(
+      RealDate,
+      args.length === 0 ? [now()] : args,
+      new.target,
+    );
+  }
+  Date.prototype = RealDate.prototype;
+  Date.now = now;
+  Date.parse = RealDate.parse;
+  Date.UTC = RealDate.UTC;
+  Object.defineProperty(RealDate.prototype, "constructor", {
+    value: Date,
+    writable: true,
+    configurable: true,
+  });
+  Object.defineProperty(globalThis, "Date", {
+    value: Date,
+    writable: true,
+    configurable: true,
+  });
+})();"#;
+
+/// Keeps the environment of a single worker in memory, see the
+/// `env_override` option.
+struct ScopedEnvProvider {
+  vars: Mutex<HashMap<String, String>>,
+}
+
+impl EnvProvider for ScopedEnvProvider {
+  fn get(&self, key: &str) -> Result<Option<String>, AnyError> {
+    Ok(self.vars.lock().get(key).cloned())
+  }
+
+  fn set(&self, key: &str, value: &str) -> Result<(), AnyError> {
+    self.vars.lock().insert(key.to_string(), value.to_string());
+    Ok(())
+  }
+
+  fn delete(&self, key: &str) -> Result<(), AnyError> {
+    self.vars.lock().remove(key);
+    Ok(())
+  }
+
+  fn list(&self) -> Result<HashMap<String, String>, AnyError> {
+    Ok(self.vars.lock().clone())
+  }
+}
+
+/// Environment variables that name the temporary directory.
+const TEMP_DIR_ENV_VARS: &[&str] = &["TMPDIR", "TMP", "TEMP"];
+
+/// Reports the sandbox temp directory of a worker as its temporary directory
+/// in `Deno.env`, and forwards everything else to the configured provider or
+/// the process environment.
+struct SandboxTempDirEnvProvider {
+  temp_dir: String,
+  inner: Option<Arc<dyn EnvProvider + Send + Sync>>,
+}
+
+impl EnvProvider for SandboxTempDirEnvProvider {
+  fn get(&self, key: &str) -> Result<Option<String>, AnyError> {
+    if TEMP_DIR_ENV_VARS.contains(&key) {
+      return Ok(Some(self.temp_dir.clone()));
+    }
+    match &self.inner {
+      Some(inner) => inner.get(key),
+      None => Ok(std::env::var(key).ok()),
+    }
+  }
+
+  fn set(&self, key: &str, value: &str) -> Result<(), AnyError> {
+    match &self.inner {
+      Some(inner) => inner.set(key, value),
+      None => {
+        std::env::set_var(key, value);
+        Ok(())
+      }
+    }
+  }
+
+  fn delete(&self, key: &str) -> Result<(), AnyError> {
+    match &self.inner {
+      Some(inner) => inner.delete(key),
+      None => {
+        std::env::remove_var(key);
+        Ok(())
+      }
+    }
+  }
+
+  fn list(&self) -> Result<HashMap<String, String>, AnyError> {
+    let mut vars = match &self.inner {
+      Some(inner) => inner.list()?,
+      None => std::env::vars().collect(),
+    };
+    for key in TEMP_DIR_ENV_VARS {
+      vars.insert(key.to_string(), self.temp_dir.clone());
+    }
+    Ok(vars)
+  }
+}
+
+/// Returns the directory the Cache API of the origin with the storage `key`
+/// stores its data in.
+fn cache_storage_dir(options: &CliMainWorkerOptions, key: &str) -> PathBuf {
+  // Note: by default we use temp_dir(), its size is only managed when
+  // `cache_quota_bytes` is set.
+  let base_dir = match &options.cache_storage_base_dir {
+    Some(base_dir) => base_dir.clone(),
+    None => std::env::temp_dir().join("deno_cache"),
+  };
+  base_dir.join(checksum::gen(&[key.as_bytes()]))
+}
+
+/// Returns the snapshot workers boot from, honoring the
+/// `startup_snapshot_override` option.
+fn startup_snapshot(options: &CliMainWorkerOptions) -> Option<Snapshot> {
+  match options.startup_snapshot_override {
+    Some(snapshot) => Some(Snapshot::Static(snapshot)),
+    None => crate::js::deno_isolate_init(),
+  }
+}
+
+/// Checks the `startup_snapshot_override` option before it is used to boot a
+/// worker, as V8 aborts the process on snapshots it can't deserialize.
+fn validate_startup_snapshot_override(
+  options: &CliMainWorkerOptions,
+) -> Result<(), AnyError> {
+  let Some(snapshot) = options.startup_snapshot_override else {
+    return Ok(());
+  };
+  if snapshot.is_empty() {
+    bail!("The startup snapshot override is empty.");
+  }
+  if crate::js::deno_isolate_init().is_none() {
+    bail!("A startup snapshot override can't be used when the runtime is built without a snapshot.");
+  }
+  Ok(())
+}
+
+/// Reads the certificate chain and private key of the `client_cert` option,
+/// checking that both can be parsed.
+fn load_client_cert(
+  cert_path: &Path,
+  key_path: &Path,
+) -> Result<(String, String), AnyError> {
+  let cert_chain = std::fs::read_to_string(cert_path).with_context(|| {
+    format!(
+      "Failed to read the client certificate {}",
+      cert_path.display()
+    )
+  })?;
+  deno_tls::load_certs(&mut cert_chain.as_bytes()).with_context(|| {
+    format!(
+      "Failed to parse the client certificate {}",
+      cert_path.display()
+    )
+  })?;
+  let key = std::fs::read_to_string(key_path).with_context(|| {
+    format!("Failed to read the client key {}", key_path.display())
+  })?;
+  deno_tls::load_private_keys(key.as_bytes()).with_context(|| {
+    format!("Failed to parse the client key {}", key_path.display())
+  })?;
+  Ok((cert_chain, key))
+}
+
+/// Errors if the lockfile on disk differs from its in-memory state.
+fn ensure_lockfile_up_to_date(lockfile: &Lockfile) -> Result<(), AnyError> {
+  let on_disk = std::fs::read_to_string(&lockfile.filename)
+    .ok()
+    .and_then(|text| serde_json::from_str::<serde_json::Value>(&text).ok());
+  let in_memory =
+    serde_json::from_str::<serde_json::Value>(&lockfile.as_json_string())?;
+  if on_disk.as_ref() != Some(&in_memory) {
+    bail!(
+      "The lockfile at {} is out of date, but it is frozen. Update it by running without a frozen lockfile.",
+      lockfile.filename.display()
+    );
+  }
+  Ok(())
+}
+
+fn describe_node_resolution(resolution: &NodeResolution) -> String {
+  match resolution {
+    NodeResolution::Esm(specifier) => format!("ES module {specifier}"),
+    NodeResolution::CommonJs(specifier) => {
+      format!("CommonJS module {specifier}")
+    }
+    NodeResolution::BuiltIn(name) => format!("built-in module {name}"),
+  }
+}
+
+/// Resolves and caches `reqs`, retrying up to `retries` times on network
+/// and registry server errors, with a delay starting at `backoff` that
+/// doubles after every attempt.
+async fn add_package_reqs_with_retries(
+  npm_resolver: &ManagedCliNpmResolver,
+  reqs: &[PackageReq],
+  retries: u32,
+  backoff: Duration,
+) -> Result<(), AnyError> {
+  let mut attempt = 0;
+  loop {
+    match npm_resolver.add_package_reqs(reqs).await {
+      Ok(()) => return Ok(()),
+      Err(err) if attempt < retries && is_retryable_npm_error(&err) => {
+        let delay = backoff.saturating_mul(2u32.saturating_pow(attempt));
+        log::debug!(
+          "Retrying npm resolution in {}ms after error: {:#}",
+          delay.as_millis(),
+          err
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+      }
+      Err(err) => return Err(err),
+    }
+  }
+}
+
+/// Whether any cause of `err` is a registry server error, or a connection
+/// failure or timeout while talking to the registry. Errors loading package
+/// information are kept inside the resolution error, so they're checked too.
+fn is_retryable_npm_error(err: &AnyError) -> bool {
+  err.chain().any(|cause| {
+    if let Some(err) = cause.downcast_ref::<NpmResolutionError>() {
+      match err {
+        NpmResolutionError::Registry(
+          NpmRegistryPackageInfoLoadError::LoadError(err),
+        ) => is_retryable_npm_error(err),
+        _ => false,
+      }
+    } else if let Some(err) = cause.downcast_ref::<deno_fetch::reqwest::Error>()
+    {
+      err.is_connect() || err.is_timeout()
+    } else if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+      matches!(
+        err.kind(),
+        ErrorKind::TimedOut
+          | ErrorKind::ConnectionRefused
+          | ErrorKind::ConnectionReset
+          | ErrorKind::ConnectionAborted
+      )
+    } else {
+      // the http client only reports the status of a failed response in
+      // its message
+      cause
+        .to_string()
+        .strip_prefix("Bad response: ")
+        .and_then(|status| status.get(..3))
+        .and_then(|code| code.parse::<u16>().ok())
+        .map(|code| code >= 500)
+        .unwrap_or(false)
+    }
+  })
+}
+
+/// Looks for a cycle in the npm dependencies of `req` and warns or errors
+/// about it according to `policy`.
+fn check_npm_circular_dependencies(
+  npm_resolver: &ManagedCliNpmResolver,
+  req: &PackageReq,
+  policy: NpmCircularPolicy,
+) -> Result<(), AnyError> {
+  if policy == NpmCircularPolicy::Allow {
+    return Ok(());
+  }
+  let snapshot = npm_resolver.snapshot();
+  let Ok(root) = snapshot.resolve_pkg_from_pkg_req(req) else {
+    return Ok(());
+  };
+  let Some(cycle) = find_npm_dependency_cycle(&snapshot, &root.id) else {
+    return Ok(());
+  };
+  let cycle = cycle
+    .iter()
+    .map(|id| id.nv.to_string())
+    .collect::<Vec<_>>()
+    .join(" -> ");
+  match policy {
+    NpmCircularPolicy::Allow => Ok(()),
+    NpmCircularPolicy::Warn => {
+      log::warn!(
+        "{} Circular npm dependency in '{}': {}",
+        colors::yellow("Warning"),
+        req,
+        cycle
+      );
+      Ok(())
+    }
+    NpmCircularPolicy::Error => {
+      bail!("Circular npm dependency in '{}': {}", req, cycle)
+    }
+  }
+}
+
+/// Returns the first dependency cycle reachable from `root`, as the ids of
+/// the packages in it, starting and ending with the same package.
+fn find_npm_dependency_cycle(
+  snapshot: &NpmResolutionSnapshot,
+  root: &NpmPackageId,
+) -> Option<Vec<NpmPackageId>> {
+  fn visit(
+    snapshot: &NpmResolutionSnapshot,
+    id: &NpmPackageId,
+    path: &mut Vec<NpmPackageId>,
+    done: &mut HashSet<NpmPackageId>,
+  ) -> Option<Vec<NpmPackageId>> {
+    if let Some(index) = path.iter().position(|p| p == id) {
+      let mut cycle = path[index..].to_vec();
+      cycle.push(id.clone());
+      return Some(cycle);
+    }
+    if done.contains(id) {
+      return None;
+    }
+    let pkg = snapshot.package_from_id(id)?;
+    let mut dependencies = pkg.dependencies.values().collect::<Vec<_>>();
+    dependencies.sort();
+    path.push(id.clone());
+    for dependency in dependencies {
+      if let Some(cycle) = visit(snapshot, dependency, path, done) {
+        return Some(cycle);
+      }
+    }
+    path.pop();
+    done.insert(id.clone());
+    None
+  }
+
+  visit(snapshot, root, &mut Vec::new(), &mut HashSet::new())
+}
 
-    // use a fake referrer since a real one doesn't exist
-    let referrer =
-      ModuleSpecifier::from_directory_path(package_folder).unwrap();
-    let Some(resolution) = self
-      .shared
-      .node_resolver
-      .resolve_package_subpath_from_deno_module(
-        package_folder,
-        sub_path,
-        &referrer,
-        NodeResolutionMode::Execution,
-        permissions,
-      )?
-    else {
-      return Ok(None);
-    };
-    match &resolution {
-      NodeResolution::BuiltIn(_) => Ok(None),
-      NodeResolution::CommonJs(specifier) | NodeResolution::Esm(specifier) => {
-        if specifier
-          .to_file_path()
-          .map(|p| p.exists())
-          .unwrap_or(false)
-        {
-          Ok(Some(resolution))
-        } else {
-          bail!("Cannot find module '{}'", specifier)
-        }
+/// Index files looked for, in order, when the main module is a directory.
+const DIRECTORY_INDEX_FILES: &[&str] = &[
+  "index.ts",
+  "index.tsx",
+  "index.js",
+  "index.mjs",
+  "index.jsx",
+  "index.cjs",
+];
+
+/// Resolves a `file:` main module that points at a directory to the entry
+/// point inside of it: the `main` of its package.json, or else an index file.
+/// Other specifiers are returned as is. The directory is read through `fs`.
+fn resolve_directory_main_module(
+  fs: &dyn deno_fs::FileSystem,
+  main_module: ModuleSpecifier,
+) -> Result<ModuleSpecifier, AnyError> {
+  if main_module.scheme() != "file" {
+    return Ok(main_module);
+  }
+  let Ok(dir) = main_module.to_file_path() else {
+    return Ok(main_module);
+  };
+  if !fs.is_dir_sync(&dir) {
+    return Ok(main_module);
+  }
+
+  let package_json_path = dir.join("package.json");
+  if let Ok(text) = fs.read_text_file_sync(&package_json_path) {
+    let package_json = serde_json::from_str::<serde_json::Value>(&text)
+      .with_context(|| {
+        format!("Failed to parse {}", package_json_path.display())
+      })?;
+    if let Some(main) = package_json.get("main").and_then(|m| m.as_str()) {
+      let path = dir.join(main);
+      if fs.is_file_sync(&path) {
+        log::trace!("Resolved main module directory {main_module} to the main of its package.json.");
+        return Ok(ModuleSpecifier::from_file_path(path).unwrap());
       }
     }
   }
+  for index_file in DIRECTORY_INDEX_FILES {
+    let path = dir.join(index_file);
+    if fs.is_file_sync(&path) {
+      log::trace!(
+        "Resolved main module directory {main_module} to {index_file}."
+      );
+      return Ok(ModuleSpecifier::from_file_path(path).unwrap());
+    }
+  }
+  bail!(
+    "The main module {} is a directory without an entry point. Add a package.json with a \"main\" field, or one of: {}.",
+    dir.display(),
+    DIRECTORY_INDEX_FILES.join(", "),
+  )
+}
+
+/// Cheaply checks if the specifier is a local file that is not part of an
+/// npm package, meaning it can be executed without going through npm or
+/// node resolution.
+fn is_local_non_npm_module(
+  specifier: &ModuleSpecifier,
+  is_npm_main: bool,
+) -> bool {
+  !is_npm_main
+    && specifier.scheme() == "file"
+    && !specifier.path().contains("/node_modules/")
+}
+
+/// Per worker settings that take precedence over the factory's options,
+/// see [`CliMainWorkerFactory::create_custom_worker`].
+#[derive(Default)]
+pub struct CustomWorkerOverrides {
+  /// Takes precedence over the factory's `seed` option.
+  pub seed: Option<u64>,
+  /// Replaces the factory's file system for the ops of this worker and its
+  /// web workers, and to find the current directory when resolving an npm
+  /// main module. Modules are still loaded by the factory's module loader.
+  pub fs: Option<Arc<dyn deno_fs::FileSystem>>,
+  /// Registers this worker and its web workers on their own inspector server
+  /// instead of the factory's, so a separate debugger can attach to them.
+  pub inspector_server: Option<Arc<InspectorServer>>,
+  /// Snapshot this worker boots from instead of the built-in startup
+  /// snapshot, see [`CliMainWorkerFactory::create_from_runtime_snapshot`]
+  /// for its requirements.
+  pub startup_snapshot: Option<Vec<u8>>,
+  /// Consulted before the factory's resolution by this worker and its web
+  /// workers, e.g. to give each tenant its own mappings, see
+  /// [`ImportMapOverrideLayer`] for its limits.
+  pub import_map: Option<Arc<ImportMap>>,
+}
+
+/// Terminate handles of the web workers spawned directly by a main worker.
+type WebWorkerRegistry =
+  Arc<Mutex<HashMap<WorkerId, WebWorkerTerminateHandle>>>;
+
+/// Returns the function used to format the uncaught errors of web workers,
+/// which also sends them to `maybe_worker_error_tx` when set.
+fn worker_format_js_error_fn(
+  maybe_worker_error_tx: Option<UnboundedSender<String>>,
+  maybe_error_redactor: Option<ErrorRedactor>,
+) -> Arc<FormatJsErrorFn> {
+  if maybe_worker_error_tx.is_none() && maybe_error_redactor.is_none() {
+    return Arc::new(format_js_error);
+  }
+  Arc::new(move |js_error| {
+    let mut message = format_js_error(js_error);
+    if let Some(redactor) = &maybe_error_redactor {
+      message = redactor(message);
+    }
+    if let Some(worker_error_tx) = &maybe_worker_error_tx {
+      let _ = worker_error_tx
+        .send(message.trim_start_matches("Uncaught ").to_string());
+    }
+    message
+  })
 }
 
+#[allow(clippy::too_many_arguments)]
 fn create_web_worker_callback(
   shared: Arc<SharedWorkerState>,
-  stdio: deno_runtime::deno_io::Stdio,
+  stdio: WebWorkerStdio,
+  maybe_registry: Option<WebWorkerRegistry>,
+  maybe_worker_error_tx: Option<UnboundedSender<String>>,
+  client_cert_chain_and_key: Option<(String, String)>,
+  fs: Arc<dyn deno_fs::FileSystem>,
+  worker_activity: Option<Arc<WorkerActivity>>,
+  maybe_inspector_server: Option<Arc<InspectorServer>>,
+  maybe_import_map: Option<Arc<ImportMap>>,
 ) -> Arc<CreateWebWorkerCb> {
   Arc::new(move |args| {
-    let maybe_inspector_server = shared.maybe_inspector_server.clone();
+    let maybe_inspector_server = maybe_inspector_server.clone();
+    let stdio = stdio.lock().clone().unwrap_or_default();
 
-    let module_loader = shared.module_loader_factory.create_for_worker(
+    let mut module_loader = shared.module_loader_factory.create_for_worker(
       args.parent_permissions.clone(),
       args.permissions.clone(),
     );
+    if let Some(import_map) = &maybe_import_map {
+      module_loader = Rc::new(LayeredModuleLoader::new(
+        module_loader,
+        ImportMapOverrideLayer::new(import_map.clone()),
+      ));
+    }
+    if let Some(rewriter) = &shared.options.specifier_rewriter {
+      module_loader = Rc::new(LayeredModuleLoader::new(
+        module_loader,
+        SpecifierRewritingLayer::new(rewriter.clone()),
+      ));
+    }
+    if shared.options.share_remote_modules {
+      module_loader = Rc::new(LayeredModuleLoader::new(
+        module_loader,
+        SharedRemoteModuleCacheLayer::new(shared.remote_module_cache.clone()),
+      ));
+    }
+    if !shared.options.allow_top_level_await {
+      module_loader = Rc::new(LayeredModuleLoader::new(
+        module_loader,
+        TopLevelAwaitForbiddenLayer,
+      ));
+    }
+    if shared.options.forbid_node_builtins {
+      module_loader = Rc::new(LayeredModuleLoader::new(
+        module_loader,
+        NodeBuiltinForbiddenLayer,
+      ));
+    }
     let maybe_source_map_getter =
       shared.module_loader_factory.create_source_map_getter();
-    let create_web_worker_cb =
-      create_web_worker_callback(shared.clone(), stdio.clone());
+    let create_web_worker_cb = create_web_worker_callback(
+      shared.clone(),
+      Arc::new(Mutex::new(Some(stdio.clone()))),
+      None,
+      maybe_worker_error_tx.clone(),
+      client_cert_chain_and_key.clone(),
+      fs.clone(),
+      worker_activity.clone(),
+      maybe_inspector_server.clone(),
+      maybe_import_map.clone(),
+    );
 
     let maybe_storage_key = shared
       .storage_key_resolver
       .resolve_storage_key(&args.main_module);
-    let cache_storage_dir = maybe_storage_key.map(|key| {
-      // TODO(@satyarohith): storage quota management
-      // Note: we currently use temp_dir() to avoid managing storage size.
-      std::env::temp_dir()
-        .join("deno_cache")
-        .join(checksum::gen(&[key.as_bytes()]))
-    });
+    let cache_storage_dir =
+      maybe_storage_key.map(|key| cache_storage_dir(&shared.options, &key));
 
     // TODO(bartlomieju): this is cruft, update FeatureChecker to spit out
     // list of enabled features.
@@ -780,13 +3960,19 @@ fn create_web_worker_callback(
     let options = WebWorkerOptions {
       bootstrap: BootstrapOptions {
         args: shared.options.argv.clone(),
-        cpu_count: std::thread::available_parallelism()
-          .map(|p| p.get())
-          .unwrap_or(1),
+        cpu_count: shared.options.cpu_count_override.unwrap_or_else(|| {
+          std::thread::available_parallelism()
+            .map(|p| p.get())
+            .unwrap_or(1)
+        }),
         log_level: shared.options.log_level,
         enable_op_summary_metrics: shared.options.enable_op_summary_metrics,
         enable_testing_features: shared.options.enable_testing_features,
-        locale: deno_core::v8::icu::get_language_tag(),
+        locale: shared
+          .options
+          .locale_override
+          .clone()
+          .unwrap_or_else(deno_core::v8::icu::get_language_tag),
         location: Some(args.main_module.clone()),
         no_color: !colors::use_color(),
         is_tty: colors::is_tty(),
@@ -802,48 +3988,83 @@ fn create_web_worker_callback(
         node_ipc_fd: None,
         disable_deprecated_api_warning: shared.disable_deprecated_api_warning,
         verbose_deprecated_api_warning: shared.verbose_deprecated_api_warning,
+        skip_node_bootstrap: shared.options.forbid_node_builtins,
       },
-      extensions: vec![],
-      startup_snapshot: crate::js::deno_isolate_init(),
+      extensions: worker_state_extension(&shared.options)
+        .into_iter()
+        .chain(
+          shared
+            .options
+            .web_worker_extensions
+            .as_ref()
+            .map(|create_extensions| create_extensions())
+            .unwrap_or_default(),
+        )
+        .collect(),
+      startup_snapshot: startup_snapshot(&shared.options),
       unsafely_ignore_certificate_errors: shared
         .options
         .unsafely_ignore_certificate_errors
         .clone(),
       root_cert_store_provider: Some(shared.root_cert_store_provider.clone()),
+      client_cert_chain_and_key: client_cert_chain_and_key.clone(),
+      worker_activity: worker_activity.clone(),
       seed: shared.options.seed,
       create_web_worker_cb,
-      format_js_error_fn: Some(Arc::new(format_js_error)),
+      format_js_error_fn: Some(worker_format_js_error_fn(
+        maybe_worker_error_tx.clone(),
+        shared.options.error_redactor.clone(),
+      )),
       source_map_getter: maybe_source_map_getter,
       module_loader,
-      fs: shared.fs.clone(),
+      fs: fs.clone(),
       npm_resolver: Some(shared.npm_resolver.clone().into_npm_resolver()),
       worker_type: args.worker_type,
       maybe_inspector_server,
-      get_error_class_fn: Some(&errors::get_error_class_name),
+      get_error_class_fn: Some(shared.get_error_class_fn),
       blob_store: shared.blob_store.clone(),
       broadcast_channel: shared.broadcast_channel.clone(),
       shared_array_buffer_store: Some(shared.shared_array_buffer_store.clone()),
-      compiled_wasm_module_store: Some(
-        shared.compiled_wasm_module_store.clone(),
-      ),
+      compiled_wasm_module_store: (shared.options.enable_wasm_module_cache
+        && shared.options.wasm_policy.allows_module_sharing())
+      .then(|| shared.compiled_wasm_module_store.clone()),
       stdio: stdio.clone(),
       cache_storage_dir,
+      cache_quota_bytes: shared.options.cache_quota_bytes,
+      import_meta_resolve_hook: shared.options.import_meta_resolve_hook.clone(),
       feature_checker,
     };
 
-    WebWorker::bootstrap_from_options(
+    let worker_id = args.worker_id;
+    let (mut worker, handle) = WebWorker::bootstrap_from_options(
       args.name,
       args.permissions,
       args.main_module,
       args.worker_id,
       options,
-    )
+    );
+    shared
+      .options
+      .wasm_policy
+      .enforce(&mut worker.js_runtime)
+      .context("Failed to enforce the WebAssembly policy")?;
+    if let Some(registry) = &maybe_registry {
+      let mut registry = registry.lock();
+      registry.retain(|_, handle| !handle.is_terminated());
+      registry.insert(worker_id, handle.terminate_handle());
+    }
+    Ok((worker, handle))
   })
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::args::Flags;
+  use crate::args::RunFlags;
+  use crate::args::UnstableConfig;
+  use crate::factory::CliFactory;
+  use deno_core::anyhow::anyhow;
   use deno_core::resolve_path;
   use deno_runtime::permissions::Permissions;
 
@@ -860,6 +4081,31 @@ mod tests {
     MainWorker::bootstrap_from_options(main_module, permissions, options)
   }
 
+  async fn create_test_factory(
+    update_options: impl FnOnce(&mut CliMainWorkerOptions),
+  ) -> CliMainWorkerFactory {
+    let flags = Flags {
+      subcommand: DenoSubcommand::Run(RunFlags::new_default(
+        "main.js".to_string(),
+      )),
+      unstable_config: UnstableConfig {
+        features: vec![
+          deno_runtime::deno_broadcast_channel::UNSTABLE_FEATURE_NAME
+            .to_string(),
+        ],
+        ..Default::default()
+      },
+      ..Default::default()
+    };
+    let factory = CliFactory::from_flags(flags).await.unwrap();
+    let mut options = factory.create_cli_main_worker_options().unwrap();
+    update_options(&mut options);
+    factory
+      .create_cli_main_worker_factory_with_options(options)
+      .await
+      .unwrap()
+  }
+
   #[tokio::test]
   async fn execute_mod_esm_imports_a() {
     let p = test_util::testdata_path().join("runtime/esm_imports_a.js");
@@ -902,6 +4148,545 @@ mod tests {
     assert!(result.is_err());
   }
 
+  #[tokio::test]
+  async fn pending_top_level_await_only_for_stalled_modules() {
+    let temp_dir = test_util::TempDir::new();
+    temp_dir.write("stalled.js", "await new Promise(() => {});");
+    temp_dir.write(
+      "throws.js",
+      "await new Promise((r) => setTimeout(r, 1)); throw new Error();",
+    );
+    temp_dir.write(
+      "rejects.js",
+      "Promise.reject(new Error()); await new Promise(() => {});",
+    );
+
+    for (name, pending) in [
+      ("stalled.js", true),
+      ("throws.js", false),
+      ("rejects.js", false),
+    ] {
+      let specifier =
+        ModuleSpecifier::from_file_path(temp_dir.path().join(name)).unwrap();
+      let mut worker = create_test_worker();
+      let id = worker.preload_main_module(&specifier).await.unwrap();
+      assert!(worker.evaluate_module(id).await.is_err(), "{name}");
+      assert_eq!(worker.has_pending_top_level_await(), pending, "{name}");
+    }
+  }
+
+  #[tokio::test]
+  async fn broadcast_channel_round_trip() {
+    use deno_runtime::deno_broadcast_channel::BroadcastChannel;
+
+    let temp_dir = test_util::TempDir::new();
+    temp_dir.write(
+      "main.js",
+      r#"
+      const channel = new BroadcastChannel("test");
+      channel.onmessage = (event) => {
+        globalThis.reply = event.data;
+        channel.close();
+      };
+      channel.postMessage("ping");
+    "#,
+    );
+    let factory = create_test_factory(|_| {}).await;
+
+    // Echo every message the script posts back to it.
+    let broadcast_channel = factory.broadcast_channel();
+    let resource = broadcast_channel.subscribe().unwrap();
+    let host = spawn(async move {
+      let (name, data) = broadcast_channel.recv(&resource).await?.unwrap();
+      broadcast_channel
+        .send(&resource, name.clone(), data)
+        .await?;
+      Ok::<_, AnyError>(name)
+    });
+
+    let main_module =
+      ModuleSpecifier::from_file_path(temp_dir.path().join("main.js")).unwrap();
+    let mut worker = factory
+      .create_main_worker(main_module, PermissionsContainer::allow_all())
+      .await
+      .unwrap();
+    assert_eq!(worker.run().await.unwrap(), 0);
+
+    assert_eq!(host.await.unwrap().unwrap(), "test");
+    let reply = worker.eval_to_json("globalThis.reply").await.unwrap();
+    assert_eq!(reply, serde_json::json!("ping"));
+  }
+
+  #[tokio::test]
+  async fn error_redactor_applies_to_returned_errors() {
+    let temp_dir = test_util::TempDir::new();
+    temp_dir.write("main.js", "throw new Error(\"token=secret\");");
+    let factory = create_test_factory(|options| {
+      options.error_redactor =
+        Some(Arc::new(|message| message.replace("secret", "[redacted]")));
+    })
+    .await;
+    let main_module =
+      ModuleSpecifier::from_file_path(temp_dir.path().join("main.js")).unwrap();
+    let mut worker = factory
+      .create_main_worker(main_module, PermissionsContainer::allow_all())
+      .await
+      .unwrap();
+
+    let err = worker.run().await.unwrap_err().to_string();
+    assert!(err.contains("token=[redacted]"), "{err}");
+    assert!(!err.contains("secret"), "{err}");
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn sandbox_temp_dir_is_passed_to_subprocesses() {
+    let temp_dir = test_util::TempDir::new();
+    temp_dir.write(
+      "main.js",
+      r#"
+      const { stdout } = await new Deno.Command("sh", {
+        args: ["-c", "printf %s \"$TMPDIR\""],
+      }).output();
+      globalThis.childTmpDir = new TextDecoder().decode(stdout);
+      globalThis.tmpDir = Deno.env.get("TMPDIR");
+    "#,
+    );
+    let factory = create_test_factory(|options| {
+      options.sandbox_temp_dir = true;
+    })
+    .await;
+    let main_module =
+      ModuleSpecifier::from_file_path(temp_dir.path().join("main.js")).unwrap();
+    let mut worker = factory
+      .create_main_worker(main_module, PermissionsContainer::allow_all())
+      .await
+      .unwrap();
+    worker
+      .execute_main_module_possibly_with_npm()
+      .await
+      .unwrap();
+
+    let tmp_dir = worker.eval_to_json("globalThis.tmpDir").await.unwrap();
+    let child_tmp_dir =
+      worker.eval_to_json("globalThis.childTmpDir").await.unwrap();
+    assert!(tmp_dir.as_str().unwrap().contains("deno_sandbox_"));
+    assert_eq!(child_tmp_dir, tmp_dir);
+  }
+
+  #[test]
+  fn error_class_mapper_slots_are_reused() {
+    let mapper: ErrorClassMapper =
+      Arc::new(|e| e.to_string().contains("timeout").then_some("TimedOut"));
+    let (slot, get_error_class_fn) =
+      ErrorClassMapperSlot::install(mapper.clone());
+    assert_eq!(get_error_class_fn(&anyhow!("timeout")), "TimedOut");
+    assert_eq!(get_error_class_fn(&anyhow!("other")), "Error");
+
+    let index = slot.0;
+    drop(slot);
+    let (slot, get_error_class_fn) = ErrorClassMapperSlot::install(mapper);
+    assert_eq!(slot.0, index);
+    assert_eq!(get_error_class_fn(&anyhow!("timeout")), "TimedOut");
+  }
+
+  #[tokio::test]
+  async fn terminate_web_worker_dispatches_unload() {
+    let temp_dir = test_util::TempDir::new();
+    temp_dir.write(
+      "main.js",
+      r#"
+      const worker = new Worker(new URL("./worker.js", import.meta.url), {
+        type: "module",
+      });
+      await new Promise((resolve) => worker.onmessage = resolve);
+    "#,
+    );
+    temp_dir.write(
+      "worker.js",
+      r#"
+      addEventListener("unload", () => {
+        Deno.writeTextFileSync(new URL("./unloaded", import.meta.url), "");
+      });
+      postMessage("ready");
+    "#,
+    );
+    let factory = create_test_factory(|_| {}).await;
+    let main_module =
+      ModuleSpecifier::from_file_path(temp_dir.path().join("main.js")).unwrap();
+    let mut worker = factory
+      .create_main_worker(main_module, PermissionsContainer::allow_all())
+      .await
+      .unwrap();
+    worker
+      .execute_main_module_possibly_with_npm()
+      .await
+      .unwrap();
+
+    let ids = worker.web_worker_ids();
+    assert_eq!(ids.len(), 1);
+    assert!(worker.terminate_web_worker(ids[0]));
+    assert!(!worker.terminate_web_worker(ids[0]));
+
+    let unloaded = temp_dir.path().join("unloaded");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !unloaded.exists() && Instant::now() < deadline {
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(unloaded.exists());
+    assert!(worker.web_worker_ids().is_empty());
+  }
+
+  #[tokio::test]
+  async fn max_egress_bytes_limits_fetch_bodies() {
+    let _http_server_guard = test_util::http_server();
+    let temp_dir = test_util::TempDir::new();
+    temp_dir.write(
+      "main.js",
+      r#"
+      const res = await fetch("http://localhost:4545/subdir/mod2.ts");
+      globalThis.result = await res.text().then(() => "", (err) => err.message);
+    "#,
+    );
+    let factory = create_test_factory(|options| {
+      options.max_egress_bytes = Some(1);
+    })
+    .await;
+    let main_module =
+      ModuleSpecifier::from_file_path(temp_dir.path().join("main.js")).unwrap();
+    let mut worker = factory
+      .create_main_worker(main_module, PermissionsContainer::allow_all())
+      .await
+      .unwrap();
+    worker
+      .execute_main_module_possibly_with_npm()
+      .await
+      .unwrap();
+
+    let result = worker.eval_to_json("globalThis.result").await.unwrap();
+    assert_eq!(
+      result,
+      serde_json::json!("The network byte budget of 1 bytes was exceeded.")
+    );
+    assert!(worker.egress_bytes() > 1);
+  }
+
+  #[tokio::test]
+  async fn cache_quota_bytes_holds_for_concurrent_puts() {
+    let temp_dir = test_util::TempDir::new();
+    temp_dir.write(
+      "main.js",
+      r#"
+      const cache = await caches.open("test");
+      const results = await Promise.allSettled(
+        [1, 2, 3].map((i) =>
+          cache.put(`https://example.com/${i}`, new Response("x".repeat(6)))
+        ),
+      );
+      globalThis.result = results.map((result) => result.status);
+    "#,
+    );
+    let cache_dir = temp_dir.path().join("cache").to_path_buf();
+    let factory = create_test_factory(move |options| {
+      options.cache_quota_bytes = Some(10);
+      options.cache_storage_base_dir = Some(cache_dir);
+    })
+    .await;
+    let main_module =
+      ModuleSpecifier::from_file_path(temp_dir.path().join("main.js")).unwrap();
+    let mut worker = factory
+      .create_main_worker(main_module, PermissionsContainer::allow_all())
+      .await
+      .unwrap();
+    worker
+      .execute_main_module_possibly_with_npm()
+      .await
+      .unwrap();
+
+    let result = worker.eval_to_json("globalThis.result").await.unwrap();
+    let statuses = result.as_array().unwrap();
+    let fulfilled = statuses.iter().filter(|s| *s == "fulfilled").count();
+    assert_eq!(fulfilled, 1, "{result}");
+  }
+
+  #[tokio::test]
+  async fn execution_deadline_terminates_busy_loop() {
+    let temp_dir = test_util::TempDir::new();
+    temp_dir.write("main.js", "while (true) {}");
+    let factory = create_test_factory(|options| {
+      options.execution_deadline = Some(Duration::from_millis(100));
+    })
+    .await;
+    let main_module =
+      ModuleSpecifier::from_file_path(temp_dir.path().join("main.js")).unwrap();
+    let mut worker = factory
+      .create_main_worker(main_module, PermissionsContainer::allow_all())
+      .await
+      .unwrap();
+
+    let err = worker.run().await.unwrap_err().to_string();
+    assert!(err.contains("deadline exceeded"), "{err}");
+  }
+
+  #[tokio::test]
+  async fn env_override_is_isolated_per_worker() {
+    let temp_dir = test_util::TempDir::new();
+    temp_dir.write(
+      "main.js",
+      r#"
+      globalThis.result = Deno.env.get("DENO_ENV_OVERRIDE_TEST");
+      Deno.env.set("DENO_ENV_OVERRIDE_TEST", "changed");
+    "#,
+    );
+    let factory = create_test_factory(|options| {
+      options.env_override = Some(HashMap::from([(
+        "DENO_ENV_OVERRIDE_TEST".to_string(),
+        "initial".to_string(),
+      )]));
+    })
+    .await;
+    let main_module =
+      ModuleSpecifier::from_file_path(temp_dir.path().join("main.js")).unwrap();
+    for _ in 0..2 {
+      let mut worker = factory
+        .create_main_worker(
+          main_module.clone(),
+          PermissionsContainer::allow_all(),
+        )
+        .await
+        .unwrap();
+      worker
+        .execute_main_module_possibly_with_npm()
+        .await
+        .unwrap();
+      let result = worker.eval_to_json("globalThis.result").await.unwrap();
+      assert_eq!(result, "initial");
+    }
+    assert!(std::env::var("DENO_ENV_OVERRIDE_TEST").is_err());
+  }
+
+  #[tokio::test]
+  async fn virtual_time_only_advances_explicitly() {
+    let temp_dir = test_util::TempDir::new();
+    temp_dir.write(
+      "main.js",
+      r#"
+      globalThis.start = Date.now();
+      globalThis.fired = false;
+      setTimeout(() => globalThis.fired = true, 1000);
+    "#,
+    );
+    let factory = create_test_factory(|options| {
+      options.virtual_time = true;
+    })
+    .await;
+    let main_module =
+      ModuleSpecifier::from_file_path(temp_dir.path().join("main.js")).unwrap();
+    let mut worker = factory
+      .create_main_worker(main_module, PermissionsContainer::allow_all())
+      .await
+      .unwrap();
+    worker
+      .execute_main_module_possibly_with_npm()
+      .await
+      .unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    let fired = worker.eval_to_json("globalThis.fired").await.unwrap();
+    assert_eq!(fired, false);
+
+    worker.advance_time(1000).unwrap();
+    worker
+      .main_worker_mut()
+      .run_event_loop(false)
+      .await
+      .unwrap();
+    let fired = worker.eval_to_json("globalThis.fired").await.unwrap();
+    let elapsed = worker
+      .eval_to_json("Date.now() - globalThis.start")
+      .await
+      .unwrap();
+    assert_eq!(fired, true);
+    assert_eq!(elapsed, 1000);
+  }
+
+  #[tokio::test]
+  async fn run_with_budget_and_partial_reads_state_after_termination() {
+    let temp_dir = test_util::TempDir::new();
+    temp_dir.write(
+      "main.js",
+      "globalThis.partialResult = { step: 1 }; while (true) {}",
+    );
+    let factory = create_test_factory(|_| {}).await;
+    let main_module =
+      ModuleSpecifier::from_file_path(temp_dir.path().join("main.js")).unwrap();
+    let mut worker = factory
+      .create_main_worker(main_module, PermissionsContainer::allow_all())
+      .await
+      .unwrap();
+
+    let result = worker
+      .run_with_budget_and_partial(Duration::from_millis(100))
+      .await
+      .unwrap();
+    assert!(!result.completed);
+    assert_eq!(result.state, Some(serde_json::json!({ "step": 1 })));
+  }
+
+  #[tokio::test]
+  async fn max_eval_recursion_rejects_deep_imports() {
+    let temp_dir = test_util::TempDir::new();
+    temp_dir.write(
+      "main.js",
+      r#"
+      try {
+        await import("./a.js");
+      } catch (error) {
+        globalThis.result = String(error);
+      }
+    "#,
+    );
+    temp_dir.write("a.js", "import './b.js';");
+    temp_dir.write("b.js", "import './c.js';");
+    temp_dir.write("c.js", "");
+    let factory = create_test_factory(|options| {
+      options.max_eval_recursion = Some(1);
+    })
+    .await;
+    let main_module =
+      ModuleSpecifier::from_file_path(temp_dir.path().join("main.js")).unwrap();
+    let mut worker = factory
+      .create_main_worker(main_module, PermissionsContainer::allow_all())
+      .await
+      .unwrap();
+    worker
+      .execute_main_module_possibly_with_npm()
+      .await
+      .unwrap();
+
+    let result = worker.eval_to_json("globalThis.result").await.unwrap();
+    let result = result.as_str().unwrap();
+    assert!(
+      result.contains("Maximum import depth of 1 exceeded"),
+      "{result}"
+    );
+  }
+
+  #[test]
+  fn local_non_npm_module() {
+    let local = ModuleSpecifier::parse("file:///project/main.ts").unwrap();
+    assert!(is_local_non_npm_module(&local, false));
+    assert!(!is_local_non_npm_module(&local, true));
+    let in_node_modules =
+      ModuleSpecifier::parse("file:///project/node_modules/pkg/index.js")
+        .unwrap();
+    assert!(!is_local_non_npm_module(&in_node_modules, false));
+    let npm = ModuleSpecifier::parse("npm:chalk@5").unwrap();
+    assert!(!is_local_non_npm_module(&npm, false));
+  }
+
+  #[test]
+  fn directory_main_module() {
+    let temp_dir = test_util::TempDir::new();
+    let dir_specifier = |name: &str| {
+      ModuleSpecifier::from_directory_path(temp_dir.path().join(name)).unwrap()
+    };
+    let file_specifier = |name: &str| {
+      ModuleSpecifier::from_file_path(temp_dir.path().join(name)).unwrap()
+    };
+
+    temp_dir.create_dir_all("index");
+    temp_dir.write("index/index.js", "");
+    temp_dir.write("index/main.ts", "");
+    assert_eq!(
+      resolve_directory_main_module(&deno_fs::RealFs, dir_specifier("index"))
+        .unwrap(),
+      file_specifier("index/index.js"),
+    );
+
+    temp_dir.create_dir_all("package");
+    temp_dir.write("package/package.json", r#"{ "main": "lib/main.js" }"#);
+    temp_dir.write("package/index.ts", "");
+    temp_dir.create_dir_all("package/lib");
+    temp_dir.write("package/lib/main.js", "");
+    assert_eq!(
+      resolve_directory_main_module(&deno_fs::RealFs, dir_specifier("package"))
+        .unwrap(),
+      file_specifier("package/lib/main.js"),
+    );
+
+    temp_dir.create_dir_all("empty");
+    let err =
+      resolve_directory_main_module(&deno_fs::RealFs, dir_specifier("empty"))
+        .unwrap_err()
+        .to_string();
+    assert!(
+      err.contains("is a directory without an entry point"),
+      "{err}"
+    );
+
+    let file = file_specifier("index/main.ts");
+    assert_eq!(
+      resolve_directory_main_module(&deno_fs::RealFs, file.clone()).unwrap(),
+      file
+    );
+  }
+
+  #[test]
+  fn well_formed_language_tag() {
+    assert!(is_well_formed_language_tag("en"));
+    assert!(is_well_formed_language_tag("en-US"));
+    assert!(is_well_formed_language_tag("zh-Hant-TW"));
+    assert!(!is_well_formed_language_tag(""));
+    assert!(!is_well_formed_language_tag("en-"));
+    assert!(!is_well_formed_language_tag("en_US"));
+    assert!(!is_well_formed_language_tag("1en"));
+  }
+
+  #[test]
+  fn retryable_npm_errors() {
+    let err = anyhow!("Bad response: 503").context(
+      "Error getting response at https://registry.npmjs.org/chalk for package \"chalk\"",
+    );
+    assert!(is_retryable_npm_error(&err));
+    let err = anyhow!("Bad response: 401\n\nUnauthorized");
+    assert!(!is_retryable_npm_error(&err));
+    let err = AnyError::from(std::io::Error::from(ErrorKind::TimedOut))
+      .context(
+        "error sending request for url (https://registry.npmjs.org/chalk)",
+      );
+    assert!(is_retryable_npm_error(&err));
+    let err = AnyError::from(NpmResolutionError::Registry(
+      NpmRegistryPackageInfoLoadError::LoadError(Arc::new(AnyError::from(
+        std::io::Error::from(ErrorKind::ConnectionRefused),
+      ))),
+    ));
+    assert!(is_retryable_npm_error(&err));
+    let err = anyhow!("npm package 'chalk' does not exist.");
+    assert!(!is_retryable_npm_error(&err));
+    // only the kind of the error counts, not the words in its message
+    let err = AnyError::from(NpmResolutionError::Registry(
+      NpmRegistryPackageInfoLoadError::PackageNotExists {
+        package_name: "connection-pool".to_string(),
+      },
+    ));
+    assert!(!is_retryable_npm_error(&err));
+    let err = anyhow!("npm package 'timed-out' does not exist.");
+    assert!(!is_retryable_npm_error(&err));
+  }
+
+  #[test]
+  fn factory_builder_missing_fields() {
+    let err = CliMainWorkerFactory::builder()
+      .subcommand(DenoSubcommand::Types)
+      .build()
+      .err()
+      .unwrap();
+    assert_eq!(
+      err.to_string(),
+      "Missing required fields for CliMainWorkerFactory: storage_key_resolver, npm_resolver, node_resolver, blob_store, module_loader_factory, root_cert_store_provider, fs, feature_checker, options"
+    );
+  }
+
   #[tokio::test]
   async fn execute_mod_002_hello() {
     // This assumes cwd is project root (an assumption made throughout the
@@ -912,4 +4697,100 @@ mod tests {
     let result = worker.execute_main_module(&module_specifier).await;
     assert!(result.is_ok());
   }
+
+  // The smallest valid WebAssembly module: the magic number and version.
+  const EMPTY_WASM_MODULE: &str =
+    "new Uint8Array([0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00])";
+
+  #[tokio::test]
+  async fn wasm_policy_deny() {
+    let mut worker = create_test_worker();
+    WasmPolicy::Deny.enforce(&mut worker.js_runtime).unwrap();
+    let script = format!("new WebAssembly.Module({EMPTY_WASM_MODULE})");
+    assert!(worker.execute_script("deny.js", script.into()).is_err());
+    let script = format!(
+      "new WebAssembly.Module.prototype.constructor({EMPTY_WASM_MODULE})"
+    );
+    assert!(worker.execute_script("deny.js", script.into()).is_err());
+  }
+
+  #[tokio::test]
+  async fn wasm_policy_size_limited() {
+    let mut worker = create_test_worker();
+    WasmPolicy::SizeLimited(8)
+      .enforce(&mut worker.js_runtime)
+      .unwrap();
+    let script = format!("new WebAssembly.Module({EMPTY_WASM_MODULE})");
+    assert!(worker.execute_script("small.js", script.into()).is_ok());
+    let script = "new WebAssembly.Module(new Uint8Array(9))";
+    let err = worker
+      .execute_script("large.js", script.into())
+      .unwrap_err()
+      .to_string();
+    assert!(err.contains("exceeds the limit of 8 bytes"), "{err}");
+    let script =
+      "new WebAssembly.Module.prototype.constructor(new Uint8Array(9))";
+    assert!(worker.execute_script("large.js", script.into()).is_err());
+  }
+
+  #[test]
+  fn wasm_policy_module_sharing() {
+    assert!(WasmPolicy::Allow.allows_module_sharing());
+    assert!(!WasmPolicy::Deny.allows_module_sharing());
+    assert!(!WasmPolicy::SizeLimited(1).allows_module_sharing());
+  }
+
+  #[test]
+  fn tee_stdio_pipe_captures_all_output() {
+    let (mut reader, writer) = os_pipe::pipe().unwrap();
+    let captured = Arc::new(Mutex::new(Vec::new()));
+    let mut threads = Vec::new();
+    let pipe = tee_stdio_pipe(
+      StdioPipe::File(pipe_writer_to_file(writer)),
+      Box::new(std::io::sink()),
+      captured.clone(),
+      &mut threads,
+    )
+    .unwrap();
+    let StdioPipe::File(mut file) = pipe else {
+      unreachable!();
+    };
+    file.write_all(b"hello world").unwrap();
+    drop(file);
+    for finished in threads {
+      finished.blocking_recv().unwrap();
+    }
+    assert_eq!(captured.lock().as_slice(), b"hello world");
+    let mut output = String::new();
+    reader.read_to_string(&mut output).unwrap();
+    assert_eq!(output, "hello world");
+  }
+
+  #[test]
+  fn meter_stdio_pipe_truncates_output() {
+    let (mut reader, writer) = os_pipe::pipe().unwrap();
+    let mut threads = Vec::new();
+    let pipe = meter_stdio_pipe(
+      StdioPipe::File(pipe_writer_to_file(writer)),
+      Box::new(std::io::sink()),
+      5,
+      Default::default(),
+      &mut threads,
+    )
+    .unwrap();
+    let StdioPipe::File(mut file) = pipe else {
+      unreachable!();
+    };
+    file.write_all(b"hello world").unwrap();
+    drop(file);
+    for finished in threads {
+      finished.blocking_recv().unwrap();
+    }
+    let mut output = String::new();
+    reader.read_to_string(&mut output).unwrap();
+    assert_eq!(
+      output,
+      "hello\n[output truncated: exceeded the limit of 5 bytes]\n"
+    );
+  }
 }
@@ -1,5 +1,6 @@
 // Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
 use std::borrow::Cow;
+use std::path::Path;
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::rc::Rc;
@@ -8,6 +9,7 @@ use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
 use async_trait::async_trait;
+use deno_core::error::custom_error;
 use deno_core::error::AnyError;
 use deno_core::futures::future::poll_fn;
 use deno_core::parking_lot::Mutex;
@@ -20,6 +22,7 @@ use deno_core::Resource;
 use rusqlite::params;
 use rusqlite::Connection;
 use rusqlite::OptionalExtension;
+use rusqlite::TransactionBehavior;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWrite;
 use tokio::io::AsyncWriteExt;
@@ -38,6 +41,9 @@ use crate::CachePutRequest;
 pub struct SqliteBackedCache {
   pub connection: Arc<Mutex<Connection>>,
   pub cache_storage_dir: PathBuf,
+  /// The maximum number of bytes of response bodies stored across all caches
+  /// in `cache_storage_dir`. Puts that would exceed it are rejected.
+  pub quota_bytes: Option<u64>,
 }
 
 impl SqliteBackedCache {
@@ -90,9 +96,15 @@ impl SqliteBackedCache {
       SqliteBackedCache {
         connection: Arc::new(Mutex::new(connection)),
         cache_storage_dir,
+        quota_bytes: None,
       }
     }
   }
+
+  pub fn with_quota_bytes(mut self, quota_bytes: Option<u64>) -> Self {
+    self.quota_bytes = quota_bytes;
+    self
+  }
 }
 
 #[async_trait(?Send)]
@@ -182,16 +194,39 @@ impl Cache for SqliteBackedCache {
     let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
 
     if let Some(resource) = resource {
+      // The response being replaced doesn't count towards the quota. This
+      // only rejects bodies that are too large early, the quota is enforced
+      // when the response is inserted.
+      let remaining_quota = match self.quota_bytes {
+        Some(quota_bytes) => {
+          let db = db.clone();
+          let cache_storage_dir = cache_storage_dir.clone();
+          let cache_id = request_response.cache_id;
+          let request_url = request_response.request_url.clone();
+          let used_bytes = spawn_blocking(move || {
+            used_body_bytes(
+              &db.lock(),
+              &cache_storage_dir,
+              cache_id,
+              &request_url,
+            )
+          })
+          .await??;
+          Some(quota_bytes.saturating_sub(used_bytes))
+        }
+        None => None,
+      };
       let body_key = hash(&format!(
         "{}_{}",
         &request_response.request_url,
         now.as_nanos()
       ));
       let responses_dir =
-        get_responses_dir(cache_storage_dir, request_response.cache_id);
+        get_responses_dir(cache_storage_dir.clone(), request_response.cache_id);
       let response_path = responses_dir.join(&body_key);
-      let mut file = tokio::fs::File::create(response_path).await?;
+      let mut file = tokio::fs::File::create(&response_path).await?;
       let mut buf = BufMutView::new(64 * 1024);
+      let mut written = 0u64;
       loop {
         let (size, buf2) = resource.clone().read_byob(buf).await?;
         if size == 0 {
@@ -199,20 +234,46 @@ impl Cache for SqliteBackedCache {
         }
         buf = buf2;
 
+        written += size as u64;
+        if let Some(remaining_quota) = remaining_quota {
+          if written > remaining_quota {
+            drop(file);
+            let _ = tokio::fs::remove_file(&response_path).await;
+            return Err(quota_exceeded_error(
+              self.quota_bytes.unwrap_or_default(),
+            ));
+          }
+        }
+
         // Use poll_write to avoid holding a slice across await points
         poll_fn(|cx| Pin::new(&mut file).poll_write(cx, &buf[..size])).await?;
       }
 
       file.flush().await?;
       file.sync_all().await?;
+      drop(file);
 
-      assert_eq!(
-        insert_cache_asset(db, request_response, Some(body_key.clone()),)
-          .await?,
-        Some(body_key)
-      );
+      let quota = self.quota_bytes.map(|quota_bytes| BodyQuota {
+        quota_bytes,
+        body_bytes: written,
+        cache_storage_dir,
+      });
+      match insert_cache_asset(
+        db,
+        request_response,
+        Some(body_key.clone()),
+        quota,
+      )
+      .await
+      {
+        Ok(inserted_body_key) => assert_eq!(inserted_body_key, Some(body_key)),
+        Err(err) => {
+          let _ = tokio::fs::remove_file(&response_path).await;
+          return Err(err);
+        }
+      }
     } else {
-      assert!(insert_cache_asset(db, request_response, None)
+      assert!(insert_cache_asset(db, request_response, None, None)
         .await?
         .is_none());
     }
@@ -317,15 +378,38 @@ impl Cache for SqliteBackedCache {
   }
 }
 
+/// The quota a response body is checked against when it is inserted.
+struct BodyQuota {
+  quota_bytes: u64,
+  body_bytes: u64,
+  cache_storage_dir: PathBuf,
+}
+
 async fn insert_cache_asset(
   db: Arc<Mutex<rusqlite::Connection>>,
   put: CachePutRequest,
   response_body_key: Option<String>,
+  quota: Option<BodyQuota>,
 ) -> Result<Option<String>, deno_core::anyhow::Error> {
   spawn_blocking(move || {
     let maybe_response_body = {
-      let db = db.lock();
-      db.query_row(
+      let mut db = db.lock();
+      // Checking the quota and inserting in one immediate transaction keeps
+      // concurrent puts, even of other connections, from together exceeding
+      // the quota.
+      let tx = db.transaction_with_behavior(TransactionBehavior::Immediate)?;
+      if let Some(quota) = &quota {
+        let used_bytes = used_body_bytes(
+          &tx,
+          &quota.cache_storage_dir,
+          put.cache_id,
+          &put.request_url,
+        )?;
+        if used_bytes + quota.body_bytes > quota.quota_bytes {
+          return Err(quota_exceeded_error(quota.quota_bytes));
+        }
+      }
+      let maybe_response_body = tx.query_row(
         "INSERT OR REPLACE INTO request_response_list
              (cache_id, request_url, request_headers, response_headers,
               response_body_key, response_status, response_status_text, last_inserted_at)
@@ -345,12 +429,51 @@ async fn insert_cache_asset(
           let response_body_key: Option<String> = row.get(0)?;
           Ok(response_body_key)
         },
-      )?
+      )?;
+      tx.commit()?;
+      maybe_response_body
     };
       Ok::<Option<String>, AnyError>(maybe_response_body)
   }).await?
 }
 
+/// Sums up the sizes of the stored response bodies of all caches, except the
+/// one of `request_url` in the cache `cache_id`.
+fn used_body_bytes(
+  db: &rusqlite::Connection,
+  cache_storage_dir: &Path,
+  cache_id: i64,
+  request_url: &str,
+) -> Result<u64, AnyError> {
+  let mut stmt = db.prepare(
+    "SELECT cache_id, response_body_key FROM request_response_list
+         WHERE response_body_key IS NOT NULL
+         AND NOT (cache_id = ?1 AND request_url = ?2)",
+  )?;
+  let rows = stmt.query_map((cache_id, request_url), |row| {
+    let cache_id: i64 = row.get(0)?;
+    let response_body_key: String = row.get(1)?;
+    Ok((cache_id, response_body_key))
+  })?;
+  let body_keys = rows.collect::<Result<Vec<_>, _>>()?;
+  let used_bytes = body_keys
+    .into_iter()
+    .filter_map(|(cache_id, response_body_key)| {
+      let path = get_responses_dir(cache_storage_dir.to_path_buf(), cache_id)
+        .join(response_body_key);
+      std::fs::metadata(path).ok().map(|metadata| metadata.len())
+    })
+    .sum();
+  Ok(used_bytes)
+}
+
+fn quota_exceeded_error(quota_bytes: u64) -> AnyError {
+  custom_error(
+    "DOMExceptionQuotaExceededError",
+    format!("Cache storage quota of {quota_bytes} bytes exceeded"),
+  )
+}
+
 #[inline]
 fn get_responses_dir(cache_storage_dir: PathBuf, cache_id: i64) -> PathBuf {
   cache_storage_dir
@@ -10,6 +10,8 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::rc::Rc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::task::Context;
 use std::task::Poll;
@@ -326,6 +328,11 @@ where
       let permissions = state.borrow_mut::<FP>();
       permissions.check_net_url(&url, "fetch()")?;
 
+      let maybe_byte_budget = state.try_borrow::<FetchByteBudget>().cloned();
+      if let Some(byte_budget) = &maybe_byte_budget {
+        byte_budget.check()?;
+      }
+
       // Make sure that we have a valid URI early, as reqwest's `RequestBuilder::send`
       // internally uses `expect_uri`, which panics instead of returning a usable `Result`.
       if url.as_str().parse::<Uri>().is_err() {
@@ -337,6 +344,9 @@ where
       if has_body {
         match (data, resource) {
           (Some(data), _) => {
+            if let Some(byte_budget) = &maybe_byte_budget {
+              byte_budget.charge(data.len() as u64)?;
+            }
             // If a body is passed, we use it, and don't return a body for streaming.
             request = request.body(data.to_vec());
           }
@@ -349,8 +359,17 @@ where
               }
               _ => {}
             }
-            request = request
-              .body(Body::wrap_stream(ResourceToBodyAdapter::new(resource)))
+            let body = ResourceToBodyAdapter::new(resource);
+            request = match maybe_byte_budget {
+              Some(byte_budget) => {
+                request.body(Body::wrap_stream(body.map(move |chunk| {
+                  let chunk = chunk?;
+                  byte_budget.charge(chunk.len() as u64)?;
+                  Ok::<_, Error>(chunk)
+                })))
+              }
+              None => request.body(Body::wrap_stream(body)),
+            };
           }
           (None, None) => unreachable!(),
         }
@@ -475,6 +494,15 @@ pub async fn op_fetch_send(
     .ok()
     .expect("multiple op_fetch_send ongoing");
 
+  let maybe_limit = state
+    .borrow()
+    .try_borrow::<FetchConcurrencyLimit>()
+    .cloned();
+  let concurrency_permit = match maybe_limit {
+    Some(limit) => Some(limit.0.acquire_owned().await?),
+    None => None,
+  };
+
   let res = match request.0.await {
     Ok(Ok(res)) => res,
     Ok(Err(err)) => {
@@ -512,16 +540,22 @@ pub async fn op_fetch_send(
 
   let content_length = res.content_length();
   let remote_addr = res.remote_addr();
+  let byte_budget = state.borrow().try_borrow::<FetchByteBudget>().cloned();
   let (remote_addr_ip, remote_addr_port) = if let Some(addr) = remote_addr {
     (Some(addr.ip().to_string()), Some(addr.port()))
   } else {
     (None, None)
   };
 
-  let response_rid = state
-    .borrow_mut()
-    .resource_table
-    .add(FetchResponseResource::new(res, content_length));
+  let response_rid =
+    state
+      .borrow_mut()
+      .resource_table
+      .add(FetchResponseResource {
+        byte_budget,
+        concurrency_permit,
+        ..FetchResponseResource::new(res, content_length)
+      });
 
   Ok(FetchResponse {
     status: status.as_u16(),
@@ -645,6 +679,70 @@ impl Resource for UpgradeStream {
 
 type CancelableResponseResult = Result<Result<Response, AnyError>, Canceled>;
 
+/// When put into the `OpState`, limits how many fetch requests can be in
+/// flight at the same time. A request holds its slot until its response body
+/// is consumed or dropped. Excess requests are queued until a slot is free.
+#[derive(Clone)]
+pub struct FetchConcurrencyLimit(Arc<tokio::sync::Semaphore>);
+
+impl FetchConcurrencyLimit {
+  pub fn new(max_concurrent_fetches: usize) -> Self {
+    Self(Arc::new(tokio::sync::Semaphore::new(
+      max_concurrent_fetches,
+    )))
+  }
+}
+
+/// When put into the `OpState`, counts the bytes of the request and response
+/// bodies sent and received by `fetch`. Once `max_bytes` is exceeded, reading
+/// or sending more body bytes fails, as does starting a new request.
+/// Other network traffic, like that of `Deno.connect` or WebSockets, isn't
+/// counted.
+#[derive(Clone, Debug)]
+pub struct FetchByteBudget(Arc<FetchByteBudgetState>);
+
+#[derive(Debug)]
+struct FetchByteBudgetState {
+  used: AtomicU64,
+  max_bytes: u64,
+}
+
+impl FetchByteBudget {
+  pub fn new(max_bytes: u64) -> Self {
+    Self(Arc::new(FetchByteBudgetState {
+      used: AtomicU64::new(0),
+      max_bytes,
+    }))
+  }
+
+  /// Returns how many body bytes were sent and received so far.
+  pub fn used(&self) -> u64 {
+    self.0.used.load(Ordering::Relaxed)
+  }
+
+  fn check(&self) -> Result<(), AnyError> {
+    if self.used() >= self.0.max_bytes {
+      return Err(self.exceeded_error());
+    }
+    Ok(())
+  }
+
+  fn charge(&self, bytes: u64) -> Result<(), AnyError> {
+    let used = self.0.used.fetch_add(bytes, Ordering::Relaxed) + bytes;
+    if used > self.0.max_bytes {
+      return Err(self.exceeded_error());
+    }
+    Ok(())
+  }
+
+  fn exceeded_error(&self) -> AnyError {
+    type_error(format!(
+      "The network byte budget of {} bytes was exceeded.",
+      self.0.max_bytes
+    ))
+  }
+}
+
 pub struct FetchRequestResource(
   pub Pin<Box<dyn Future<Output = CancelableResponseResult>>>,
 );
@@ -686,6 +784,9 @@ pub struct FetchResponseResource {
   pub response_reader: AsyncRefCell<FetchResponseReader>,
   pub cancel: CancelHandle,
   pub size: Option<u64>,
+  pub byte_budget: Option<FetchByteBudget>,
+  /// Slot of the [`FetchConcurrencyLimit`] held while the body is read.
+  pub concurrency_permit: Option<tokio::sync::OwnedSemaphorePermit>,
 }
 
 impl FetchResponseResource {
@@ -694,6 +795,8 @@ impl FetchResponseResource {
       response_reader: AsyncRefCell::new(FetchResponseReader::Start(response)),
       cancel: CancelHandle::default(),
       size,
+      byte_budget: None,
+      concurrency_permit: None,
     }
   }
 
@@ -734,12 +837,18 @@ impl Resource for FetchResponseResource {
           FetchResponseReader::BodyReader(_) => unreachable!(),
         }
       };
+      let byte_budget = self.byte_budget.clone();
       let fut = async move {
         let mut reader = Pin::new(body);
         loop {
           match reader.as_mut().peek_mut().await {
             Some(Ok(chunk)) if !chunk.is_empty() => {
               let len = min(limit, chunk.len());
+              if let Some(byte_budget) = &byte_budget {
+                if let Err(err) = byte_budget.charge(len as u64) {
+                  break Err(err);
+                }
+              }
               let chunk = chunk.split_to(len);
               break Ok(chunk.into());
             }
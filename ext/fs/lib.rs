@@ -19,6 +19,17 @@ use crate::ops::*;
 use deno_core::error::AnyError;
 use deno_core::OpState;
 use std::path::Path;
+use std::path::PathBuf;
+
+/// When put into the `OpState`, `Deno.readDir()` and `Deno.readDirSync()`
+/// return their entries sorted by name instead of in the order reported by
+/// the file system.
+pub struct SortedReadDir;
+
+/// When put into the `OpState`, `Deno.makeTempDir()` and `Deno.makeTempFile()`
+/// create their entries in this directory instead of the system temporary
+/// directory, unless an explicit `dir` is passed.
+pub struct TempDirOverride(pub PathBuf);
 
 pub trait FsPermissions {
   fn check_read(&mut self, path: &Path, api_name: &str)
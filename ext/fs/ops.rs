@@ -32,6 +32,8 @@ use crate::interface::FsDirEntry;
 use crate::interface::FsFileType;
 use crate::FsPermissions;
 use crate::OpenOptions;
+use crate::SortedReadDir;
+use crate::TempDirOverride;
 
 #[op2]
 #[string]
@@ -526,7 +528,10 @@ where
     .check_read(&path, "Deno.readDirSync()")?;
 
   let fs = state.borrow::<FileSystemRc>();
-  let entries = fs.read_dir_sync(&path).context_path("readdir", &path)?;
+  let mut entries = fs.read_dir_sync(&path).context_path("readdir", &path)?;
+  if state.has::<SortedReadDir>() {
+    sort_dir_entries(&mut entries);
+  }
 
   Ok(entries)
 }
@@ -542,22 +547,32 @@ where
 {
   let path = PathBuf::from(path);
 
-  let fs = {
+  let (fs, sorted) = {
     let mut state = state.borrow_mut();
     state
       .borrow_mut::<P>()
       .check_read(&path, "Deno.readDir()")?;
-    state.borrow::<FileSystemRc>().clone()
+    (
+      state.borrow::<FileSystemRc>().clone(),
+      state.has::<SortedReadDir>(),
+    )
   };
 
-  let entries = fs
+  let mut entries = fs
     .read_dir_async(path.clone())
     .await
     .context_path("readdir", &path)?;
+  if sorted {
+    sort_dir_entries(&mut entries);
+  }
 
   Ok(entries)
 }
 
+fn sort_dir_entries(entries: &mut [FsDirEntry]) {
+  entries.sort_by(|a, b| a.name.cmp(&b.name));
+}
+
 #[op2(fast)]
 pub fn op_fs_rename_sync<P>(
   state: &mut OpState,
@@ -1035,7 +1050,10 @@ where
       dir
     }
     None => {
-      let dir = fs.tmp_dir().context("tmpdir")?;
+      let dir = match state.try_borrow::<TempDirOverride>() {
+        Some(temp_dir) => temp_dir.0.clone(),
+        None => fs.tmp_dir().context("tmpdir")?,
+      };
       state.borrow_mut::<P>().check_write_blind(
         &dir,
         "TMP",
@@ -1065,7 +1083,10 @@ where
       dir
     }
     None => {
-      let dir = fs.tmp_dir().context("tmpdir")?;
+      let dir = match state.try_borrow::<TempDirOverride>() {
+        Some(temp_dir) => temp_dir.0.clone(),
+        None => fs.tmp_dir().context("tmpdir")?,
+      };
       state.borrow_mut::<P>().check_write_blind(
         &dir,
         "TMP",
@@ -32,6 +32,7 @@ mod resolution;
 
 pub use ops::ipc::ChildPipeFd;
 pub use ops::ipc::IpcJsonStreamResource;
+pub use ops::require::RequireInterceptor;
 pub use ops::v8::VM_CONTEXT_INDEX;
 pub use package_json::PackageJson;
 pub use path::PathClean;
@@ -322,6 +323,7 @@ deno_core::extension!(deno_node,
     ops::require::op_require_read_package_scope<P>,
     ops::require::op_require_package_imports_resolve<P>,
     ops::require::op_require_break_on_next_statement,
+    ops::require::op_require_intercept,
     ops::util::op_node_guess_handle_type,
     ops::crypto::op_node_create_private_key,
     ops::ipc::op_node_child_ipc_pipe,
@@ -14,6 +14,7 @@ use std::cell::RefCell;
 use std::path::Path;
 use std::path::PathBuf;
 use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::resolution;
 use crate::NodeModuleKind;
@@ -23,6 +24,14 @@ use crate::NodeResolver;
 use crate::NpmResolverRc;
 use crate::PackageJson;
 
+/// When put into the `OpState`, called with every `require` request and the
+/// filename of the requiring module, if any, before the request is resolved.
+/// Returning a request replaces the original one, e.g. to inject a mock.
+#[derive(Clone)]
+pub struct RequireInterceptor(
+  pub Arc<dyn Fn(&str, Option<&str>) -> Option<String> + Send + Sync>,
+);
+
 fn ensure_read_permission<P>(
   state: &mut OpState,
   file_path: &Path,
@@ -613,3 +622,14 @@ pub fn op_require_break_on_next_statement(state: &mut OpState) {
     .borrow_mut()
     .wait_for_session_and_break_on_next_statement()
 }
+
+#[op2]
+#[string]
+pub fn op_require_intercept(
+  state: &mut OpState,
+  #[string] request: String,
+  #[string] parent_filename: Option<String>,
+) -> Option<String> {
+  let interceptor = state.try_borrow::<RequireInterceptor>()?;
+  (interceptor.0)(&request, parent_filename.as_deref())
+}
@@ -8,6 +8,7 @@ import {
   op_require_as_file_path,
   op_require_break_on_next_statement,
   op_require_init_paths,
+  op_require_intercept,
   op_require_is_deno_dir_package,
   op_require_is_request_relative,
   op_require_node_module_paths,
@@ -680,6 +681,13 @@ Module._resolveLookupPaths = function (request, parent) {
 };
 
 Module._load = function (request, parent, isMain) {
+  const interceptedRequest = op_require_intercept(
+    request,
+    parent?.filename ?? null,
+  );
+  if (interceptedRequest !== null) {
+    request = interceptedRequest;
+  }
   let relResolveCacheIdentifier;
   if (parent) {
     // Fast path for (lazy loaded) modules in the same directory. The indirect
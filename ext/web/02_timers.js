@@ -1,6 +1,6 @@
 // Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
 
-import { core, primordials } from "ext:core/mod.js";
+import { core, internals, primordials } from "ext:core/mod.js";
 import {
   op_now,
   op_sleep,
@@ -10,11 +10,13 @@ import {
 const {
   ArrayPrototypePush,
   ArrayPrototypeShift,
+  MathMax,
   FunctionPrototypeCall,
   MapPrototypeDelete,
   MapPrototypeGet,
   MapPrototypeHas,
   MapPrototypeSet,
+  SafeMapIterator,
   Uint8Array,
   Uint32Array,
   PromisePrototypeThen,
@@ -78,7 +80,7 @@ function handleTimerMacrotask() {
  * The keys in this map correspond to the key ID's in the spec's map of active
  * timers. The values are the timeout's cancel rid.
  *
- * @type {Map<number, { cancelRid: number, isRef: boolean, promise: Promise<void> }>}
+ * @type {Map<number, { cancelRid: number, isRef: boolean, promise: Promise<void>, repeat: boolean, dueAt: number }>}
  */
 const activeTimers = new SafeMap();
 
@@ -117,7 +119,7 @@ function initializeTimer(
     // https://github.com/whatwg/html/issues/7358
     id = nextId++;
     const cancelRid = op_timer_handle();
-    timerInfo = { cancelRid, isRef: true, promise: null };
+    timerInfo = { cancelRid, isRef: true, promise: null, repeat, dueAt: 0 };
 
     // Step 4 in "run steps after a timeout".
     MapPrototypeSet(activeTimers, id, timerInfo);
@@ -219,10 +221,11 @@ const scheduledTimers = { head: null, tail: null };
  * @param { {action: () => void, nestingLevel: number}[] } task Will be run
  * after the timeout, if it hasn't been cancelled.
  * @param {number} millis
- * @param {{ cancelRid: number, isRef: boolean, promise: Promise<void> }} timerInfo
+ * @param {{ cancelRid: number, isRef: boolean, promise: Promise<void>, repeat: boolean, dueAt: number }} timerInfo
  */
 function runAfterTimeout(task, millis, timerInfo) {
   const cancelRid = timerInfo.cancelRid;
+  timerInfo.dueAt = opNow() + millis;
   let sleepPromise;
   // If this timeout is scheduled for 0ms it means we want it to run at the
   // end of the event loop turn. There's no point in setting up a Tokio timer,
@@ -318,6 +321,25 @@ function removeFromScheduledTimers(timerObj) {
   }
 }
 
+/**
+ * Lists the active timers, for debugging why the event loop stays alive.
+ * @returns { {id: number, dueInMs: number, repeating: boolean}[] }
+ */
+function pendingTimers() {
+  const now = opNow();
+  const timers = [];
+  for (const { 0: id, 1: timerInfo } of new SafeMapIterator(activeTimers)) {
+    ArrayPrototypePush(timers, {
+      id,
+      dueInMs: MathMax(0, timerInfo.dueAt - now),
+      repeating: timerInfo.repeat,
+    });
+  }
+  return timers;
+}
+
+internals.pendingTimers = pendingTimers;
+
 // ---------------------------------------------------------------------------
 
 function checkThis(thisArg) {
@@ -56,6 +56,7 @@ use crate::timers::op_sleep;
 use crate::timers::op_timer_handle;
 use crate::timers::StartTime;
 pub use crate::timers::TimersPermission;
+pub use crate::timers::VirtualClock;
 
 deno_core::extension!(deno_web,
   deps = [ deno_webidl, deno_console, deno_url ],
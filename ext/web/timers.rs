@@ -10,6 +10,7 @@ use deno_core::CancelHandle;
 use deno_core::OpState;
 use deno_core::Resource;
 use deno_core::ResourceId;
+use futures::channel::oneshot;
 use std::borrow::Cow;
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -22,6 +23,52 @@ pub trait TimersPermission {
 
 pub type StartTime = Instant;
 
+/// When put into the `OpState`, `performance.now()` and timers follow this
+/// clock instead of the wall clock. It only moves forward when
+/// [`VirtualClock::advance`] is called.
+#[derive(Clone, Default)]
+pub struct VirtualClock(Rc<RefCell<VirtualClockState>>);
+
+#[derive(Default)]
+struct VirtualClockState {
+  elapsed: Duration,
+  timers: Vec<(Duration, oneshot::Sender<()>)>,
+}
+
+impl VirtualClock {
+  /// Returns the virtual time passed since the clock was created.
+  pub fn elapsed(&self) -> Duration {
+    self.0.borrow().elapsed
+  }
+
+  /// Moves the clock forward, resolving the sleeps that are due. Their
+  /// timers fire the next time the event loop is polled.
+  pub fn advance(&self, by: Duration) {
+    let mut state = self.0.borrow_mut();
+    state.elapsed += by;
+    let now = state.elapsed;
+    let (due, pending) = std::mem::take(&mut state.timers)
+      .into_iter()
+      .partition::<Vec<_>, _>(|(deadline, _)| *deadline <= now);
+    state.timers = pending;
+    for (_, sender) in due {
+      let _ = sender.send(());
+    }
+  }
+
+  fn sleep(&self, duration: Duration) -> oneshot::Receiver<()> {
+    let (sender, receiver) = oneshot::channel();
+    let mut state = self.0.borrow_mut();
+    if duration.is_zero() {
+      let _ = sender.send(());
+    } else {
+      let deadline = state.elapsed + duration;
+      state.timers.push((deadline, sender));
+    }
+    receiver
+  }
+}
+
 // Returns a milliseconds and nanoseconds subsec
 // since the start time of the deno runtime.
 // If the High precision flag is not set, the
@@ -31,8 +78,10 @@ pub fn op_now<TP>(state: &mut OpState, #[buffer] buf: &mut [u8])
 where
   TP: TimersPermission + 'static,
 {
-  let start_time = state.borrow::<StartTime>();
-  let elapsed = start_time.elapsed();
+  let elapsed = match state.try_borrow::<VirtualClock>() {
+    Some(clock) => clock.elapsed(),
+    None => state.borrow::<StartTime>().elapsed(),
+  };
   let seconds = elapsed.as_secs();
   let mut subsec_nanos = elapsed.subsec_nanos();
 
@@ -91,6 +140,15 @@ pub async fn op_sleep(
     return Ok(false);
   };
 
+  let maybe_clock = state.borrow().try_borrow::<VirtualClock>().cloned();
+  if let Some(clock) = maybe_clock {
+    let res = clock
+      .sleep(Duration::from_millis(millis))
+      .or_cancel(handle.0.clone())
+      .await;
+    return Ok(matches!(res, Ok(Ok(()))));
+  }
+
   // If a timer is requested with <=100ms resolution, request the high-res timer. Since the default
   // Windows timer period is 15ms, this means a 100ms timer could fire at 115ms (15% late). We assume that
   // timers longer than 100ms are a reasonable cutoff here.
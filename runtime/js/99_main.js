@@ -469,6 +469,11 @@ function processUnhandledPromiseRejection(promise, reason) {
     internals.nodeProcessUnhandledRejectionCallback(rejectionEvent);
   }
 
+  // Give the embedder a chance to swallow the rejection.
+  if (!rejectionEvent.defaultPrevented && ops.op_unhandled_rejection(reason)) {
+    return true;
+  }
+
   // If event was not prevented (or "unhandledrejection" listeners didn't
   // throw) we will let Rust side handle it.
   if (rejectionEvent.defaultPrevented) {
@@ -578,6 +583,7 @@ function bootstrapMainRuntime(runtimeOptions) {
     6: maybeBinaryNpmCommandName,
     7: shouldDisableDeprecatedApiWarning,
     8: shouldUseVerboseDeprecatedApiWarning,
+    9: shouldSkipNodeBootstrap,
   } = runtimeOptions;
 
   deprecatedApiWarningDisabled = shouldDisableDeprecatedApiWarning;
@@ -695,7 +701,7 @@ function bootstrapMainRuntime(runtimeOptions) {
   // `Deno` with `Deno` namespace from "./deno.ts".
   ObjectDefineProperty(globalThis, "Deno", util.readOnly(finalDenoNs));
 
-  if (nodeBootstrap) {
+  if (nodeBootstrap && !shouldSkipNodeBootstrap) {
     nodeBootstrap(hasNodeModulesDir, maybeBinaryNpmCommandName);
   }
 }
@@ -720,6 +726,7 @@ function bootstrapWorkerRuntime(
     6: maybeBinaryNpmCommandName,
     7: shouldDisableDeprecatedApiWarning,
     8: shouldUseVerboseDeprecatedApiWarning,
+    9: shouldSkipNodeBootstrap,
   } = runtimeOptions;
 
   deprecatedApiWarningDisabled = shouldDisableDeprecatedApiWarning;
@@ -818,12 +825,27 @@ function bootstrapWorkerRuntime(
   // existing global `Deno` with `Deno` namespace from "./deno.ts".
   ObjectDefineProperty(globalThis, "Deno", util.readOnly(finalDenoNs));
 
-  if (nodeBootstrap) {
+  if (nodeBootstrap && !shouldSkipNodeBootstrap) {
     nodeBootstrap(hasNodeModulesDir, maybeBinaryNpmCommandName);
   }
 }
 
+// Used by the Rust side to skip dispatching lifecycle events that nobody
+// listens to.
+function hasGlobalEventListeners(type) {
+  return event.listenerCount(globalThis_, type) > 0;
+}
+
+// Used by the Rust side to change the deprecation warning settings after
+// bootstrap.
+function setDeprecatedApiWarning(disable, verbose) {
+  deprecatedApiWarningDisabled = disable;
+  verboseDeprecatedApiWarning = verbose;
+}
+
 globalThis.bootstrap = {
   mainRuntime: bootstrapMainRuntime,
   workerRuntime: bootstrapWorkerRuntime,
+  hasGlobalEventListeners,
+  setDeprecatedApiWarning,
 };
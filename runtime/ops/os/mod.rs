@@ -14,9 +14,22 @@ use deno_node::NODE_ENV_VAR_ALLOWLIST;
 use serde::Serialize;
 use std::collections::HashMap;
 use std::env;
+use std::sync::Arc;
 
 mod sys_info;
 
+/// A custom backing store for `Deno.env`, used instead of the process
+/// environment when an [`SharedEnvProvider`] is put into the op state.
+pub trait EnvProvider {
+  fn get(&self, key: &str) -> Result<Option<String>, AnyError>;
+  fn set(&self, key: &str, value: &str) -> Result<(), AnyError>;
+  fn delete(&self, key: &str) -> Result<(), AnyError>;
+  fn list(&self) -> Result<HashMap<String, String>, AnyError>;
+}
+
+#[derive(Clone)]
+pub struct SharedEnvProvider(pub Arc<dyn EnvProvider + Send + Sync>);
+
 deno_core::extension!(
   deno_os,
   ops = [
@@ -107,6 +120,9 @@ fn op_set_env(
       "Value contains invalid characters: {value:?}"
     )));
   }
+  if let Some(provider) = state.try_borrow::<SharedEnvProvider>() {
+    return provider.0.set(key, value);
+  }
   env::set_var(key, value);
   Ok(())
 }
@@ -115,6 +131,9 @@ fn op_set_env(
 #[serde]
 fn op_env(state: &mut OpState) -> Result<HashMap<String, String>, AnyError> {
   state.borrow_mut::<PermissionsContainer>().check_env_all()?;
+  if let Some(provider) = state.try_borrow::<SharedEnvProvider>() {
+    return provider.0.list();
+  }
   Ok(env::vars().collect())
 }
 
@@ -140,6 +159,10 @@ fn op_get_env(
     )));
   }
 
+  if let Some(provider) = state.try_borrow::<SharedEnvProvider>() {
+    return provider.0.get(&key);
+  }
+
   let r = match env::var(key) {
     Err(env::VarError::NotPresent) => None,
     v => Some(v?),
@@ -156,13 +179,16 @@ fn op_delete_env(
   if key.is_empty() || key.contains(&['=', '\0'] as &[char]) {
     return Err(type_error("Key contains invalid characters."));
   }
+  if let Some(provider) = state.try_borrow::<SharedEnvProvider>() {
+    return provider.0.delete(&key);
+  }
   env::remove_var(key);
   Ok(())
 }
 
 #[op2(fast)]
 fn op_set_exit_code(state: &mut OpState, #[smi] code: i32) {
-  state.borrow_mut::<ExitCode>().set(code);
+  state.borrow_mut::<ExitCode>().set_explicit(code);
 }
 
 #[op2(fast)]
@@ -12,6 +12,7 @@ use deno_core::OpState;
 use serde::Deserialize;
 use serde::Serialize;
 use std::path::Path;
+use std::sync::Arc;
 
 deno_core::extension!(
   deno_permissions,
@@ -22,6 +23,17 @@ deno_core::extension!(
   ],
 );
 
+pub type PermissionRevokedCallback = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Lets the embedder observe `Deno.permissions.revoke()`. Takes effect when
+/// put into the `OpState` of a worker. To make revocations final, see
+/// `Permissions::set_deny_revoked`.
+#[derive(Clone, Default)]
+pub struct PermissionRevocationOptions {
+  /// Called with the name of the permission after a script revoked it.
+  pub on_revoked: Option<PermissionRevokedCallback>,
+}
+
 #[derive(Deserialize)]
 pub struct PermissionArgs {
   name: String,
@@ -118,6 +130,13 @@ pub fn op_revoke_permission(
       ))
     }
   };
+  drop(permissions);
+  if let Some(on_revoked) = state
+    .try_borrow::<PermissionRevocationOptions>()
+    .and_then(|options| options.on_revoked.as_ref())
+  {
+    on_revoked(&args.name);
+  }
   Ok(PermissionStatus::from(perm))
 }
 
@@ -22,6 +22,7 @@ use serde::Deserialize;
 use serde::Serialize;
 use std::borrow::Cow;
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::process::ExitStatus;
 use std::rc::Rc;
 use tokio::process::Command;
@@ -36,6 +37,12 @@ use std::os::unix::process::CommandExt;
 
 pub const UNSTABLE_FEATURE_NAME: &str = "process";
 
+/// When put into the `OpState`, these environment variables are set for every
+/// subprocess that does not clear its environment. Variables passed to the
+/// subprocess explicitly take precedence.
+#[derive(Clone, Default)]
+pub struct SubprocessEnv(pub HashMap<String, String>);
+
 #[derive(Copy, Clone, Eq, PartialEq, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum Stdio {
@@ -237,6 +244,8 @@ fn create_command(
 
   if args.clear_env {
     command.env_clear();
+  } else if let Some(env) = state.try_borrow::<SubprocessEnv>() {
+    command.envs(&env.0);
   }
   command.envs(args.env);
 
@@ -716,6 +725,8 @@ mod deprecated {
     if run_args.clear_env {
       super::check_unstable(state, UNSTABLE_FEATURE_NAME, "Deno.run.clearEnv");
       c.env_clear();
+    } else if let Some(env) = state.try_borrow::<SubprocessEnv>() {
+      c.envs(&env.0);
     }
     for (key, value) in &env {
       c.env(key, value);
@@ -2,19 +2,42 @@
 
 use crate::permissions::PermissionsContainer;
 use deno_core::error::AnyError;
+use deno_core::error::JsError;
 use deno_core::op2;
+use deno_core::v8;
 use deno_core::ModuleSpecifier;
 use deno_core::OpState;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
 
 deno_core::extension!(
   deno_runtime,
-  ops = [op_main_module, op_ppid],
+  ops = [op_main_module, op_ppid, op_unhandled_rejection],
   options = { main_module: ModuleSpecifier },
   state = |state, options| {
     state.put::<ModuleSpecifier>(options.main_module);
   },
 );
 
+/// What to do with a promise rejection that no `unhandledrejection`
+/// listener prevented.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnhandledRejectionAction {
+  /// Ignore the rejection and keep the worker running.
+  Swallow,
+  /// Terminate the worker with the rejection as error, like by default.
+  Abort,
+}
+
+pub type UnhandledRejectionCallback =
+  Arc<dyn Fn(&AnyError) -> UnhandledRejectionAction + Send + Sync>;
+
+/// Decides what happens to unhandled promise rejections. Takes effect when
+/// put into the `OpState` of a worker.
+#[derive(Clone)]
+pub struct UnhandledRejectionHandler(pub UnhandledRejectionCallback);
+
 #[op2]
 #[string]
 fn op_main_module(state: &mut OpState) -> Result<String, AnyError> {
@@ -29,6 +52,24 @@ fn op_main_module(state: &mut OpState) -> Result<String, AnyError> {
   Ok(main_path)
 }
 
+/// Returns true if the rejection should be swallowed.
+#[op2]
+fn op_unhandled_rejection(
+  scope: &mut v8::HandleScope,
+  state: Rc<RefCell<OpState>>,
+  reason: v8::Local<v8::Value>,
+) -> bool {
+  let Some(handler) = state
+    .borrow()
+    .try_borrow::<UnhandledRejectionHandler>()
+    .cloned()
+  else {
+    return false;
+  };
+  let error = AnyError::from(JsError::from_v8_exception(scope, reason));
+  (handler.0)(&error) == UnhandledRejectionAction::Swallow
+}
+
 /// This is an op instead of being done at initialization time because
 /// it's expensive to retrieve the ppid on Windows.
 #[op2(fast)]
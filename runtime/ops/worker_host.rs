@@ -37,7 +37,9 @@ pub struct CreateWebWorkerArgs {
   pub worker_type: WebWorkerType,
 }
 
-pub type CreateWebWorkerCb = dyn Fn(CreateWebWorkerArgs) -> (WebWorker, SendableWebWorkerHandle)
+pub type CreateWebWorkerCb = dyn Fn(
+    CreateWebWorkerArgs,
+  ) -> Result<(WebWorker, SendableWebWorkerHandle), AnyError>
   + Sync
   + Send;
 
@@ -181,15 +183,21 @@ fn op_create_worker(
     //  all action done upon it should be noops
     // - newly spawned thread exits
 
-    let (worker, external_handle) =
-      (create_web_worker_cb.0)(CreateWebWorkerArgs {
-        name: worker_name,
-        worker_id,
-        parent_permissions,
-        permissions: worker_permissions,
-        main_module: module_specifier.clone(),
-        worker_type,
-      });
+    let result = (create_web_worker_cb.0)(CreateWebWorkerArgs {
+      name: worker_name,
+      worker_id,
+      parent_permissions,
+      permissions: worker_permissions,
+      main_module: module_specifier.clone(),
+      worker_type,
+    });
+    let (worker, external_handle) = match result {
+      Ok(created) => created,
+      Err(err) => {
+        handle_sender.send(Err(err)).unwrap();
+        return Ok(());
+      }
+    };
 
     // Send thread safe handle from newly created worker to host thread
     handle_sender.send(Ok(external_handle)).unwrap();
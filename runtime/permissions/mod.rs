@@ -30,11 +30,13 @@ use which::which;
 
 mod prompter;
 use prompter::permission_prompt;
-use prompter::PromptResponse;
 use prompter::PERMISSION_EMOJI;
 
 pub use prompter::set_prompt_callbacks;
+pub use prompter::PermissionPrompter;
 pub use prompter::PromptCallback;
+pub use prompter::PromptResponse;
+pub use prompter::SharedPermissionPrompter;
 
 static DEBUG_LOG_ENABLED: Lazy<bool> =
   Lazy::new(|| log::log_enabled!(log::Level::Debug));
@@ -123,8 +125,15 @@ impl PermissionState {
     api_name: Option<&str>,
     info: Option<&str>,
     prompt: bool,
+    prompter: Option<&SharedPermissionPrompter>,
   ) -> (Result<(), AnyError>, bool, bool) {
-    self.check2(name, api_name, || info.map(|s| s.to_string()), prompt)
+    self.check2(
+      name,
+      api_name,
+      || info.map(|s| s.to_string()),
+      prompt,
+      prompter,
+    )
   }
 
   #[inline]
@@ -134,6 +143,7 @@ impl PermissionState {
     api_name: Option<&str>,
     info: impl Fn() -> Option<String>,
     prompt: bool,
+    prompter: Option<&SharedPermissionPrompter>,
   ) -> (Result<(), AnyError>, bool, bool) {
     match self {
       PermissionState::Granted => {
@@ -148,7 +158,7 @@ impl PermissionState {
             .map(|info| { format!(" to {info}") })
             .unwrap_or_default(),
         );
-        match permission_prompt(&msg, name, api_name, true) {
+        match permission_prompt(&msg, name, api_name, true, prompter) {
           PromptResponse::Allow => {
             Self::log_perm_access(name, info);
             (Ok(()), true, false)
@@ -182,6 +192,11 @@ pub struct UnitPermission {
   pub description: &'static str,
   pub state: PermissionState,
   pub prompt: bool,
+  /// Answers the prompts of this permission instead of the process wide
+  /// prompter.
+  pub prompter: Option<SharedPermissionPrompter>,
+  /// Deny what gets revoked, so it can't be granted again by prompting.
+  pub deny_revoked: bool,
 }
 
 impl UnitPermission {
@@ -197,6 +212,7 @@ impl UnitPermission {
           self.name,
           Some("Deno.permissions.query()"),
           false,
+          self.prompter.as_ref(),
         )
       {
         self.state = PermissionState::Granted;
@@ -208,15 +224,22 @@ impl UnitPermission {
   }
 
   pub fn revoke(&mut self) -> PermissionState {
-    if self.state == PermissionState::Granted {
+    if self.deny_revoked {
+      self.state = PermissionState::Denied;
+    } else if self.state == PermissionState::Granted {
       self.state = PermissionState::Prompt;
     }
     self.state
   }
 
   pub fn check(&mut self) -> Result<(), AnyError> {
-    let (result, prompted, _is_allow_all) =
-      self.state.check(self.name, None, None, self.prompt);
+    let (result, prompted, _is_allow_all) = self.state.check(
+      self.name,
+      None,
+      None,
+      self.prompt,
+      self.prompter.as_ref(),
+    );
     if prompted {
       if result.is_ok() {
         self.state = PermissionState::Granted;
@@ -275,6 +298,11 @@ pub struct UnaryPermission<T: Descriptor + Hash> {
   pub prompt_denied_global: bool,
   pub prompt_denied_list: HashSet<T>,
   pub prompt: bool,
+  /// Answers the prompts of this permission instead of the process wide
+  /// prompter.
+  pub prompter: Option<SharedPermissionPrompter>,
+  /// Deny what gets revoked, so it can't be granted again by prompting.
+  pub deny_revoked: bool,
 }
 
 impl<T: Descriptor + Hash> Default for UnaryPermission<T> {
@@ -287,6 +315,8 @@ impl<T: Descriptor + Hash> Default for UnaryPermission<T> {
       prompt_denied_global: Default::default(),
       prompt_denied_list: Default::default(),
       prompt: Default::default(),
+      prompter: Default::default(),
+      deny_revoked: Default::default(),
     }
   }
 }
@@ -309,6 +339,7 @@ impl<T: Descriptor + Hash> UnaryPermission<T> {
           None => desc.as_ref().map(|d| format!("\"{}\"", d.name())),
         },
         self.prompt,
+        self.prompter.as_ref(),
       );
     if prompted {
       if result.is_ok() {
@@ -397,6 +428,7 @@ impl<T: Descriptor + Hash> UnaryPermission<T> {
       T::flag_name(),
       Some("Deno.permissions.request()"),
       true,
+      self.prompter.as_ref(),
     ) {
       PromptResponse::Allow => {
         self.insert_granted(desc.clone());
@@ -429,6 +461,9 @@ impl<T: Descriptor + Hash> UnaryPermission<T> {
         self.granted_list.clear();
       }
     }
+    if self.deny_revoked {
+      self.insert_prompt_denied(desc.clone());
+    }
     self.query_desc(desc, AllowPartial::TreatAsPartialGranted)
   }
 
@@ -1237,6 +1272,32 @@ impl Permissions {
     }
   }
 
+  /// Answers the prompts of these permissions, and of the permissions of
+  /// workers derived from them, with `prompter`.
+  pub fn set_prompter(&mut self, prompter: SharedPermissionPrompter) {
+    self.read.prompter = Some(prompter.clone());
+    self.write.prompter = Some(prompter.clone());
+    self.net.prompter = Some(prompter.clone());
+    self.env.prompter = Some(prompter.clone());
+    self.sys.prompter = Some(prompter.clone());
+    self.run.prompter = Some(prompter.clone());
+    self.ffi.prompter = Some(prompter.clone());
+    self.hrtime.prompter = Some(prompter);
+  }
+
+  /// Makes revocations final: whatever gets revoked is denied from then on,
+  /// so it can't be granted again by a request or an implicit prompt.
+  pub fn set_deny_revoked(&mut self, deny_revoked: bool) {
+    self.read.deny_revoked = deny_revoked;
+    self.write.deny_revoked = deny_revoked;
+    self.net.deny_revoked = deny_revoked;
+    self.env.deny_revoked = deny_revoked;
+    self.sys.deny_revoked = deny_revoked;
+    self.run.deny_revoked = deny_revoked;
+    self.ffi.deny_revoked = deny_revoked;
+    self.hrtime.deny_revoked = deny_revoked;
+  }
+
   /// A helper function that determines if the module specifier is a local or
   /// remote, and performs a read or net check for the specifier.
   pub fn check_specifier(
@@ -1579,6 +1640,8 @@ fn unit_permission_from_flag_bools(
       PermissionState::Prompt
     },
     prompt,
+    prompter: None,
+    deny_revoked: false,
   }
 }
 
@@ -1965,6 +2028,8 @@ pub fn create_child_permissions(
   worker_perms.env.prompt_denied_list =
     main_perms.env.prompt_denied_list.clone();
   worker_perms.env.prompt = main_perms.env.prompt;
+  worker_perms.env.prompter = main_perms.env.prompter.clone();
+  worker_perms.env.deny_revoked = main_perms.env.deny_revoked;
   match child_permissions_arg.sys {
     ChildUnaryPermissionArg::Inherit => {
       worker_perms.sys = main_perms.sys.clone();
@@ -1994,6 +2059,8 @@ pub fn create_child_permissions(
   worker_perms.sys.prompt_denied_list =
     main_perms.sys.prompt_denied_list.clone();
   worker_perms.sys.prompt = main_perms.sys.prompt;
+  worker_perms.sys.prompter = main_perms.sys.prompter.clone();
+  worker_perms.sys.deny_revoked = main_perms.sys.deny_revoked;
   match child_permissions_arg.hrtime {
     ChildUnitPermissionArg::Inherit => {
       worker_perms.hrtime = main_perms.hrtime.clone();
@@ -2010,6 +2077,8 @@ pub fn create_child_permissions(
     worker_perms.hrtime.state = PermissionState::Denied;
   }
   worker_perms.hrtime.prompt = main_perms.hrtime.prompt;
+  worker_perms.hrtime.prompter = main_perms.hrtime.prompter.clone();
+  worker_perms.hrtime.deny_revoked = main_perms.hrtime.deny_revoked;
   match child_permissions_arg.net {
     ChildUnaryPermissionArg::Inherit => {
       worker_perms.net = main_perms.net.clone();
@@ -2039,6 +2108,8 @@ pub fn create_child_permissions(
   worker_perms.net.prompt_denied_list =
     main_perms.net.prompt_denied_list.clone();
   worker_perms.net.prompt = main_perms.net.prompt;
+  worker_perms.net.prompter = main_perms.net.prompter.clone();
+  worker_perms.net.deny_revoked = main_perms.net.deny_revoked;
   match child_permissions_arg.ffi {
     ChildUnaryPermissionArg::Inherit => {
       worker_perms.ffi = main_perms.ffi.clone();
@@ -2071,6 +2142,8 @@ pub fn create_child_permissions(
   worker_perms.ffi.prompt_denied_list =
     main_perms.ffi.prompt_denied_list.clone();
   worker_perms.ffi.prompt = main_perms.ffi.prompt;
+  worker_perms.ffi.prompter = main_perms.ffi.prompter.clone();
+  worker_perms.ffi.deny_revoked = main_perms.ffi.deny_revoked;
   match child_permissions_arg.read {
     ChildUnaryPermissionArg::Inherit => {
       worker_perms.read = main_perms.read.clone();
@@ -2103,6 +2176,8 @@ pub fn create_child_permissions(
   worker_perms.read.prompt_denied_list =
     main_perms.read.prompt_denied_list.clone();
   worker_perms.read.prompt = main_perms.read.prompt;
+  worker_perms.read.prompter = main_perms.read.prompter.clone();
+  worker_perms.read.deny_revoked = main_perms.read.deny_revoked;
   match child_permissions_arg.run {
     ChildUnaryPermissionArg::Inherit => {
       worker_perms.run = main_perms.run.clone();
@@ -2132,6 +2207,8 @@ pub fn create_child_permissions(
   worker_perms.run.prompt_denied_list =
     main_perms.run.prompt_denied_list.clone();
   worker_perms.run.prompt = main_perms.run.prompt;
+  worker_perms.run.prompter = main_perms.run.prompter.clone();
+  worker_perms.run.deny_revoked = main_perms.run.deny_revoked;
   match child_permissions_arg.write {
     ChildUnaryPermissionArg::Inherit => {
       worker_perms.write = main_perms.write.clone();
@@ -2166,6 +2243,99 @@ pub fn create_child_permissions(
   worker_perms.write.prompt_denied_list =
     main_perms.write.prompt_denied_list.clone();
   worker_perms.write.prompt = main_perms.write.prompt;
+  worker_perms.write.prompter = main_perms.write.prompter.clone();
+  worker_perms.write.deny_revoked = main_perms.write.deny_revoked;
+  Ok(worker_perms)
+}
+
+fn child_unary_arg<T>(
+  allow_list: &Option<Vec<T>>,
+  to_string: impl Fn(&T) -> String,
+) -> ChildUnaryPermissionArg {
+  match allow_list {
+    None => ChildUnaryPermissionArg::NotGranted,
+    Some(list) if list.is_empty() => ChildUnaryPermissionArg::Granted,
+    Some(list) => {
+      ChildUnaryPermissionArg::GrantedList(list.iter().map(to_string).collect())
+    }
+  }
+}
+
+/// Creates the permissions requested by `child_options` as a subset of
+/// `main_perms`, failing if they request anything `main_perms` doesn't grant.
+/// The deny lists of `child_options` are applied on top of the denials
+/// inherited from `main_perms`.
+pub fn create_child_permissions_from_options(
+  main_perms: &mut Permissions,
+  child_options: &PermissionsOptions,
+) -> Result<Permissions, AnyError> {
+  let path_to_string = |path: &PathBuf| path.to_string_lossy().into_owned();
+  let child_permissions_arg = ChildPermissionsArg {
+    env: child_unary_arg(&child_options.allow_env, String::clone),
+    hrtime: if child_options.allow_hrtime {
+      ChildUnitPermissionArg::Granted
+    } else {
+      ChildUnitPermissionArg::NotGranted
+    },
+    net: child_unary_arg(&child_options.allow_net, String::clone),
+    ffi: child_unary_arg(&child_options.allow_ffi, path_to_string),
+    read: child_unary_arg(&child_options.allow_read, path_to_string),
+    run: child_unary_arg(&child_options.allow_run, String::clone),
+    sys: child_unary_arg(&child_options.allow_sys, String::clone),
+    write: child_unary_arg(&child_options.allow_write, path_to_string),
+  };
+  let mut worker_perms =
+    create_child_permissions(main_perms, child_permissions_arg)?;
+
+  let denied = Permissions::from_options(&PermissionsOptions {
+    deny_env: child_options.deny_env.clone(),
+    deny_hrtime: child_options.deny_hrtime,
+    deny_net: child_options.deny_net.clone(),
+    deny_ffi: child_options.deny_ffi.clone(),
+    deny_read: child_options.deny_read.clone(),
+    deny_run: child_options.deny_run.clone(),
+    deny_sys: child_options.deny_sys.clone(),
+    deny_write: child_options.deny_write.clone(),
+    ..Default::default()
+  })?;
+  worker_perms.env.flag_denied_global |= denied.env.flag_denied_global;
+  worker_perms
+    .env
+    .flag_denied_list
+    .extend(denied.env.flag_denied_list);
+  if child_options.deny_hrtime {
+    worker_perms.hrtime.state = PermissionState::Denied;
+  }
+  worker_perms.net.flag_denied_global |= denied.net.flag_denied_global;
+  worker_perms
+    .net
+    .flag_denied_list
+    .extend(denied.net.flag_denied_list);
+  worker_perms.ffi.flag_denied_global |= denied.ffi.flag_denied_global;
+  worker_perms
+    .ffi
+    .flag_denied_list
+    .extend(denied.ffi.flag_denied_list);
+  worker_perms.read.flag_denied_global |= denied.read.flag_denied_global;
+  worker_perms
+    .read
+    .flag_denied_list
+    .extend(denied.read.flag_denied_list);
+  worker_perms.run.flag_denied_global |= denied.run.flag_denied_global;
+  worker_perms
+    .run
+    .flag_denied_list
+    .extend(denied.run.flag_denied_list);
+  worker_perms.sys.flag_denied_global |= denied.sys.flag_denied_global;
+  worker_perms
+    .sys
+    .flag_denied_list
+    .extend(denied.sys.flag_denied_list);
+  worker_perms.write.flag_denied_global |= denied.write.flag_denied_global;
+  worker_perms
+    .write
+    .flag_denied_list
+    .extend(denied.write.flag_denied_list);
   Ok(worker_perms)
 }
 
@@ -3192,6 +3362,39 @@ mod tests {
     .is_err());
   }
 
+  #[test]
+  fn test_create_child_permissions_from_options() {
+    set_prompter(Box::new(TestPrompter));
+    let mut main_perms = Permissions {
+      env: Permissions::new_env(&Some(vec![]), &None, false).unwrap(),
+      net: Permissions::new_net(&Some(svec!["foo", "bar"]), &None, false)
+        .unwrap(),
+      ..Default::default()
+    };
+    let mut child_perms = create_child_permissions_from_options(
+      &mut main_perms.clone(),
+      &PermissionsOptions {
+        allow_env: Some(vec![]),
+        deny_env: Some(svec!["HOME"]),
+        allow_net: Some(svec!["foo"]),
+        ..Default::default()
+      },
+    )
+    .unwrap();
+    assert!(child_perms.env.check("PATH").is_ok());
+    assert!(child_perms.env.check("HOME").is_err());
+    assert!(child_perms.net.check(&("foo", None), None).is_ok());
+    assert!(child_perms.net.check(&("bar", None), None).is_err());
+    assert!(create_child_permissions_from_options(
+      &mut main_perms,
+      &PermissionsOptions {
+        allow_read: Some(vec![]),
+        ..Default::default()
+      },
+    )
+    .is_err());
+  }
+
   #[test]
   fn test_create_child_permissions_with_prompt() {
     set_prompter(Box::new(TestPrompter));
@@ -3262,4 +3465,79 @@ mod tests {
         .is_err()
     );
   }
+
+  #[test]
+  fn test_shared_prompter() {
+    struct AllowReadPrompter;
+
+    impl PermissionPrompter for AllowReadPrompter {
+      fn prompt(
+        &mut self,
+        _message: &str,
+        name: &str,
+        _api_name: Option<&str>,
+        _is_unary: bool,
+      ) -> PromptResponse {
+        if name == "read" {
+          PromptResponse::Allow
+        } else {
+          PromptResponse::Deny
+        }
+      }
+    }
+
+    set_prompter(Box::new(TestPrompter));
+    let prompt_value = PERMISSION_PROMPT_STUB_VALUE_SETTER.lock();
+    prompt_value.set(false);
+    let mut main_perms = Permissions::from_options(&PermissionsOptions {
+      prompt: true,
+      ..Default::default()
+    })
+    .unwrap();
+    let mut other_perms = main_perms.clone();
+    main_perms
+      .set_prompter(SharedPermissionPrompter::new(Box::new(AllowReadPrompter)));
+    assert!(main_perms.read.check(Path::new("foo"), None).is_ok());
+    assert!(main_perms.write.check(Path::new("foo"), None).is_err());
+    // the process wide prompter still answers for other permissions
+    assert!(other_perms.read.check(Path::new("foo"), None).is_err());
+
+    let mut worker_perms =
+      create_child_permissions(&mut main_perms, ChildPermissionsArg::inherit())
+        .unwrap();
+    assert!(worker_perms.read.check(Path::new("bar"), None).is_ok());
+  }
+
+  #[test]
+  fn test_deny_revoked() {
+    set_prompter(Box::new(TestPrompter));
+    let prompt_value = PERMISSION_PROMPT_STUB_VALUE_SETTER.lock();
+    prompt_value.set(true);
+    let mut perms = Permissions::from_options(&PermissionsOptions {
+      allow_read: Some(vec![]),
+      allow_net: Some(svec!["deno.land", "example.com"]),
+      prompt: true,
+      ..Default::default()
+    })
+    .unwrap();
+    perms.set_deny_revoked(true);
+
+    let foo = Path::new("/foo");
+    assert_eq!(perms.read.revoke(Some(foo)), PermissionState::Denied);
+    // the implicit prompt of a check doesn't grant it again
+    assert!(perms.read.check(foo, None).is_err());
+    assert_eq!(perms.read.request(Some(foo)), PermissionState::Denied);
+    assert!(perms.read.check(Path::new("/bar"), None).is_ok());
+
+    perms.net.revoke(Some(&("deno.land", None)));
+    assert!(perms.net.check(&("deno.land", None), None).is_err());
+    assert!(perms.net.check(&("example.com", None), None).is_ok());
+
+    let mut worker_perms =
+      create_child_permissions(&mut perms, ChildPermissionsArg::inherit())
+        .unwrap();
+    worker_perms.hrtime.state = PermissionState::Granted;
+    assert_eq!(worker_perms.hrtime.revoke(), PermissionState::Denied);
+    assert!(worker_perms.hrtime.check().is_err());
+  }
 }
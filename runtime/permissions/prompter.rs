@@ -4,12 +4,14 @@ use crate::colors;
 use deno_core::error::AnyError;
 use deno_core::parking_lot::Mutex;
 use once_cell::sync::Lazy;
+use std::fmt;
 use std::fmt::Write;
 use std::io::BufRead;
 use std::io::IsTerminal;
 use std::io::StderrLock;
 use std::io::StdinLock;
 use std::io::Write as IoWrite;
+use std::sync::Arc;
 
 /// Helper function to strip ansi codes and ASCII control characters.
 fn strip_ansi_codes_and_ascii_control(s: &str) -> std::borrow::Cow<str> {
@@ -40,12 +42,42 @@ static MAYBE_BEFORE_PROMPT_CALLBACK: Lazy<Mutex<Option<PromptCallback>>> =
 static MAYBE_AFTER_PROMPT_CALLBACK: Lazy<Mutex<Option<PromptCallback>>> =
   Lazy::new(|| Mutex::new(None));
 
+/// A prompter that answers the prompts of a single set of permissions instead
+/// of the process wide prompter.
+#[derive(Clone)]
+pub struct SharedPermissionPrompter(Arc<Mutex<Box<dyn PermissionPrompter>>>);
+
+impl SharedPermissionPrompter {
+  pub fn new(prompter: Box<dyn PermissionPrompter>) -> Self {
+    Self(Arc::new(Mutex::new(prompter)))
+  }
+}
+
+impl fmt::Debug for SharedPermissionPrompter {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("SharedPermissionPrompter")
+      .finish_non_exhaustive()
+  }
+}
+
+impl PartialEq for SharedPermissionPrompter {
+  fn eq(&self, other: &Self) -> bool {
+    Arc::ptr_eq(&self.0, &other.0)
+  }
+}
+
+impl Eq for SharedPermissionPrompter {}
+
 pub fn permission_prompt(
   message: &str,
   flag: &str,
   api_name: Option<&str>,
   is_unary: bool,
+  prompter: Option<&SharedPermissionPrompter>,
 ) -> PromptResponse {
+  if let Some(prompter) = prompter {
+    return prompter.0.lock().prompt(message, flag, api_name, is_unary);
+  }
   if let Some(before_callback) = MAYBE_BEFORE_PROMPT_CALLBACK.lock().as_mut() {
     before_callback();
   }
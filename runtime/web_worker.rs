@@ -8,6 +8,9 @@ use crate::tokio_util::create_and_run_current_thread;
 use crate::worker::import_meta_resolve_callback;
 use crate::worker::validate_import_attributes_callback;
 use crate::worker::FormatJsErrorFn;
+use crate::worker::ImportMetaResolveHook;
+use crate::worker::WorkerActivity;
+use crate::worker::WorkerActivityTracker;
 use crate::BootstrapOptions;
 use deno_broadcast_channel::InMemoryBroadcastChannel;
 use deno_cache::CreateCache;
@@ -20,6 +23,7 @@ use deno_core::futures::future::poll_fn;
 use deno_core::futures::stream::StreamExt;
 use deno_core::futures::task::AtomicWaker;
 use deno_core::located_script_name;
+use deno_core::merge_op_metrics;
 use deno_core::serde::Deserialize;
 use deno_core::serde::Serialize;
 use deno_core::serde_json::json;
@@ -139,6 +143,7 @@ pub struct WebWorkerInternalHandle {
   termination_signal: Arc<AtomicBool>,
   has_terminated: Arc<AtomicBool>,
   terminate_waker: Arc<AtomicWaker>,
+  unload_requested: Arc<AtomicBool>,
   isolate_handle: v8::IsolateHandle,
   pub name: String,
   pub worker_type: WebWorkerType,
@@ -206,9 +211,22 @@ pub struct SendableWebWorkerHandle {
   termination_signal: Arc<AtomicBool>,
   has_terminated: Arc<AtomicBool>,
   terminate_waker: Arc<AtomicWaker>,
+  unload_requested: Arc<AtomicBool>,
   isolate_handle: v8::IsolateHandle,
 }
 
+impl SendableWebWorkerHandle {
+  pub fn terminate_handle(&self) -> WebWorkerTerminateHandle {
+    WebWorkerTerminateHandle {
+      termination_signal: self.termination_signal.clone(),
+      has_terminated: self.has_terminated.clone(),
+      terminate_waker: self.terminate_waker.clone(),
+      unload_requested: self.unload_requested.clone(),
+      isolate_handle: self.isolate_handle.clone(),
+    }
+  }
+}
+
 impl From<SendableWebWorkerHandle> for WebWorkerHandle {
   fn from(handle: SendableWebWorkerHandle) -> Self {
     WebWorkerHandle {
@@ -217,6 +235,7 @@ impl From<SendableWebWorkerHandle> for WebWorkerHandle {
       termination_signal: handle.termination_signal,
       has_terminated: handle.has_terminated,
       terminate_waker: handle.terminate_waker,
+      unload_requested: handle.unload_requested,
       isolate_handle: handle.isolate_handle,
     }
   }
@@ -236,6 +255,7 @@ pub struct WebWorkerHandle {
   termination_signal: Arc<AtomicBool>,
   has_terminated: Arc<AtomicBool>,
   terminate_waker: Arc<AtomicWaker>,
+  unload_requested: Arc<AtomicBool>,
   isolate_handle: v8::IsolateHandle,
 }
 
@@ -254,6 +274,51 @@ impl WebWorkerHandle {
   /// This function will set the termination signal, close the message channel,
   /// and schedule to terminate the isolate after two seconds.
   pub fn terminate(self) {
+    self.port.disentangle();
+    self.terminate_handle().terminate();
+  }
+
+  pub fn terminate_handle(&self) -> WebWorkerTerminateHandle {
+    WebWorkerTerminateHandle {
+      termination_signal: self.termination_signal.clone(),
+      has_terminated: self.has_terminated.clone(),
+      terminate_waker: self.terminate_waker.clone(),
+      unload_requested: self.unload_requested.clone(),
+      isolate_handle: self.isolate_handle.clone(),
+    }
+  }
+}
+
+/// A thread safe handle that can only be used to terminate a web worker. It
+/// allows hosts other than the parent thread to stop a worker.
+#[derive(Clone)]
+pub struct WebWorkerTerminateHandle {
+  termination_signal: Arc<AtomicBool>,
+  has_terminated: Arc<AtomicBool>,
+  terminate_waker: Arc<AtomicWaker>,
+  unload_requested: Arc<AtomicBool>,
+  isolate_handle: v8::IsolateHandle,
+}
+
+impl WebWorkerTerminateHandle {
+  /// Check if the worker is terminated or being terminated
+  pub fn is_terminated(&self) -> bool {
+    self.termination_signal.load(Ordering::SeqCst)
+      || self.has_terminated.load(Ordering::SeqCst)
+  }
+
+  /// Like [`Self::terminate`], but dispatches the `unload` event in the
+  /// worker first. The event can't be dispatched while the worker is busy
+  /// running JavaScript, so it is skipped if the isolate has to be terminated
+  /// forcefully, and asynchronous work started by listeners doesn't run.
+  pub fn terminate_with_unload(&self) {
+    self.unload_requested.store(true, Ordering::SeqCst);
+    self.terminate();
+  }
+
+  /// Set the termination signal and schedule to terminate the isolate after
+  /// two seconds, giving the worker's event loop a chance to stop first.
+  pub fn terminate(&self) {
     use std::thread::sleep;
     use std::thread::spawn;
     use std::time::Duration;
@@ -261,13 +326,12 @@ impl WebWorkerHandle {
     let schedule_termination =
       !self.termination_signal.swap(true, Ordering::SeqCst);
 
-    self.port.disentangle();
-
     if schedule_termination && !self.has_terminated.load(Ordering::SeqCst) {
       // Wake up the worker's event loop so it can terminate.
       self.terminate_waker.wake();
 
       let has_terminated = self.has_terminated.clone();
+      let isolate_handle = self.isolate_handle.clone();
 
       // Schedule to terminate the isolate's execution.
       spawn(move || {
@@ -279,7 +343,7 @@ impl WebWorkerHandle {
 
         if !already_terminated {
           // Stop javascript execution
-          self.isolate_handle.terminate_execution();
+          isolate_handle.terminate_execution();
         }
       });
     }
@@ -296,12 +360,14 @@ fn create_handles(
   let termination_signal = Arc::new(AtomicBool::new(false));
   let has_terminated = Arc::new(AtomicBool::new(false));
   let terminate_waker = Arc::new(AtomicWaker::new());
+  let unload_requested = Arc::new(AtomicBool::new(false));
   let internal_handle = WebWorkerInternalHandle {
     name,
     port: Rc::new(parent_port),
     termination_signal: termination_signal.clone(),
     has_terminated: has_terminated.clone(),
     terminate_waker: terminate_waker.clone(),
+    unload_requested: unload_requested.clone(),
     isolate_handle: isolate_handle.clone(),
     cancel: CancelHandle::new_rc(),
     sender: ctrl_tx,
@@ -313,6 +379,7 @@ fn create_handles(
     termination_signal,
     has_terminated,
     terminate_waker,
+    unload_requested,
     isolate_handle,
   };
   (internal_handle, external_handle)
@@ -331,6 +398,7 @@ pub struct WebWorker {
   pub main_module: ModuleSpecifier,
   poll_for_messages_fn: Option<v8::Global<v8::Value>>,
   bootstrap_fn_global: Option<v8::Global<v8::Function>>,
+  activity_tracker: Option<WorkerActivityTracker>,
 }
 
 pub struct WebWorkerOptions {
@@ -339,6 +407,8 @@ pub struct WebWorkerOptions {
   pub startup_snapshot: Option<Snapshot>,
   pub unsafely_ignore_certificate_errors: Option<Vec<String>>,
   pub root_cert_store_provider: Option<Arc<dyn RootCertStoreProvider>>,
+  pub client_cert_chain_and_key: Option<(String, String)>,
+  pub worker_activity: Option<Arc<WorkerActivity>>,
   pub seed: Option<u64>,
   pub fs: Arc<dyn FileSystem>,
   pub module_loader: Rc<dyn ModuleLoader>,
@@ -354,6 +424,13 @@ pub struct WebWorkerOptions {
   pub shared_array_buffer_store: Option<SharedArrayBufferStore>,
   pub compiled_wasm_module_store: Option<CompiledWasmModuleStore>,
   pub cache_storage_dir: Option<std::path::PathBuf>,
+  /// Caps the number of bytes the Cache API may store in
+  /// `cache_storage_dir`, rejecting `cache.put` with a `QuotaExceededError`
+  /// once exceeded. Nothing is evicted automatically.
+  pub cache_quota_bytes: Option<u64>,
+  /// Consulted for `import.meta.resolve()` calls instead of the module
+  /// loader. Imports are still resolved by the module loader.
+  pub import_meta_resolve_hook: Option<ImportMetaResolveHook>,
   pub stdio: Stdio,
   pub feature_checker: Arc<FeatureChecker>,
 }
@@ -393,8 +470,12 @@ impl WebWorker {
 
     // Permissions: many ops depend on this
     let enable_testing_features = options.bootstrap.enable_testing_features;
+    let cache_quota_bytes = options.cache_quota_bytes;
     let create_cache = options.cache_storage_dir.map(|storage_dir| {
-      let create_cache_fn = move || SqliteBackedCache::new(storage_dir.clone());
+      let create_cache_fn = move || {
+        SqliteBackedCache::new(storage_dir.clone())
+          .with_quota_bytes(cache_quota_bytes)
+      };
       CreateCache(Arc::new(create_cache_fn))
     });
 
@@ -419,6 +500,7 @@ impl WebWorker {
           unsafely_ignore_certificate_errors: options
             .unsafely_ignore_certificate_errors
             .clone(),
+          client_cert_chain_and_key: options.client_cert_chain_and_key.clone(),
           file_fetch_handler: Rc::new(deno_fetch::FsFetchHandler),
           ..Default::default()
         },
@@ -452,7 +534,9 @@ impl WebWorker {
             unsafely_ignore_certificate_errors: options
               .unsafely_ignore_certificate_errors
               .clone(),
-            client_cert_chain_and_key: None,
+            client_cert_chain_and_key: options
+              .client_cert_chain_and_key
+              .clone(),
             proxy: None,
           },
         ),
@@ -517,7 +601,7 @@ impl WebWorker {
     options.startup_snapshot.as_ref().expect("A user snapshot was not provided, even though 'only_snapshotted_js_sources' is used.");
 
     // Hook up the summary metrics if the user or subcommand requested them
-    let (op_summary_metrics, op_metrics_factory_fn) =
+    let (op_summary_metrics, mut op_metrics_factory_fn) =
       if options.bootstrap.enable_op_summary_metrics {
         let op_summary_metrics = Rc::new(OpMetricsSummaryTracker::default());
         (
@@ -528,6 +612,19 @@ impl WebWorker {
         (None, None)
       };
 
+    let activity_tracker = options
+      .worker_activity
+      .clone()
+      .map(|activity| WorkerActivityTracker::new(activity, true));
+    if let Some(activity_tracker) = &activity_tracker {
+      let activity_metrics = activity_tracker.op_metrics_factory_fn();
+      op_metrics_factory_fn = Some(match op_metrics_factory_fn {
+        Some(f) => merge_op_metrics(f, activity_metrics),
+        None => activity_metrics,
+      });
+    }
+
+    let import_meta_resolve_hook = options.import_meta_resolve_hook.clone();
     let mut js_runtime = JsRuntime::new(RuntimeOptions {
       module_loader: Some(options.module_loader.clone()),
       startup_snapshot: options.startup_snapshot,
@@ -540,7 +637,12 @@ impl WebWorker {
       feature_checker: Some(options.feature_checker.clone()),
       op_metrics_factory_fn,
       import_meta_resolve_callback: Some(Box::new(
-        import_meta_resolve_callback,
+        move |loader: &dyn deno_core::ModuleLoader,
+              specifier: String,
+              referrer: String| match &import_meta_resolve_hook {
+          Some(hook) => hook(&specifier, &referrer),
+          None => import_meta_resolve_callback(loader, specifier, referrer),
+        },
       )),
       validate_import_attributes_cb: Some(Box::new(
         validate_import_attributes_callback,
@@ -608,6 +710,7 @@ impl WebWorker {
         main_module,
         poll_for_messages_fn: None,
         bootstrap_fn_global: Some(bootstrap_fn_global),
+        activity_tracker,
       },
       external_handle,
     )
@@ -740,6 +843,7 @@ impl WebWorker {
     cx: &mut Context,
     poll_options: PollEventLoopOptions,
   ) -> Poll<Result<(), AnyError>> {
+    self.maybe_dispatch_unload_event();
     // If awakened because we are terminating, just return Ok
     if self.internal_handle.terminate_if_needed() {
       return Poll::Ready(Ok(()));
@@ -747,7 +851,15 @@ impl WebWorker {
 
     self.internal_handle.terminate_waker.register(cx.waker());
 
-    match self.js_runtime.poll_event_loop(cx, poll_options) {
+    if let Some(activity_tracker) = &self.activity_tracker {
+      activity_tracker.set_busy(true);
+    }
+    let poll = self.js_runtime.poll_event_loop(cx, poll_options);
+    if let Some(activity_tracker) = &self.activity_tracker {
+      activity_tracker.poll_finished(poll.is_ready());
+    }
+
+    match poll {
       Poll::Ready(r) => {
         // If js ended because we are terminating, just return Ok
         if self.internal_handle.terminate_if_needed() {
@@ -780,6 +892,26 @@ impl WebWorker {
     poll_fn(|cx| self.poll_event_loop(cx, poll_options)).await
   }
 
+  /// Dispatches the `unload` event once the worker is asked to terminate with
+  /// [`WebWorkerTerminateHandle::terminate_with_unload`], before it is torn
+  /// down.
+  fn maybe_dispatch_unload_event(&mut self) {
+    let handle = &self.internal_handle;
+    if !handle.termination_signal.load(Ordering::SeqCst)
+      || handle.is_terminated()
+      || !handle.unload_requested.swap(false, Ordering::SeqCst)
+    {
+      return;
+    }
+    // the worker is terminating anyway, so errors thrown by listeners are
+    // ignored
+    let _ = self.js_runtime.execute_script(
+      located_script_name!(),
+      // not using `globalThis`, the user might have deleted it
+      ascii_str!("dispatchEvent(new Event('unload'))"),
+    );
+  }
+
   // Starts polling for messages from worker host from JavaScript.
   fn start_polling_for_messages(&mut self) {
     let poll_for_messages_fn = self.poll_for_messages_fn.take().unwrap();
@@ -822,6 +954,7 @@ pub fn run_web_worker(
   format_js_error_fn: Option<Arc<FormatJsErrorFn>>,
 ) -> Result<(), AnyError> {
   let name = worker.name.to_string();
+  let has_terminated = worker.internal_handle.has_terminated.clone();
 
   // TODO(bartlomieju): run following block using "select!"
   // with terminate
@@ -876,5 +1009,9 @@ pub fn run_web_worker(
     debug!("Worker thread shuts down {}", &name);
     result
   };
-  create_and_run_current_thread(fut)
+  let result = create_and_run_current_thread(fut);
+  // let terminate handles know the worker is gone, even if it wasn't
+  // terminated
+  has_terminated.store(true, Ordering::SeqCst);
+  result
 }
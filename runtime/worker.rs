@@ -1,10 +1,18 @@
 // Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+use std::cell::Cell;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
 use std::rc::Rc;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicI32;
+use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::Ordering::SeqCst;
 use std::sync::Arc;
+use std::task::Poll;
 use std::time::Duration;
 use std::time::Instant;
 
@@ -12,8 +20,11 @@ use deno_broadcast_channel::InMemoryBroadcastChannel;
 use deno_cache::CreateCache;
 use deno_cache::SqliteBackedCache;
 use deno_core::ascii_str;
+use deno_core::error::generic_error;
 use deno_core::error::AnyError;
 use deno_core::error::JsError;
+use deno_core::futures::future::poll_fn;
+use deno_core::futures::task::AtomicWaker;
 use deno_core::merge_op_metrics;
 use deno_core::v8;
 use deno_core::CompiledWasmModuleStore;
@@ -27,6 +38,7 @@ use deno_core::ModuleCodeString;
 use deno_core::ModuleId;
 use deno_core::ModuleLoader;
 use deno_core::ModuleSpecifier;
+use deno_core::OpMetricsEvent;
 use deno_core::OpMetricsFactoryFn;
 use deno_core::OpMetricsSummaryTracker;
 use deno_core::PollEventLoopOptions;
@@ -51,6 +63,11 @@ use crate::BootstrapOptions;
 
 pub type FormatJsErrorFn = dyn Fn(&JsError) -> String + Sync + Send;
 
+/// Resolves `import.meta.resolve()` calls of a worker instead of its module
+/// loader, given the specifier and the referrer.
+pub type ImportMetaResolveHook =
+  Arc<dyn Fn(&str, &str) -> Result<ModuleSpecifier, AnyError> + Send + Sync>;
+
 pub fn import_meta_resolve_callback(
   loader: &dyn deno_core::ModuleLoader,
   specifier: String,
@@ -90,15 +107,31 @@ pub fn validate_import_attributes_callback(
 }
 
 #[derive(Clone, Default)]
-pub struct ExitCode(Arc<AtomicI32>);
+pub struct ExitCode {
+  code: Arc<AtomicI32>,
+  is_explicit: Arc<AtomicBool>,
+}
 
 impl ExitCode {
   pub fn get(&self) -> i32 {
-    self.0.load(Relaxed)
+    self.code.load(Relaxed)
   }
 
   pub fn set(&mut self, code: i32) {
-    self.0.store(code, Relaxed);
+    self.code.store(code, Relaxed);
+  }
+
+  /// Sets the exit code on behalf of the executed code, with `Deno.exit()`
+  /// or `process.exitCode`.
+  pub fn set_explicit(&mut self, code: i32) {
+    self.set(code);
+    self.is_explicit.store(true, Relaxed);
+  }
+
+  /// Returns true if the executed code set the exit code, as opposed to it
+  /// being the default of 0.
+  pub fn is_explicit(&self) -> bool {
+    self.is_explicit.load(Relaxed)
   }
 }
 
@@ -113,8 +146,74 @@ pub struct MainWorker {
   pub js_runtime: JsRuntime,
   should_break_on_first_statement: bool,
   should_wait_for_inspector_session: bool,
+  /// Set once waiting for an inspector session returned.
+  inspector_session_attached: bool,
   exit_code: ExitCode,
   bootstrap_fn_global: Option<v8::Global<v8::Function>>,
+  has_global_event_listeners_fn_global: v8::Global<v8::Function>,
+  set_deprecated_api_warning_fn_global: v8::Global<v8::Function>,
+  has_pending_top_level_await: bool,
+  event_loop_stats: Option<Rc<EventLoopStatsTracker>>,
+  op_call_metrics: Option<Rc<OpCallMetricsTracker>>,
+  op_rate_limiter: Option<Rc<RefCell<OpRateLimiter>>>,
+  activity_tracker: Option<WorkerActivityTracker>,
+  extension_names: Vec<&'static str>,
+}
+
+/// Counters describing how the event loop of a worker behaved.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EventLoopStats {
+  /// Number of times the event loop was polled.
+  pub ticks: u64,
+  /// Highest number of async ops that were pending at once.
+  pub max_pending_ops: u64,
+  /// Number of ops dispatched, sync and async.
+  pub total_ops_dispatched: u64,
+  /// Number of timers created with `setTimeout` or `setInterval`.
+  pub total_timers_created: u64,
+}
+
+#[derive(Default)]
+struct EventLoopStatsTracker {
+  stats: Cell<EventLoopStats>,
+  pending_ops: Cell<u64>,
+}
+
+impl EventLoopStatsTracker {
+  fn update(&self, f: impl FnOnce(&mut EventLoopStats)) {
+    let mut stats = self.stats.get();
+    f(&mut stats);
+    self.stats.set(stats);
+  }
+
+  fn op_metrics_factory_fn(self: Rc<Self>) -> OpMetricsFactoryFn {
+    Box::new(move |_, _, decl| {
+      let tracker = self.clone();
+      let is_async = decl.is_async;
+      let is_timer = decl.name == "op_timer_handle";
+      Some(Rc::new(
+        move |_: &deno_core::_ops::OpCtx, event: OpMetricsEvent, _| match event
+        {
+          OpMetricsEvent::Dispatched => {
+            let pending_ops = tracker.pending_ops.get() + u64::from(is_async);
+            tracker.pending_ops.set(pending_ops);
+            tracker.update(|stats| {
+              stats.total_ops_dispatched += 1;
+              stats.max_pending_ops = stats.max_pending_ops.max(pending_ops);
+              if is_timer {
+                stats.total_timers_created += 1;
+              }
+            });
+          }
+          OpMetricsEvent::CompletedAsync | OpMetricsEvent::ErrorAsync => {
+            let pending_ops = tracker.pending_ops.get();
+            tracker.pending_ops.set(pending_ops.saturating_sub(1));
+          }
+          _ => {}
+        },
+      ))
+    })
+  }
 }
 
 pub struct WorkerOptions {
@@ -138,6 +237,9 @@ pub struct WorkerOptions {
 
   pub unsafely_ignore_certificate_errors: Option<Vec<String>>,
   pub root_cert_store_provider: Option<Arc<dyn RootCertStoreProvider>>,
+  /// PEM encoded certificate chain and private key presented as the client
+  /// certificate of outbound TLS connections made by `fetch`.
+  pub client_cert_chain_and_key: Option<(String, String)>,
   pub seed: Option<u64>,
 
   pub fs: Arc<dyn FileSystem>,
@@ -164,11 +266,29 @@ pub struct WorkerOptions {
   pub should_wait_for_inspector_session: bool,
   /// If Some, print a low-level trace output for ops matching the given patterns.
   pub strace_ops: Option<Vec<String>>,
+  /// If Some, limits the async ops the worker dispatches after bootstrapping
+  /// to the given number per second. Over the limit, the event loop is paused
+  /// until the rate allows the ops, so they complete later. Sync ops aren't
+  /// limited.
+  pub max_ops_per_second: Option<u32>,
+  /// If true, count how the event loop behaves, see
+  /// [`MainWorker::event_loop_stats`].
+  pub collect_event_loop_stats: bool,
+  /// Shared with the web workers this worker spawns, so
+  /// [`MainWorker::run_until_all_idle`] can wait for all of them.
+  pub worker_activity: Option<Arc<WorkerActivity>>,
 
   /// Allows to map error type to a string "class" used to represent
   /// error in JavaScript.
   pub get_error_class_fn: Option<GetErrorClassFn>,
   pub cache_storage_dir: Option<std::path::PathBuf>,
+  /// Caps the number of bytes the Cache API may store in
+  /// `cache_storage_dir`, rejecting `cache.put` with a `QuotaExceededError`
+  /// once exceeded. Nothing is evicted automatically.
+  pub cache_quota_bytes: Option<u64>,
+  /// Consulted for `import.meta.resolve()` calls instead of the module
+  /// loader. Imports are still resolved by the module loader.
+  pub import_meta_resolve_hook: Option<ImportMetaResolveHook>,
   pub origin_storage_dir: Option<std::path::PathBuf>,
   pub blob_store: Arc<BlobStore>,
   pub broadcast_channel: InMemoryBroadcastChannel,
@@ -202,9 +322,13 @@ impl Default for WorkerOptions {
       skip_op_registration: false,
       seed: None,
       unsafely_ignore_certificate_errors: Default::default(),
+      client_cert_chain_and_key: Default::default(),
       should_break_on_first_statement: Default::default(),
       should_wait_for_inspector_session: Default::default(),
       strace_ops: Default::default(),
+      max_ops_per_second: Default::default(),
+      collect_event_loop_stats: Default::default(),
+      worker_activity: Default::default(),
       compiled_wasm_module_store: Default::default(),
       shared_array_buffer_store: Default::default(),
       maybe_inspector_server: Default::default(),
@@ -212,6 +336,8 @@ impl Default for WorkerOptions {
       get_error_class_fn: Default::default(),
       origin_storage_dir: Default::default(),
       cache_storage_dir: Default::default(),
+      cache_quota_bytes: Default::default(),
+      import_meta_resolve_hook: Default::default(),
       broadcast_channel: Default::default(),
       source_map_getter: Default::default(),
       root_cert_store_provider: Default::default(),
@@ -227,9 +353,279 @@ impl Default for WorkerOptions {
   }
 }
 
+/// Token bucket used to throttle the async ops of a worker to a fixed rate.
+///
+/// Dispatching an op never blocks: once the bucket is empty the op still
+/// starts, but the event loop of the worker is paused until the rate allows
+/// it, which delays the completion of the async ops. The thread stays free
+/// for other tasks meanwhile. Ops dispatched while bootstrapping, sync ops and
+/// the ops the runtime uses to pass messages between workers aren't counted.
+struct OpRateLimiter {
+  ops_per_second: f64,
+  /// Goes below zero when ops were dispatched faster than the rate allows.
+  tokens: f64,
+  last_refill: Instant,
+  /// Set once the worker was bootstrapped.
+  enabled: bool,
+  delay: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl OpRateLimiter {
+  fn new(ops_per_second: u32) -> Self {
+    let ops_per_second = ops_per_second.max(1) as f64;
+    Self {
+      ops_per_second,
+      tokens: ops_per_second,
+      last_refill: Instant::now(),
+      enabled: false,
+      delay: None,
+    }
+  }
+
+  fn refill(&mut self) {
+    let now = Instant::now();
+    let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+    self.tokens =
+      (self.tokens + elapsed * self.ops_per_second).min(self.ops_per_second);
+    self.last_refill = now;
+  }
+
+  /// Takes a token from the bucket for an op that was just dispatched.
+  fn acquire(&mut self) {
+    if self.enabled {
+      self.refill();
+      self.tokens -= 1.0;
+    }
+  }
+
+  /// Returns ready once the event loop may be polled again.
+  fn poll_ready(&mut self, cx: &mut std::task::Context) -> Poll<()> {
+    loop {
+      if let Some(delay) = &mut self.delay {
+        if delay.as_mut().poll(cx).is_pending() {
+          return Poll::Pending;
+        }
+        self.delay = None;
+      }
+      self.refill();
+      if self.tokens >= 0.0 {
+        return Poll::Ready(());
+      }
+      let wait = Duration::from_secs_f64(-self.tokens / self.ops_per_second);
+      self.delay = Some(Box::pin(tokio::time::sleep(wait)));
+    }
+  }
+
+  fn op_metrics_factory_fn(limiter: Rc<RefCell<Self>>) -> OpMetricsFactoryFn {
+    Box::new(move |_, _, decl| {
+      let is_messaging = matches!(
+        decl.name,
+        "op_host_recv_message" | "op_worker_recv_message" | "op_host_recv_ctrl"
+      );
+      if !decl.is_async || is_messaging {
+        return None;
+      }
+      let limiter = limiter.clone();
+      Some(Rc::new(
+        move |_: &deno_core::_ops::OpCtx, event: OpMetricsEvent, _| {
+          if matches!(event, OpMetricsEvent::Dispatched) {
+            limiter.borrow_mut().acquire();
+          }
+        },
+      ))
+    })
+  }
+}
+
+/// How often an op was called and how long its calls took, as returned by
+/// [`MainWorker::op_call_metrics`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpCallMetrics {
+  pub name: &'static str,
+  /// Number of calls dispatched.
+  pub calls: u64,
+  /// Time from dispatching to completing the calls that completed, which
+  /// for async ops includes the time spent waiting on the event loop.
+  pub total_duration: Duration,
+}
+
+#[derive(Default)]
+struct OpCallMetricsTracker {
+  ops: RefCell<Vec<OpCallMetrics>>,
+}
+
+impl OpCallMetricsTracker {
+  fn reset(&self) {
+    for op in self.ops.borrow_mut().iter_mut() {
+      op.calls = 0;
+      op.total_duration = Duration::ZERO;
+    }
+  }
+
+  fn op_metrics_factory_fn(self: Rc<Self>) -> OpMetricsFactoryFn {
+    Box::new(move |_, _, decl| {
+      let tracker = self.clone();
+      let index = {
+        let mut ops = tracker.ops.borrow_mut();
+        ops.push(OpCallMetrics {
+          name: decl.name,
+          ..Default::default()
+        });
+        ops.len() - 1
+      };
+      // Async calls may complete in any order, but pairing them with the
+      // dispatch times in order still gives the correct total duration.
+      let dispatched = RefCell::new(VecDeque::<Instant>::new());
+      Some(Rc::new(
+        move |_: &deno_core::_ops::OpCtx, event: OpMetricsEvent, _| {
+          let start = match event {
+            OpMetricsEvent::Dispatched => {
+              dispatched.borrow_mut().push_back(Instant::now());
+              tracker.ops.borrow_mut()[index].calls += 1;
+              return;
+            }
+            OpMetricsEvent::CompletedAsync | OpMetricsEvent::ErrorAsync => {
+              dispatched.borrow_mut().pop_front()
+            }
+            // sync calls complete before any other call of the op
+            _ => dispatched.borrow_mut().pop_back(),
+          };
+          if let Some(start) = start {
+            tracker.ops.borrow_mut()[index].total_duration += start.elapsed();
+          }
+        },
+      ))
+    })
+  }
+}
+
+/// Tracks whether a main worker and the web workers it spawns still have work
+/// to do, see [`MainWorker::run_until_all_idle`].
+///
+/// A worker is busy while its event loop is being polled, and while it has
+/// pending async ops other than the ones waiting for messages from other
+/// workers. Messages that were posted but not received yet count as work too.
+#[derive(Default)]
+pub struct WorkerActivity {
+  busy_workers: AtomicUsize,
+  messages_in_flight: AtomicUsize,
+  waker: AtomicWaker,
+}
+
+impl WorkerActivity {
+  pub fn is_idle(&self) -> bool {
+    self.busy_workers.load(SeqCst) == 0
+      && self.messages_in_flight.load(SeqCst) == 0
+  }
+
+  fn changed(&self) {
+    self.waker.wake();
+  }
+}
+
+/// The state of a single worker in its [`WorkerActivity`].
+pub(crate) struct WorkerActivityTracker {
+  activity: Arc<WorkerActivity>,
+  /// False for the main worker, which is the one waiting for changes.
+  notify: bool,
+  busy: Cell<bool>,
+  pending_ops: Rc<Cell<usize>>,
+}
+
+impl WorkerActivityTracker {
+  /// Registers a worker, which is busy until its event loop is first polled.
+  pub(crate) fn new(activity: Arc<WorkerActivity>, notify: bool) -> Self {
+    activity.busy_workers.fetch_add(1, SeqCst);
+    Self {
+      activity,
+      notify,
+      busy: Cell::new(true),
+      pending_ops: Default::default(),
+    }
+  }
+
+  pub(crate) fn set_busy(&self, busy: bool) {
+    if self.busy.replace(busy) == busy {
+      return;
+    }
+    if busy {
+      self.activity.busy_workers.fetch_add(1, SeqCst);
+    } else {
+      self.activity.busy_workers.fetch_sub(1, SeqCst);
+      if self.notify {
+        self.activity.changed();
+      }
+    }
+  }
+
+  /// Marks the worker as idle if polling its event loop left no pending ops
+  /// besides the ones waiting for messages.
+  pub(crate) fn poll_finished(&self, event_loop_done: bool) {
+    self.set_busy(!event_loop_done && self.pending_ops.get() > 0);
+  }
+
+  pub(crate) fn op_metrics_factory_fn(&self) -> OpMetricsFactoryFn {
+    let activity = self.activity.clone();
+    let notify = self.notify;
+    let pending_ops = self.pending_ops.clone();
+    Box::new(move |_, _, decl| {
+      let is_post_message =
+        matches!(decl.name, "op_host_post_message" | "op_worker_post_message");
+      let is_recv_message =
+        matches!(decl.name, "op_host_recv_message" | "op_worker_recv_message");
+      // waiting for a worker to stop isn't work either
+      let is_waiting = is_recv_message || decl.name == "op_host_recv_ctrl";
+      let is_async = decl.is_async;
+      if !is_async && !is_post_message {
+        return None;
+      }
+      let activity = activity.clone();
+      let pending_ops = pending_ops.clone();
+      Some(Rc::new(
+        move |_: &deno_core::_ops::OpCtx, event: OpMetricsEvent, _| match event
+        {
+          OpMetricsEvent::Completed if is_post_message => {
+            activity.messages_in_flight.fetch_add(1, SeqCst);
+          }
+          OpMetricsEvent::Dispatched if is_async && !is_waiting => {
+            pending_ops.set(pending_ops.get() + 1);
+          }
+          OpMetricsEvent::CompletedAsync | OpMetricsEvent::ErrorAsync => {
+            if !is_waiting {
+              pending_ops.set(pending_ops.get().saturating_sub(1));
+            } else if is_recv_message {
+              // receiving fails once the other side is gone, so never go
+              // below zero
+              let _ = activity.messages_in_flight.fetch_update(
+                SeqCst,
+                SeqCst,
+                |count| Some(count.saturating_sub(1)),
+              );
+              if notify {
+                activity.changed();
+              }
+            }
+          }
+          _ => {}
+        },
+      ))
+    })
+  }
+}
+
+impl Drop for WorkerActivityTracker {
+  fn drop(&mut self) {
+    self.set_busy(false);
+  }
+}
+
 fn create_op_metrics(
   enable_op_summary_metrics: bool,
   strace_ops: Option<Vec<String>>,
+  op_rate_limiter: Option<Rc<RefCell<OpRateLimiter>>>,
+  event_loop_stats: Option<Rc<EventLoopStatsTracker>>,
+  op_call_metrics: Option<Rc<OpCallMetricsTracker>>,
+  activity_tracker: Option<&WorkerActivityTracker>,
 ) -> (
   Option<Rc<OpMetricsSummaryTracker>>,
   Option<OpMetricsFactoryFn>,
@@ -289,6 +685,39 @@ fn create_op_metrics(
     op_summary_metrics = Some(summary);
   }
 
+  if let Some(op_rate_limiter) = op_rate_limiter {
+    let rate_limit_metrics =
+      OpRateLimiter::op_metrics_factory_fn(op_rate_limiter);
+    op_metrics_factory_fn = Some(match op_metrics_factory_fn {
+      Some(f) => merge_op_metrics(f, rate_limit_metrics),
+      None => rate_limit_metrics,
+    });
+  }
+
+  if let Some(event_loop_stats) = event_loop_stats {
+    let event_loop_stats_metrics = event_loop_stats.op_metrics_factory_fn();
+    op_metrics_factory_fn = Some(match op_metrics_factory_fn {
+      Some(f) => merge_op_metrics(f, event_loop_stats_metrics),
+      None => event_loop_stats_metrics,
+    });
+  }
+
+  if let Some(op_call_metrics) = op_call_metrics {
+    let op_call_metrics = op_call_metrics.op_metrics_factory_fn();
+    op_metrics_factory_fn = Some(match op_metrics_factory_fn {
+      Some(f) => merge_op_metrics(f, op_call_metrics),
+      None => op_call_metrics,
+    });
+  }
+
+  if let Some(activity_tracker) = activity_tracker {
+    let activity_metrics = activity_tracker.op_metrics_factory_fn();
+    op_metrics_factory_fn = Some(match op_metrics_factory_fn {
+      Some(f) => merge_op_metrics(f, activity_metrics),
+      None => activity_metrics,
+    });
+  }
+
   (op_summary_metrics, op_metrics_factory_fn)
 }
 
@@ -321,16 +750,38 @@ impl MainWorker {
     );
 
     // Get our op metrics
+    let event_loop_stats = options
+      .collect_event_loop_stats
+      .then(|| Rc::new(EventLoopStatsTracker::default()));
+    let op_call_metrics = options
+      .bootstrap
+      .enable_op_summary_metrics
+      .then(|| Rc::new(OpCallMetricsTracker::default()));
+    let activity_tracker = options
+      .worker_activity
+      .clone()
+      .map(|activity| WorkerActivityTracker::new(activity, false));
+    let op_rate_limiter = options
+      .max_ops_per_second
+      .map(|limit| Rc::new(RefCell::new(OpRateLimiter::new(limit))));
     let (op_summary_metrics, op_metrics_factory_fn) = create_op_metrics(
       options.bootstrap.enable_op_summary_metrics,
       options.strace_ops,
+      op_rate_limiter.clone(),
+      event_loop_stats.clone(),
+      op_call_metrics.clone(),
+      activity_tracker.as_ref(),
     );
 
     // Permissions: many ops depend on this
     let enable_testing_features = options.bootstrap.enable_testing_features;
-    let exit_code = ExitCode(Arc::new(AtomicI32::new(0)));
+    let exit_code = ExitCode::default();
+    let cache_quota_bytes = options.cache_quota_bytes;
     let create_cache = options.cache_storage_dir.map(|storage_dir| {
-      let create_cache_fn = move || SqliteBackedCache::new(storage_dir.clone());
+      let create_cache_fn = move || {
+        SqliteBackedCache::new(storage_dir.clone())
+          .with_quota_bytes(cache_quota_bytes)
+      };
       CreateCache(Arc::new(create_cache_fn))
     });
 
@@ -354,6 +805,7 @@ impl MainWorker {
           unsafely_ignore_certificate_errors: options
             .unsafely_ignore_certificate_errors
             .clone(),
+          client_cert_chain_and_key: options.client_cert_chain_and_key.clone(),
           file_fetch_handler: Rc::new(deno_fetch::FsFetchHandler),
           ..Default::default()
         },
@@ -389,7 +841,9 @@ impl MainWorker {
             unsafely_ignore_certificate_errors: options
               .unsafely_ignore_certificate_errors
               .clone(),
-            client_cert_chain_and_key: None,
+            client_cert_chain_and_key: options
+              .client_cert_chain_and_key
+              .clone(),
             proxy: None,
           },
         ),
@@ -454,6 +908,7 @@ impl MainWorker {
     }
 
     extensions.extend(std::mem::take(&mut options.extensions));
+    let extension_names = extensions.iter().map(|ext| ext.name).collect();
 
     #[cfg(feature = "only_snapshotted_js_sources")]
     options.startup_snapshot.as_ref().expect("A user snapshot was not provided, even though 'only_snapshotted_js_sources' is used.");
@@ -467,6 +922,7 @@ impl MainWorker {
       }
     });
 
+    let import_meta_resolve_hook = options.import_meta_resolve_hook.clone();
     let mut js_runtime = JsRuntime::new(RuntimeOptions {
       module_loader: Some(options.module_loader.clone()),
       startup_snapshot: options.startup_snapshot,
@@ -485,7 +941,12 @@ impl MainWorker {
         wait_for_inspector_disconnect_callback,
       ),
       import_meta_resolve_callback: Some(Box::new(
-        import_meta_resolve_callback,
+        move |loader: &dyn deno_core::ModuleLoader,
+              specifier: String,
+              referrer: String| match &import_meta_resolve_hook {
+          Some(hook) => hook(&specifier, &referrer),
+          None => import_meta_resolve_callback(loader, specifier, referrer),
+        },
       )),
       validate_import_attributes_cb: Some(Box::new(
         validate_import_attributes_callback,
@@ -511,7 +972,11 @@ impl MainWorker {
       let inspector = js_runtime.inspector();
       op_state.borrow_mut().put(inspector);
     }
-    let bootstrap_fn_global = {
+    let (
+      bootstrap_fn_global,
+      has_global_event_listeners_fn_global,
+      set_deprecated_api_warning_fn_global,
+    ) = {
       let context = js_runtime.main_context();
       let scope = &mut js_runtime.handle_scope();
       let context_local = v8::Local::new(scope, context);
@@ -529,7 +994,35 @@ impl MainWorker {
         bootstrap_ns.get(scope, main_runtime_str.into()).unwrap();
       let bootstrap_fn =
         v8::Local::<v8::Function>::try_from(bootstrap_fn).unwrap();
-      v8::Global::new(scope, bootstrap_fn)
+      let has_global_event_listeners_str =
+        v8::String::new_external_onebyte_static(
+          scope,
+          b"hasGlobalEventListeners",
+        )
+        .unwrap();
+      let has_global_event_listeners_fn = bootstrap_ns
+        .get(scope, has_global_event_listeners_str.into())
+        .unwrap();
+      let has_global_event_listeners_fn =
+        v8::Local::<v8::Function>::try_from(has_global_event_listeners_fn)
+          .unwrap();
+      let set_deprecated_api_warning_str =
+        v8::String::new_external_onebyte_static(
+          scope,
+          b"setDeprecatedApiWarning",
+        )
+        .unwrap();
+      let set_deprecated_api_warning_fn = bootstrap_ns
+        .get(scope, set_deprecated_api_warning_str.into())
+        .unwrap();
+      let set_deprecated_api_warning_fn =
+        v8::Local::<v8::Function>::try_from(set_deprecated_api_warning_fn)
+          .unwrap();
+      (
+        v8::Global::new(scope, bootstrap_fn),
+        v8::Global::new(scope, has_global_event_listeners_fn),
+        v8::Global::new(scope, set_deprecated_api_warning_fn),
+      )
     };
 
     Self {
@@ -537,8 +1030,17 @@ impl MainWorker {
       should_break_on_first_statement: options.should_break_on_first_statement,
       should_wait_for_inspector_session: options
         .should_wait_for_inspector_session,
+      inspector_session_attached: false,
       exit_code,
       bootstrap_fn_global: Some(bootstrap_fn_global),
+      has_global_event_listeners_fn_global,
+      set_deprecated_api_warning_fn_global,
+      has_pending_top_level_await: false,
+      event_loop_stats,
+      op_call_metrics,
+      op_rate_limiter,
+      activity_tracker,
+      extension_names,
     }
   }
 
@@ -553,12 +1055,18 @@ impl MainWorker {
       }
     }
 
-    let scope = &mut self.js_runtime.handle_scope();
-    let args = options.as_v8(scope);
-    let bootstrap_fn = self.bootstrap_fn_global.take().unwrap();
-    let bootstrap_fn = v8::Local::new(scope, bootstrap_fn);
-    let undefined = v8::undefined(scope);
-    bootstrap_fn.call(scope, undefined.into(), &[args]).unwrap();
+    {
+      let scope = &mut self.js_runtime.handle_scope();
+      let args = options.as_v8(scope);
+      let bootstrap_fn = self.bootstrap_fn_global.take().unwrap();
+      let bootstrap_fn = v8::Local::new(scope, bootstrap_fn);
+      let undefined = v8::undefined(scope);
+      bootstrap_fn.call(scope, undefined.into(), &[args]).unwrap();
+    }
+
+    if let Some(op_rate_limiter) = &self.op_rate_limiter {
+      op_rate_limiter.borrow_mut().enabled = true;
+    }
   }
 
   /// See [JsRuntime::execute_script](deno_core::JsRuntime::execute_script)
@@ -598,8 +1106,9 @@ impl MainWorker {
     id: ModuleId,
   ) -> Result<(), AnyError> {
     self.wait_for_inspector_session();
+    self.has_pending_top_level_await = false;
     let mut receiver = self.js_runtime.mod_evaluate(id);
-    tokio::select! {
+    let result = tokio::select! {
       // Not using biased mode leads to non-determinism for relatively simple
       // programs.
       biased;
@@ -610,10 +1119,24 @@ impl MainWorker {
       }
 
       event_loop_result = self.run_event_loop(false) => {
-        event_loop_result?;
-        receiver.await
+        match event_loop_result {
+          Ok(()) => receiver.await,
+          Err(err) => Err(err),
+        }
       }
-    }
+    };
+    self.has_pending_top_level_await = matches!(
+      &result,
+      Err(err) if is_stalled_module_evaluation_error(err)
+    );
+    result
+  }
+
+  /// Returns true if the module last passed to `evaluate_module` is still
+  /// parked on a top-level await that didn't settle, as opposed to having
+  /// finished or thrown.
+  pub fn has_pending_top_level_await(&self) -> bool {
+    self.has_pending_top_level_await
   }
 
   /// Run the event loop up to a given duration. If the runtime resolves early, returns
@@ -622,14 +1145,7 @@ impl MainWorker {
     &mut self,
     duration: Duration,
   ) -> Result<(), AnyError> {
-    match tokio::time::timeout(
-      duration,
-      self
-        .js_runtime
-        .run_event_loop(PollEventLoopOptions::default()),
-    )
-    .await
-    {
+    match tokio::time::timeout(duration, self.run_event_loop(false)).await {
       Ok(Ok(_)) => Ok(()),
       Err(_) => Ok(()),
       Ok(Err(e)) => Err(e),
@@ -656,6 +1172,19 @@ impl MainWorker {
     self.evaluate_module(id).await
   }
 
+  /// Returns true if `evaluate_module` waits for an inspector session to
+  /// connect before evaluating the module.
+  pub fn should_wait_for_inspector_session(&self) -> bool {
+    self.should_wait_for_inspector_session
+  }
+
+  /// Sets whether `evaluate_module` waits for an inspector session to connect
+  /// before evaluating the module. Waiting requires the worker to have been
+  /// created with an inspector server.
+  pub fn set_should_wait_for_inspector_session(&mut self, value: bool) {
+    self.should_wait_for_inspector_session = value;
+  }
+
   fn wait_for_inspector_session(&mut self) {
     if self.should_break_on_first_statement {
       self
@@ -663,11 +1192,19 @@ impl MainWorker {
         .inspector()
         .borrow_mut()
         .wait_for_session_and_break_on_next_statement();
+      self.inspector_session_attached = true;
     } else if self.should_wait_for_inspector_session {
       self.js_runtime.inspector().borrow_mut().wait_for_session();
+      self.inspector_session_attached = true;
     }
   }
 
+  /// Returns true if `evaluate_module` waited for an inspector session,
+  /// which means a debugger attached.
+  pub fn has_inspector_session_attached(&self) -> bool {
+    self.inspector_session_attached
+  }
+
   /// Create new inspector session. This function panics if Worker
   /// was not configured to create inspector.
   pub async fn create_inspector_session(&mut self) -> LocalInspectorSession {
@@ -679,13 +1216,99 @@ impl MainWorker {
     &mut self,
     wait_for_inspector: bool,
   ) -> Result<(), AnyError> {
+    let poll_options = deno_core::PollEventLoopOptions {
+      wait_for_inspector,
+      ..Default::default()
+    };
+    if self.event_loop_stats.is_none() && self.op_rate_limiter.is_none() {
+      return self.js_runtime.run_event_loop(poll_options).await;
+    }
+    poll_fn(|cx| {
+      if let Some(op_rate_limiter) = &self.op_rate_limiter {
+        if op_rate_limiter.borrow_mut().poll_ready(cx).is_pending() {
+          return Poll::Pending;
+        }
+      }
+      if let Some(event_loop_stats) = &self.event_loop_stats {
+        event_loop_stats.update(|stats| stats.ticks += 1);
+      }
+      self.js_runtime.poll_event_loop(cx, poll_options)
+    })
+    .await
+  }
+
+  /// Runs the event loop until this worker and the web workers sharing its
+  /// `WorkerOptions::worker_activity` are all idle, see [`WorkerActivity`].
+  pub async fn run_until_all_idle(&mut self) -> Result<(), AnyError> {
+    let Some(tracker) = &self.activity_tracker else {
+      return Err(generic_error(
+        "Waiting for all workers to be idle requires the worker_activity option.",
+      ));
+    };
+    let activity = tracker.activity.clone();
+    let mut event_loop_done = false;
+    poll_fn(|cx| {
+      activity.waker.register(cx.waker());
+      if !event_loop_done {
+        tracker.set_busy(true);
+        match self
+          .js_runtime
+          .poll_event_loop(cx, PollEventLoopOptions::default())
+        {
+          Poll::Ready(Err(err)) => {
+            tracker.poll_finished(true);
+            return Poll::Ready(Err(err));
+          }
+          Poll::Ready(Ok(())) => event_loop_done = true,
+          Poll::Pending => {}
+        }
+        tracker.poll_finished(event_loop_done);
+      }
+      if activity.is_idle() {
+        Poll::Ready(Ok(()))
+      } else {
+        Poll::Pending
+      }
+    })
+    .await
+  }
+
+  /// Returns the event loop counters collected so far, if
+  /// `WorkerOptions::collect_event_loop_stats` was set. Ticks are only
+  /// counted while the event loop is run with [`Self::run_event_loop`].
+  pub fn event_loop_stats(&self) -> Option<EventLoopStats> {
     self
-      .js_runtime
-      .run_event_loop(deno_core::PollEventLoopOptions {
-        wait_for_inspector,
-        ..Default::default()
-      })
-      .await
+      .event_loop_stats
+      .as_ref()
+      .map(|tracker| tracker.stats.get())
+  }
+
+  /// Returns the calls of every op that was called since the worker was
+  /// created or [`Self::reset_op_call_metrics`] was last called, if
+  /// `BootstrapOptions::enable_op_summary_metrics` was set.
+  pub fn op_call_metrics(&self) -> Option<Vec<OpCallMetrics>> {
+    self.op_call_metrics.as_ref().map(|tracker| {
+      tracker
+        .ops
+        .borrow()
+        .iter()
+        .filter(|op| op.calls > 0)
+        .cloned()
+        .collect()
+    })
+  }
+
+  /// Resets the counters returned by [`Self::op_call_metrics`].
+  pub fn reset_op_call_metrics(&self) {
+    if let Some(tracker) = &self.op_call_metrics {
+      tracker.reset();
+    }
+  }
+
+  /// Returns the names of the extensions the runtime was created with, the
+  /// built-in ones followed by `WorkerOptions::extensions`.
+  pub fn extension_names(&self) -> &[&'static str] {
+    &self.extension_names
   }
 
   /// Return exit code set by the executed code (either in main worker
@@ -694,6 +1317,40 @@ impl MainWorker {
     self.exit_code.get()
   }
 
+  /// Returns true if the exit code was set by the executed code, see
+  /// [`ExitCode::is_explicit`].
+  pub fn exit_was_explicit(&self) -> bool {
+    self.exit_code.is_explicit()
+  }
+
+  /// Changes the deprecated API warning settings that were set by
+  /// `BootstrapOptions::disable_deprecated_api_warning` and
+  /// `BootstrapOptions::verbose_deprecated_api_warning`.
+  pub fn set_deprecated_api_warning(&mut self, disable: bool, verbose: bool) {
+    let scope = &mut self.js_runtime.handle_scope();
+    let set_fn =
+      v8::Local::new(scope, &self.set_deprecated_api_warning_fn_global);
+    let disable = v8::Boolean::new(scope, disable);
+    let verbose = v8::Boolean::new(scope, verbose);
+    let undefined = v8::undefined(scope);
+    set_fn.call(scope, undefined.into(), &[disable.into(), verbose.into()]);
+  }
+
+  /// Returns true if there are listeners for the given event on the global
+  /// scope, including handlers assigned to `on<event>` properties.
+  pub fn has_global_event_listeners(&mut self, event_name: &str) -> bool {
+    let scope = &mut self.js_runtime.handle_scope();
+    let has_listeners_fn =
+      v8::Local::new(scope, &self.has_global_event_listeners_fn_global);
+    let event_name = v8::String::new(scope, event_name).unwrap();
+    let undefined = v8::undefined(scope);
+    has_listeners_fn
+      .call(scope, undefined.into(), &[event_name.into()])
+      // be conservative and assume there are listeners if the check failed
+      .map(|value| value.is_true())
+      .unwrap_or(true)
+  }
+
   /// Dispatches "load" event to the JavaScript runtime.
   ///
   /// Does not poll event loop, and thus not await any of the "load" event handlers.
@@ -748,3 +1405,74 @@ impl MainWorker {
     Ok(local_value.is_false())
   }
 }
+
+/// How `deno_core` words the errors of an event loop that finished while a
+/// module was still evaluating. It doesn't expose the evaluation state of a
+/// module otherwise, so `pending_top_level_await_is_detected` pins these.
+const STALLED_MODULE_EVALUATION_MESSAGES: &[&str] = &[
+  "Module evaluation is still pending",
+  "Promise resolution is still pending",
+];
+
+/// Returns true if `err` is the error of an event loop that finished while a
+/// module was still evaluating, which means its top-level await never
+/// settled.
+fn is_stalled_module_evaluation_error(err: &AnyError) -> bool {
+  let message = err.to_string();
+  STALLED_MODULE_EVALUATION_MESSAGES
+    .iter()
+    .any(|prefix| message.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn op_rate_limiter_pauses_event_loop() {
+    let mut limiter = OpRateLimiter::new(100);
+    // ops dispatched while bootstrapping aren't counted
+    for _ in 0..1000 {
+      limiter.acquire();
+    }
+    let ready = poll_fn(|cx| Poll::Ready(limiter.poll_ready(cx))).await;
+    assert!(ready.is_ready());
+
+    limiter.enabled = true;
+    for _ in 0..102 {
+      limiter.acquire();
+    }
+    let ready = poll_fn(|cx| Poll::Ready(limiter.poll_ready(cx))).await;
+    assert!(ready.is_pending());
+    let start = Instant::now();
+    poll_fn(|cx| limiter.poll_ready(cx)).await;
+    assert!(start.elapsed() >= Duration::from_millis(10));
+  }
+
+  #[tokio::test]
+  async fn pending_top_level_await_is_detected() {
+    let temp_dir = test_util::TempDir::new();
+    temp_dir.write("stalled.js", "await new Promise(() => {});");
+    temp_dir.write("settled.js", "await Promise.resolve();");
+    for (name, stalled) in [("stalled.js", true), ("settled.js", false)] {
+      let main_module =
+        ModuleSpecifier::from_file_path(temp_dir.path().join(name)).unwrap();
+      let mut worker = MainWorker::bootstrap_from_options(
+        main_module.clone(),
+        PermissionsContainer::allow_all(),
+        WorkerOptions {
+          module_loader: Rc::new(FsModuleLoader),
+          ..Default::default()
+        },
+      );
+      let id = worker.preload_main_module(&main_module).await.unwrap();
+      let result = worker.evaluate_module(id).await;
+      assert_eq!(result.is_err(), stalled, "{result:?}");
+      if let Err(err) = &result {
+        // fails if deno_core rewords the error
+        assert!(is_stalled_module_evaluation_error(err), "{err}");
+      }
+      assert_eq!(worker.has_pending_top_level_await(), stalled);
+    }
+  }
+}
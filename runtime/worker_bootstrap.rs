@@ -62,6 +62,8 @@ pub struct BootstrapOptions {
   pub node_ipc_fd: Option<i64>,
   pub disable_deprecated_api_warning: bool,
   pub verbose_deprecated_api_warning: bool,
+  /// Don't initialize the Node compatibility layer.
+  pub skip_node_bootstrap: bool,
 }
 
 impl Default for BootstrapOptions {
@@ -92,6 +94,7 @@ impl Default for BootstrapOptions {
       node_ipc_fd: None,
       disable_deprecated_api_warning: false,
       verbose_deprecated_api_warning: false,
+      skip_node_bootstrap: false,
     }
   }
 }
@@ -125,6 +128,8 @@ struct BootstrapV8<'a>(
   bool,
   // verbose_deprecated_api_warning
   bool,
+  // skip_node_bootstrap
+  bool,
 );
 
 impl BootstrapOptions {
@@ -146,6 +151,7 @@ impl BootstrapOptions {
       self.maybe_binary_npm_command_name.as_deref(),
       self.disable_deprecated_api_warning,
       self.verbose_deprecated_api_warning,
+      self.skip_node_bootstrap,
     );
 
     bootstrap.serialize(ser).unwrap()